@@ -1,4 +1,4 @@
-from typing import Any, Optional
+from typing import Any, Callable, Optional
 
 class AudioFormat:
     def __init__(
@@ -8,7 +8,7 @@ class AudioFormat:
         Create an audio format object.
 
         Args:
-            container (Optional[str]): The container format for the audio. It can be either 'mp3', 'wav', 'ogg' or 'webm'.
+            container (Optional[str]): The container format for the audio. It can be one of 'mp3', 'wav', 'ogg', 'webm', 'pcm', 'alaw', 'ulaw' or 'amr'.
             quality (Optional[int]): The quality for the audio. Defaults to 0.
             use_closest (Optional[bool]): Whether to use the closest quality if the specified quality does not exist. Defaults to False.
         """
@@ -74,7 +74,7 @@ class SpeechService:
                                   In websocket mode, the websocket connection will be established when this object is created.
             key (Optional[str]): The subscription key for the speech service.
             token (Optional[str]): The auth token for the speech service.
-            proxy (Optional[str]): The proxy for the speech service. Only http/socks5 proxy servers are supported by now.
+            proxy (Optional[str]): The proxy for the speech service. http, https, socks5 and socks5h proxy servers are supported, credentials in the url are used to authenticate to the proxy server. If not set, the proxy is taken from the http_proxy, https_proxy and all_proxy environment variables, and hosts in NO_PROXY are connected to directly.
             headers (Optional[Iterable[Tuple[str, str]]]): Additional request headers.
         """
     def speak_text(self, text: str, **options: Any) -> None:
@@ -92,6 +92,10 @@ class SpeechService:
             style (Optional[str]): Speech style.
             style_degree (Optional[float]): Speech style degree. It can be a float number between 0.01 and 2.
             role (Optional[Role]): Speech role.
+            device (Optional[str]): The audio output device to play on. Defaults to the default output device.
+            volume (Optional[float]): The playback volume. Defaults to 1.0.
+            speed (Optional[float]): The playback speed. Defaults to 1.0. Unlike rate, it does not change the synthesized audio.
+            start_offset (Optional[float]): Start playing from this offset in seconds.
         """
     def synthesize_text(self, text: str, **options: Any) -> Optional[bytes]:
         """
@@ -110,12 +114,18 @@ class SpeechService:
             style_degree (Optional[float]): Speech style degree. It can be a float number between 0.01 and 2.
             role (Optional[Role]): Speech role.
         """
-    def speak_ssml(self, ssml: str) -> None:
+    def speak_ssml(self, ssml: str, **options: Any) -> None:
         """
         Synthesize SSML to speech and output to speaker.
 
         Args:
             ssml (str): The SSML to synthesize.
+
+        Kwargs:
+            device (Optional[str]): The audio output device to play on. Defaults to the default output device.
+            volume (Optional[float]): The playback volume. Defaults to 1.0.
+            speed (Optional[float]): The playback speed. Defaults to 1.0. Unlike rate, it does not change the synthesized audio.
+            start_offset (Optional[float]): Start playing from this offset in seconds.
         """
     def synthesize_ssml(self, ssml: str, **options: Any) -> Optional[bytes]:
         """
@@ -127,3 +137,43 @@ class SpeechService:
         Kwargs:
             output (Optional[str]): The output file path. If this argument is not specified, the audio data will be returned.
         """
+    def stream_text(
+        self,
+        text: str,
+        on_audio: Callable[[bytes], Any],
+        on_boundary: Optional[Callable[[str, float, float, str], Any]] = None,
+        **options: Any,
+    ) -> None:
+        """
+        Synthesize text to speech and receive the audio in chunks as soon as they arrive.
+
+        Args:
+            text (str): The text to synthesize.
+            on_audio (Callable[[bytes], Any]): Called with each chunk of the audio.
+            on_boundary (Optional[Callable[[str, float, float, str], Any]]): Called with the kind ('Word', 'Punctuation' or 'Sentence'),
+                                  the offset and the duration in seconds and the text of each boundary. Only the websocket mode reports boundaries.
+
+        Kwargs:
+            pitch (Optional[str]): The pitch for the speech.
+            rate (Optional[str]): The rate for the speech.
+            locale (Optional[str]): The locale for the speech.
+            voice (Optional[str]): The voice to be used. It takes precedence over locale.
+            style (Optional[str]): Speech style.
+            style_degree (Optional[float]): Speech style degree. It can be a float number between 0.01 and 2.
+            role (Optional[Role]): Speech role.
+        """
+    def stream_ssml(
+        self,
+        ssml: str,
+        on_audio: Callable[[bytes], Any],
+        on_boundary: Optional[Callable[[str, float, float, str], Any]] = None,
+    ) -> None:
+        """
+        Synthesize SSML to speech and receive the audio in chunks as soon as they arrive.
+
+        Args:
+            ssml (str): The SSML to synthesize.
+            on_audio (Callable[[bytes], Any]): Called with each chunk of the audio.
+            on_boundary (Optional[Callable[[str, float, float, str], Any]]): Called with the kind ('Word', 'Punctuation' or 'Sentence'),
+                                  the offset and the duration in seconds and the text of each boundary. Only the websocket mode reports boundaries.
+        """
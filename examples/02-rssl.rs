@@ -15,7 +15,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     .key(auth_key) // Fill in the auth key
     .build();
     let config = SynthesizerConfig::new(auth, AudioFormat::Audio16Khz32KBitRateMonoMp3);
-    let mut syn = config.connect_websocket().await?; // Get the synthesizer from the config
+    let syn = config.connect_websocket().await?; // Get the synthesizer from the config
     let options = TextOptionsBuilder::new() // Adjusting text options like rate, pitch and voice
         .rate("+20%")
         .voice("zh-CN-XiaoxiaoNeural")
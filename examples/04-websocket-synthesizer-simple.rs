@@ -15,7 +15,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     .key("YOUR_AZURE_SUBSCRIPTION_KEY")
     .build();
     let config = SynthesizerConfig::new(auth, AudioFormat::Riff16Khz16BitMonoPcm);
-    let mut ws_syn = config.connect_websocket().await?;
+    let ws_syn = config.connect_websocket().await?;
     let ssml = r#"<speak version="1.0" xmlns="http://www.w3.org/2001/10/synthesis" xml:lang="en-US"><voice name="en-US-JennyNeural">Hello, world!</voice></speak>"#;
     let audio_data = ws_syn.synthesize_ssml(ssml).await?;
     let mut file = File::create("ssml-output.wav").await?;
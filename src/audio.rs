@@ -1,4 +1,5 @@
 use std::{
+    borrow::Cow,
     error::Error,
     fmt::{self, Display, Formatter},
 };
@@ -41,30 +42,256 @@ static WEBM_QUALITY_MAP: QualityMap = phf_map! {
     1i8  => AudioFormat::Webm24Khz16Bit24KbpsMonoOpus,
 };
 
+static PCM_QUALITY_MAP: QualityMap = phf_map! {
+    -2i8 => AudioFormat::Raw8Khz16BitMonoPcm,
+    -1i8 => AudioFormat::Raw16Khz16BitMonoPcm,
+    0i8  => AudioFormat::Raw24Khz16BitMonoPcm,
+    1i8  => AudioFormat::Raw48Khz16BitMonoPcm,
+};
+
+static ALAW_QUALITY_MAP: QualityMap = phf_map! {
+    0i8 => AudioFormat::Raw8Khz8BitMonoALaw,
+};
+
+static ULAW_QUALITY_MAP: QualityMap = phf_map! {
+    0i8 => AudioFormat::Raw8Khz8BitMonoMULaw,
+};
+
+static AMR_QUALITY_MAP: QualityMap = phf_map! {
+    0i8 => AudioFormat::AmrWb16000Hz,
+};
+
 #[cfg(feature = "audio")]
 mod internal {
+    use std::borrow::Cow;
     use std::error::Error;
     use std::fmt::{self, Display, Formatter};
+    use std::time::Duration;
 
+    use rodio::cpal::traits::HostTrait;
+    use rodio::cpal::{self, DeviceNameError, DevicesError};
     use rodio::{decoder::DecoderError, PlayError, StreamError};
-    use rodio::{Decoder, OutputStream, Sink};
-    #[allow(unused)]
+    use rodio::{DeviceTrait, OutputStream, OutputStreamHandle};
+
     pub fn play_borrowed_audio_blocking(buffer: &[u8]) -> Result<(), AudioError> {
         play_owned_audio_blocking(buffer.to_vec())
     }
 
     pub fn play_owned_audio_blocking(buffer: Vec<u8>) -> Result<(), AudioError> {
+        play_owned_audio_blocking_with_options(buffer, &PlaybackOptions::default())
+    }
+
+    /// Play the audio on the output device selected by `options` and block until it ends.
+    pub fn play_owned_audio_blocking_with_options(
+        buffer: Vec<u8>,
+        options: &PlaybackOptions<'_>,
+    ) -> Result<(), AudioError> {
         log::info!("Playing audio... ({} bytes)", buffer.len());
-        let (_stream, stream_handle) = OutputStream::try_default()?;
-        let sink = Sink::try_new(&stream_handle).unwrap();
-        let cursor = std::io::Cursor::new(buffer);
-        let source = Decoder::new(cursor)?;
-        sink.append(source);
-        sink.sleep_until_end();
+        let player = super::Player::new(options)?;
+        player.enqueue_from(buffer, options.start_offset)?;
+        player.wait_until_finished()?;
         log::debug!("Done playing audio");
         Ok(())
     }
 
+    /// List the names of all available audio output devices.
+    pub fn list_output_devices() -> Result<Vec<String>, AudioError> {
+        cpal::default_host()
+            .output_devices()?
+            .map(|device| device.name().map_err(Into::into))
+            .collect()
+    }
+
+    /// Get the name of the default audio output device, if there is one.
+    pub fn default_output_device() -> Result<Option<String>, AudioError> {
+        cpal::default_host()
+            .default_output_device()
+            .map(|device| device.name())
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Open an output stream on the device with the given name,
+    /// or on the default device if `device` is `None`.
+    pub(crate) fn open_output_stream(
+        device: Option<&str>,
+    ) -> Result<(OutputStream, OutputStreamHandle), AudioError> {
+        let Some(name) = device else {
+            return Ok(OutputStream::try_default()?);
+        };
+        let mut devices = cpal::default_host().output_devices()?;
+        let device = devices
+            .find(|d| d.name().is_ok_and(|n| n == name))
+            .ok_or_else(|| AudioError {
+                kind: AudioErrorKind::Device,
+                source: Some(anyhow::anyhow!(
+                    "No output device named {name}. Run `aspeak list-devices` to list available devices."
+                )),
+            })?;
+        Ok(OutputStream::try_from_device(&device)?)
+    }
+
+    /// Options for playing audio
+    #[derive(Debug, Clone)]
+    pub struct PlaybackOptions<'a> {
+        /// Name of the output device. The default device is used if it is `None`.
+        pub(crate) device: Option<Cow<'a, str>>,
+        /// Playback volume, where `1.0` is the original volume
+        pub(crate) volume: f32,
+        /// Playback speed, where `1.0` is the original speed.
+        /// Note that it also changes the pitch, unlike the rate in SSML.
+        pub(crate) speed: f32,
+        /// Skip this amount of audio at the beginning
+        pub(crate) start_offset: Duration,
+    }
+
+    impl Default for PlaybackOptions<'_> {
+        fn default() -> Self {
+            Self {
+                device: None,
+                volume: 1.0,
+                speed: 1.0,
+                start_offset: Duration::ZERO,
+            }
+        }
+    }
+
+    impl<'a> PlaybackOptions<'a> {
+        /// Name of the output device. The default device is used if it is `None`.
+        pub fn device(&self) -> Option<&str> {
+            self.device.as_deref()
+        }
+
+        /// Name of the output device. The default device is used if it is `None`.
+        pub fn device_mut(&mut self) -> &mut Option<Cow<'a, str>> {
+            &mut self.device
+        }
+
+        /// Playback volume, where `1.0` is the original volume
+        pub fn volume(&self) -> f32 {
+            self.volume
+        }
+
+        /// Playback volume, where `1.0` is the original volume
+        pub fn volume_mut(&mut self) -> &mut f32 {
+            &mut self.volume
+        }
+
+        /// Playback speed, where `1.0` is the original speed.
+        pub fn speed(&self) -> f32 {
+            self.speed
+        }
+
+        /// Playback speed, where `1.0` is the original speed.
+        pub fn speed_mut(&mut self) -> &mut f32 {
+            &mut self.speed
+        }
+
+        /// Skip this amount of audio at the beginning
+        pub fn start_offset(&self) -> Duration {
+            self.start_offset
+        }
+
+        /// Skip this amount of audio at the beginning
+        pub fn start_offset_mut(&mut self) -> &mut Duration {
+            &mut self.start_offset
+        }
+
+        /// Create a builder for [`PlaybackOptions`]
+        pub fn builder() -> PlaybackOptionsBuilder<'a> {
+            PlaybackOptionsBuilder::new()
+        }
+
+        /// Check that the volume is not negative and the speed is positive.
+        pub fn validate(&self) -> Result<(), AudioError> {
+            let reason = if !(self.volume.is_finite() && self.volume >= 0.0) {
+                format!(
+                    "the volume must be a non-negative number, got {}",
+                    self.volume
+                )
+            } else if !(self.speed.is_finite() && self.speed > 0.0) {
+                format!("the speed must be a positive number, got {}", self.speed)
+            } else {
+                return Ok(());
+            };
+            Err(AudioError {
+                kind: AudioErrorKind::InvalidOptions,
+                source: Some(anyhow::anyhow!(reason)),
+            })
+        }
+    }
+
+    /// Builder for [`PlaybackOptions`]
+    pub struct PlaybackOptionsBuilder<'a> {
+        device: Option<Cow<'a, str>>,
+        volume: f32,
+        speed: f32,
+        start_offset: Duration,
+    }
+
+    impl Default for PlaybackOptionsBuilder<'_> {
+        fn default() -> Self {
+            Self {
+                device: None,
+                volume: 1.0,
+                speed: 1.0,
+                start_offset: Duration::ZERO,
+            }
+        }
+    }
+
+    impl<'a> PlaybackOptionsBuilder<'a> {
+        /// Create a new builder
+        pub fn new() -> Self {
+            Default::default()
+        }
+
+        /// Name of the output device
+        pub fn device(mut self, device: impl Into<Cow<'a, str>>) -> Self {
+            self.device = Some(device.into());
+            self
+        }
+
+        /// Name of the output device. The default device is used if it is `None`.
+        pub fn optional_device(mut self, device: Option<impl Into<Cow<'a, str>>>) -> Self {
+            self.device = device.map(Into::into);
+            self
+        }
+
+        /// Playback volume, where `1.0` is the original volume
+        pub fn volume(mut self, volume: f32) -> Self {
+            self.volume = volume;
+            self
+        }
+
+        /// Playback speed, where `1.0` is the original speed.
+        /// Note that it also changes the pitch, unlike the rate in SSML.
+        pub fn speed(mut self, speed: f32) -> Self {
+            self.speed = speed;
+            self
+        }
+
+        /// Skip this amount of audio at the beginning
+        pub fn start_offset(mut self, start_offset: Duration) -> Self {
+            self.start_offset = start_offset;
+            self
+        }
+
+        /// Build [`PlaybackOptions`]
+        ///
+        /// Fails if the volume is negative or the speed is not positive.
+        pub fn build(self) -> Result<PlaybackOptions<'a>, AudioError> {
+            let options = PlaybackOptions {
+                device: self.device,
+                volume: self.volume,
+                speed: self.speed,
+                start_offset: self.start_offset,
+            };
+            options.validate()?;
+            Ok(options)
+        }
+    }
+
     #[derive(Debug)]
     #[non_exhaustive]
     /// An error that can occur when trying to play audio
@@ -76,7 +303,7 @@ mod internal {
     /// - Audio stream error
     pub struct AudioError {
         pub kind: AudioErrorKind,
-        source: Option<anyhow::Error>,
+        pub(crate) source: Option<anyhow::Error>,
     }
 
     impl Display for AudioError {
@@ -98,6 +325,10 @@ mod internal {
         Stream,
         #[allow(unused)]
         Play,
+        /// Failed to enumerate or find the output device
+        Device,
+        /// The volume or speed in [`PlaybackOptions`] is out of range
+        InvalidOptions,
     }
 
     macro_rules! impl_from_for_audio_error {
@@ -116,23 +347,91 @@ mod internal {
     impl_from_for_audio_error!(StreamError, Stream);
     impl_from_for_audio_error!(DecoderError, Decoder);
     impl_from_for_audio_error!(PlayError, Decoder);
+    impl_from_for_audio_error!(DevicesError, Device);
+    impl_from_for_audio_error!(DeviceNameError, Device);
 
     #[cfg(feature = "python")]
     impl From<AudioError> for pyo3::PyErr {
         fn from(value: AudioError) -> Self {
+            if value.kind == AudioErrorKind::InvalidOptions {
+                return pyo3::exceptions::PyValueError::new_err(format!(
+                    "{:?}",
+                    color_eyre::Report::from(value)
+                ));
+            }
             pyo3::exceptions::PyOSError::new_err(format!("{:?}", color_eyre::Report::from(value)))
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn playback_options_accept_valid_volume_and_speed() {
+            let options = PlaybackOptions::builder()
+                .volume(0.0)
+                .speed(0.5)
+                .build()
+                .unwrap();
+            assert_eq!(options.volume(), 0.0);
+            assert_eq!(options.speed(), 0.5);
+        }
+
+        #[test]
+        fn playback_options_reject_invalid_volume_and_speed() {
+            for (volume, speed) in [
+                (-1.0, 1.0),
+                (f32::NAN, 1.0),
+                (1.0, 0.0),
+                (1.0, -2.0),
+                (1.0, f32::INFINITY),
+            ] {
+                let error = PlaybackOptions::builder()
+                    .volume(volume)
+                    .speed(speed)
+                    .build()
+                    .unwrap_err();
+                assert_eq!(error.kind, AudioErrorKind::InvalidOptions);
+            }
+        }
+
+        #[test]
+        fn playback_options_changed_in_place_are_validated() {
+            let mut options = PlaybackOptions::default();
+            *options.speed_mut() = 0.0;
+            assert!(options.validate().is_err());
+        }
+    }
 }
 
 #[cfg(feature = "audio")]
 pub use internal::*;
 
+pub mod pcm;
+mod probe;
+pub use probe::{probe, AudioInfo, ProbeError, ProbeErrorKind};
+#[cfg(any(feature = "audio", feature = "transcode"))]
+pub mod tag;
+#[cfg(any(feature = "audio", feature = "transcode"))]
+pub use tag::{embed_tags, AudioTags, AudioTagsBuilder, TagError, TagErrorKind};
+#[cfg(feature = "transcode")]
+pub mod transcode;
+
+#[cfg(feature = "audio")]
+mod player;
+#[cfg(feature = "audio")]
+pub use player::*;
+
 pub static QUALITY_MAP: phf::Map<&'static str, &'static QualityMap> = phf_map! {
     "wav" => &WAV_QUALITY_MAP,
     "mp3" => &MP3_QUALITY_MAP,
     "ogg" => &OGG_QUALITY_MAP,
     "webm" => &WEBM_QUALITY_MAP,
+    "pcm" => &PCM_QUALITY_MAP,
+    "alaw" => &ALAW_QUALITY_MAP,
+    "ulaw" => &ULAW_QUALITY_MAP,
+    "amr" => &AMR_QUALITY_MAP,
 };
 
 pub static QUALITY_RANGE_MAP: phf::Map<&'static str, (i8, i8)> = phf_map! {
@@ -140,6 +439,10 @@ pub static QUALITY_RANGE_MAP: phf::Map<&'static str, (i8, i8)> = phf_map! {
     "mp3" => (-4, 3),
     "ogg" => (-1, 1),
     "webm" => (-1, 1),
+    "pcm" => (-2, 1),
+    "alaw" => (0, 0),
+    "ulaw" => (0, 0),
+    "amr" => (0, 0),
 };
 
 /// All possible audio formats
@@ -147,7 +450,17 @@ pub static QUALITY_RANGE_MAP: phf::Map<&'static str, (i8, i8)> = phf_map! {
 /// Some endpoints only support a subset of these formats.
 #[cfg_attr(feature = "python", pyo3::pyclass)]
 #[derive(
-    Debug, Clone, Copy, Default, IntoStaticStr, EnumString, EnumIter, Deserialize, Serialize,
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    IntoStaticStr,
+    EnumString,
+    EnumIter,
+    Deserialize,
+    Serialize,
 )]
 #[non_exhaustive]
 pub enum AudioFormat {
@@ -294,6 +607,181 @@ impl AudioFormat {
             })
         }
     }
+
+    /// Sample rate, codec and container of this format
+    fn properties(self) -> (u32, AudioCodec, AudioContainer) {
+        use AudioCodec as Codec;
+        use AudioContainer as Container;
+        use AudioFormat::*;
+        match self {
+            AmrWb16000Hz => (16000, Codec::AmrWb, Container::Amr),
+            Audio16Khz128KBitRateMonoMp3 => (16000, Codec::Mp3, Container::Mp3),
+            Audio16Khz16Bit32KbpsMonoOpus => (16000, Codec::Opus, Container::Raw),
+            Audio16Khz32KBitRateMonoMp3 => (16000, Codec::Mp3, Container::Mp3),
+            Audio16Khz64KBitRateMonoMp3 => (16000, Codec::Mp3, Container::Mp3),
+            Audio24Khz160KBitRateMonoMp3 => (24000, Codec::Mp3, Container::Mp3),
+            Audio24Khz16Bit24KbpsMonoOpus => (24000, Codec::Opus, Container::Raw),
+            Audio24Khz16Bit48KbpsMonoOpus => (24000, Codec::Opus, Container::Raw),
+            Audio24Khz48KBitRateMonoMp3 => (24000, Codec::Mp3, Container::Mp3),
+            Audio24Khz96KBitRateMonoMp3 => (24000, Codec::Mp3, Container::Mp3),
+            Audio48Khz192KBitRateMonoMp3 => (48000, Codec::Mp3, Container::Mp3),
+            Audio48Khz96KBitRateMonoMp3 => (48000, Codec::Mp3, Container::Mp3),
+            Ogg16Khz16BitMonoOpus => (16000, Codec::Opus, Container::Ogg),
+            Ogg24Khz16BitMonoOpus => (24000, Codec::Opus, Container::Ogg),
+            Ogg48Khz16BitMonoOpus => (48000, Codec::Opus, Container::Ogg),
+            Raw16Khz16BitMonoPcm => (16000, Codec::Pcm, Container::Raw),
+            Raw16Khz16BitMonoTrueSilk => (16000, Codec::Silk, Container::Raw),
+            Raw22050Hz16BitMonoPcm => (22050, Codec::Pcm, Container::Raw),
+            Raw24Khz16BitMonoPcm => (24000, Codec::Pcm, Container::Raw),
+            Raw24Khz16BitMonoTrueSilk => (24000, Codec::Silk, Container::Raw),
+            Raw44100Hz16BitMonoPcm => (44100, Codec::Pcm, Container::Raw),
+            Raw48Khz16BitMonoPcm => (48000, Codec::Pcm, Container::Raw),
+            Raw8Khz16BitMonoPcm => (8000, Codec::Pcm, Container::Raw),
+            Raw8Khz8BitMonoALaw => (8000, Codec::ALaw, Container::Raw),
+            Raw8Khz8BitMonoMULaw => (8000, Codec::MuLaw, Container::Raw),
+            Riff16Khz16BitMonoPcm => (16000, Codec::Pcm, Container::Wav),
+            Riff22050Hz16BitMonoPcm => (22050, Codec::Pcm, Container::Wav),
+            Riff24Khz16BitMonoPcm => (24000, Codec::Pcm, Container::Wav),
+            Riff44100Hz16BitMonoPcm => (44100, Codec::Pcm, Container::Wav),
+            Riff48Khz16BitMonoPcm => (48000, Codec::Pcm, Container::Wav),
+            Riff8Khz16BitMonoPcm => (8000, Codec::Pcm, Container::Wav),
+            Riff8Khz8BitMonoALaw => (8000, Codec::ALaw, Container::Wav),
+            Riff8Khz8BitMonoMULaw => (8000, Codec::MuLaw, Container::Wav),
+            Webm16Khz16BitMonoOpus => (16000, Codec::Opus, Container::Webm),
+            Webm24Khz16Bit24KbpsMonoOpus => (24000, Codec::Opus, Container::Webm),
+            Webm24Khz16BitMonoOpus => (24000, Codec::Opus, Container::Webm),
+        }
+    }
+
+    /// Sample rate in Hz
+    pub fn sample_rate(self) -> u32 {
+        self.properties().0
+    }
+
+    /// Number of channels. All formats provided by Azure are mono.
+    pub fn channels(self) -> u16 {
+        1
+    }
+
+    /// Bits per sample for uncompressed formats (PCM, A-law and μ-law).
+    ///
+    /// Returns `None` for compressed formats.
+    pub fn bits_per_sample(self) -> Option<u16> {
+        match self.codec() {
+            AudioCodec::Pcm => Some(16),
+            AudioCodec::ALaw | AudioCodec::MuLaw => Some(8),
+            _ => None,
+        }
+    }
+
+    /// The codec of the audio data
+    pub fn codec(self) -> AudioCodec {
+        self.properties().1
+    }
+
+    /// The container of the audio data
+    pub fn container(self) -> AudioContainer {
+        self.properties().2
+    }
+
+    /// MIME type of this format, which is suitable for the `Content-Type` header.
+    ///
+    /// Raw PCM and G.711 formats carry the sample rate as a parameter, as described in RFC 2586 and RFC 4856.
+    pub fn mime_type(self) -> Cow<'static, str> {
+        match (self.container(), self.codec()) {
+            (AudioContainer::Wav, _) => "audio/wav".into(),
+            (AudioContainer::Mp3, _) => "audio/mpeg".into(),
+            (AudioContainer::Ogg, _) => "audio/ogg".into(),
+            (AudioContainer::Webm, _) => "audio/webm".into(),
+            (AudioContainer::Amr, _) => "audio/AMR-WB".into(),
+            (AudioContainer::Raw, AudioCodec::Opus) => "audio/opus".into(),
+            (AudioContainer::Raw, AudioCodec::Silk) => "audio/SILK".into(),
+            (AudioContainer::Raw, AudioCodec::ALaw) => {
+                format!("audio/PCMA; rate={}", self.sample_rate()).into()
+            }
+            (AudioContainer::Raw, AudioCodec::MuLaw) => {
+                format!("audio/PCMU; rate={}", self.sample_rate()).into()
+            }
+            (AudioContainer::Raw, _) => format!(
+                "audio/L16; rate={}; channels={}",
+                self.sample_rate(),
+                self.channels()
+            )
+            .into(),
+        }
+    }
+
+    /// Conventional file extension (without the leading dot) of this format
+    pub fn file_extension(self) -> &'static str {
+        match (self.container(), self.codec()) {
+            (AudioContainer::Wav, _) => "wav",
+            (AudioContainer::Mp3, _) => "mp3",
+            (AudioContainer::Ogg, _) => "ogg",
+            (AudioContainer::Webm, _) => "webm",
+            (AudioContainer::Amr, _) => "amr",
+            (AudioContainer::Raw, AudioCodec::Opus) => "opus",
+            (AudioContainer::Raw, AudioCodec::Silk) => "silk",
+            (AudioContainer::Raw, AudioCodec::ALaw) => "alaw",
+            (AudioContainer::Raw, AudioCodec::MuLaw) => "ulaw",
+            (AudioContainer::Raw, _) => "pcm",
+        }
+    }
+
+    /// Whether the audio can be consumed incrementally while it is being received.
+    ///
+    /// RIFF files start with a header that contains the length of the audio,
+    /// so they are only valid once they are complete.
+    pub fn is_streamable(self) -> bool {
+        self.container() != AudioContainer::Wav
+    }
+
+    /// Whether aspeak can decode this format for playback.
+    ///
+    /// Raw and G.711 formats need to be converted with [`pcm::into_playable`] first.
+    pub fn is_decodable_locally(self) -> bool {
+        matches!(
+            (self.container(), self.codec()),
+            (
+                AudioContainer::Wav | AudioContainer::Raw,
+                AudioCodec::Pcm | AudioCodec::ALaw | AudioCodec::MuLaw
+            ) | (AudioContainer::Mp3, AudioCodec::Mp3)
+        )
+    }
+}
+
+/// Audio codecs used by [`AudioFormat`]s
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, IntoStaticStr)]
+#[strum(serialize_all = "lowercase")]
+#[non_exhaustive]
+pub enum AudioCodec {
+    /// Linear PCM, signed 16-bit little endian
+    Pcm,
+    /// G.711 A-law
+    ALaw,
+    /// G.711 μ-law
+    MuLaw,
+    Mp3,
+    Opus,
+    Silk,
+    /// Adaptive Multi-Rate Wideband
+    AmrWb,
+}
+
+/// Containers used by [`AudioFormat`]s
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, IntoStaticStr)]
+#[strum(serialize_all = "lowercase")]
+#[non_exhaustive]
+pub enum AudioContainer {
+    /// RIFF WAVE
+    Wav,
+    /// No container, just the encoded audio data
+    Raw,
+    /// MPEG audio stream
+    Mp3,
+    Ogg,
+    Webm,
+    /// AMR storage format
+    Amr,
 }
 
 #[cfg(feature = "python")]
@@ -376,3 +864,89 @@ pub(crate) fn register_python_items(
     m.add_class::<AudioFormat>()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use strum::IntoEnumIterator;
+
+    use super::*;
+
+    /// Check the properties of every format against the tokens of its name, e.g. `riff-24khz-16bit-mono-pcm`
+    #[test]
+    fn properties_match_names() {
+        for format in AudioFormat::iter() {
+            let name: &str = format.into();
+            let tokens: Vec<_> = name.split('-').collect();
+            let sample_rate = tokens
+                .iter()
+                .find_map(|token| {
+                    token
+                        .strip_suffix("khz")
+                        .map(|khz| khz.parse::<u32>().unwrap() * 1000)
+                        .or_else(|| token.strip_suffix("hz").map(|hz| hz.parse().unwrap()))
+                })
+                .unwrap();
+            assert_eq!(format.sample_rate(), sample_rate, "{name}");
+            assert!(
+                tokens.contains(&"mono") || name.starts_with("amr"),
+                "{name}"
+            );
+            assert_eq!(format.channels(), 1, "{name}");
+            let codec = match *tokens.last().unwrap() {
+                "pcm" => AudioCodec::Pcm,
+                "alaw" => AudioCodec::ALaw,
+                "mulaw" => AudioCodec::MuLaw,
+                "mp3" => AudioCodec::Mp3,
+                "opus" => AudioCodec::Opus,
+                "truesilk" => AudioCodec::Silk,
+                _ if name.starts_with("amr-wb") => AudioCodec::AmrWb,
+                _ => panic!("unknown codec of {name}"),
+            };
+            assert_eq!(format.codec(), codec, "{name}");
+            let bits = tokens
+                .iter()
+                .find_map(|token| token.strip_suffix("bit")?.parse::<u16>().ok());
+            let uncompressed = matches!(
+                codec,
+                AudioCodec::Pcm | AudioCodec::ALaw | AudioCodec::MuLaw
+            );
+            assert_eq!(
+                format.bits_per_sample(),
+                bits.filter(|_| uncompressed),
+                "{name}"
+            );
+            let container = match tokens[0] {
+                "riff" => AudioContainer::Wav,
+                "raw" => AudioContainer::Raw,
+                "ogg" => AudioContainer::Ogg,
+                "webm" => AudioContainer::Webm,
+                "amr" => AudioContainer::Amr,
+                "audio" if codec == AudioCodec::Mp3 => AudioContainer::Mp3,
+                "audio" => AudioContainer::Raw,
+                _ => panic!("unknown container of {name}"),
+            };
+            assert_eq!(format.container(), container, "{name}");
+            assert_eq!(
+                format.is_streamable(),
+                container != AudioContainer::Wav,
+                "{name}"
+            );
+            let (mime_type, extension) = match (container, codec) {
+                (AudioContainer::Wav, _) => ("audio/wav".to_string(), "wav"),
+                (AudioContainer::Mp3, _) => ("audio/mpeg".to_string(), "mp3"),
+                (AudioContainer::Raw, AudioCodec::Pcm) => {
+                    (format!("audio/L16; rate={sample_rate}; channels=1"), "pcm")
+                }
+                (AudioContainer::Raw, AudioCodec::ALaw) => {
+                    (format!("audio/PCMA; rate={sample_rate}"), "alaw")
+                }
+                (AudioContainer::Raw, AudioCodec::MuLaw) => {
+                    (format!("audio/PCMU; rate={sample_rate}"), "ulaw")
+                }
+                _ => (format.mime_type().into_owned(), format.file_extension()),
+            };
+            assert_eq!(format.mime_type(), mime_type, "{name}");
+            assert_eq!(format.file_extension(), extension, "{name}");
+        }
+    }
+}
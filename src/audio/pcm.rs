@@ -0,0 +1,284 @@
+//! Local processing of uncompressed audio.
+//!
+//! Raw formats like [`AudioFormat::Raw24Khz16BitMonoPcm`] come back from Azure without any header,
+//! and the G.711 formats (A-law and μ-law) can't be decoded by the audio backend.
+//! The functions in this module use the known parameters of an [`AudioFormat`]
+//! to wrap such audio into RIFF/WAVE files and to decode it to linear PCM.
+
+use super::{AudioCodec, AudioContainer, AudioFormat};
+
+/// Size of the RIFF/WAVE header written by [`wav_header`] for linear PCM audio
+pub const WAV_HEADER_LEN: usize = 44;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_ALAW: u16 = 6;
+const WAVE_FORMAT_MULAW: u16 = 7;
+
+/// Decode a G.711 A-law sample to 16-bit linear PCM.
+pub fn decode_alaw(sample: u8) -> i16 {
+    let sample = sample ^ 0x55;
+    let segment = (sample & 0x70) >> 4;
+    let mut value = ((sample & 0x0f) as i16) << 4;
+    match segment {
+        0 => value += 8,
+        1 => value += 0x108,
+        _ => value = (value + 0x108) << (segment - 1),
+    }
+    if sample & 0x80 != 0 {
+        value
+    } else {
+        -value
+    }
+}
+
+/// Decode a G.711 μ-law sample to 16-bit linear PCM.
+pub fn decode_mulaw(sample: u8) -> i16 {
+    let sample = !sample;
+    let value = ((((sample & 0x0f) as i16) << 3) + 0x84) << ((sample & 0x70) >> 4);
+    if sample & 0x80 != 0 {
+        0x84 - value
+    } else {
+        value - 0x84
+    }
+}
+
+fn header(format_tag: u16, sample_rate: u32, channels: u16, bits: u16, data_len: u32) -> Vec<u8> {
+    let block_align = channels * bits / 8;
+    // Formats other than PCM have a cbSize field and a fact chunk with the number of samples
+    let extended = format_tag != WAVE_FORMAT_PCM;
+    let fmt_len: u32 = if extended { 18 } else { 16 };
+    let len = if extended { 58 } else { WAV_HEADER_LEN };
+    let mut header = Vec::with_capacity(len);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&data_len.saturating_add(len as u32 - 8).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&fmt_len.to_le_bytes());
+    header.extend_from_slice(&format_tag.to_le_bytes());
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&bits.to_le_bytes());
+    if extended {
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(b"fact");
+        header.extend_from_slice(&4u32.to_le_bytes());
+        header.extend_from_slice(&(data_len / block_align.max(1) as u32).to_le_bytes());
+    }
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_len.to_le_bytes());
+    debug_assert_eq!(header.len(), len);
+    header
+}
+
+/// Build a RIFF/WAVE header for `data_len` bytes of audio in `format`.
+///
+/// The codec of the audio is kept as is, so A-law and μ-law audio get a G.711 header,
+/// which is longer than [`WAV_HEADER_LEN`] because of its `fact` chunk.
+/// Returns `None` if `format` is not PCM, A-law or μ-law.
+pub fn wav_header(format: AudioFormat, data_len: u32) -> Option<Vec<u8>> {
+    let format_tag = match format.codec() {
+        AudioCodec::Pcm => WAVE_FORMAT_PCM,
+        AudioCodec::ALaw => WAVE_FORMAT_ALAW,
+        AudioCodec::MuLaw => WAVE_FORMAT_MULAW,
+        _ => return None,
+    };
+    Some(header(
+        format_tag,
+        format.sample_rate(),
+        format.channels(),
+        format.bits_per_sample()?,
+        data_len,
+    ))
+}
+
+/// Wrap headerless audio into a RIFF/WAVE file.
+///
+/// Returns `None` if `format` is not a raw PCM, A-law or μ-law format.
+pub fn wrap_in_riff(format: AudioFormat, audio: &[u8]) -> Option<Vec<u8>> {
+    if format.container() != AudioContainer::Raw {
+        return None;
+    }
+    let mut wav = wav_header(format, u32::try_from(audio.len()).ok()?)?;
+    wav.extend_from_slice(audio);
+    Some(wav)
+}
+
+/// Find the offset of the audio data in a RIFF/WAVE file.
+///
+/// Returns `None` if `wav` is not a RIFF/WAVE file, or does not contain the start of the data chunk yet.
+pub fn riff_data_offset(wav: &[u8]) -> Option<usize> {
+    if wav.len() < 12 || &wav[0..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+        return None;
+    }
+    let mut offset = 12;
+    while let Some(chunk) = wav.get(offset..offset + 8) {
+        if &chunk[0..4] == b"data" {
+            return Some(offset + 8);
+        }
+        let size = u32::from_le_bytes(chunk[4..8].try_into().unwrap()) as usize;
+        // Chunks are padded to an even size
+        offset = offset
+            .checked_add(8)?
+            .checked_add(size)?
+            .checked_add(size & 1)?;
+    }
+    None
+}
+
+/// Find the audio data in a RIFF/WAVE file
+fn riff_data(wav: &[u8]) -> Option<&[u8]> {
+    let offset = riff_data_offset(wav)?;
+    let size = u32::from_le_bytes(wav[offset - 4..offset].try_into().unwrap()) as usize;
+    // Streamed files may not contain the correct size of the data chunk
+    Some(&wav[offset..][..size.min(wav.len() - offset)])
+}
+
+/// Convert PCM, A-law or μ-law audio to a 16-bit linear PCM RIFF/WAVE file.
+///
+/// Both raw audio and audio in RIFF/WAVE files are accepted.
+/// Returns `None` if `format` is not PCM, A-law or μ-law, or the audio is not a valid RIFF/WAVE file.
+pub fn to_linear_pcm_wav(format: AudioFormat, audio: &[u8]) -> Option<Vec<u8>> {
+    let data = match format.container() {
+        AudioContainer::Raw => audio,
+        AudioContainer::Wav => riff_data(audio)?,
+        _ => return None,
+    };
+    let samples: Vec<u8> = match format.codec() {
+        AudioCodec::Pcm => data[..data.len() & !1].to_vec(),
+        AudioCodec::ALaw => data
+            .iter()
+            .flat_map(|&sample| decode_alaw(sample).to_le_bytes())
+            .collect(),
+        AudioCodec::MuLaw => data
+            .iter()
+            .flat_map(|&sample| decode_mulaw(sample).to_le_bytes())
+            .collect(),
+        _ => return None,
+    };
+    let mut wav = header(
+        WAVE_FORMAT_PCM,
+        format.sample_rate(),
+        format.channels(),
+        16,
+        u32::try_from(samples.len()).ok()?,
+    );
+    wav.extend_from_slice(&samples);
+    Some(wav)
+}
+
+/// Convert audio in `format` to something the audio backend can decode.
+///
+/// Raw formats and G.711 formats are converted to 16-bit linear PCM RIFF/WAVE files.
+/// Other audio is returned unchanged.
+pub fn into_playable(format: AudioFormat, audio: Vec<u8>) -> Vec<u8> {
+    match (format.container(), format.codec()) {
+        (AudioContainer::Wav, AudioCodec::Pcm) => audio,
+        (AudioContainer::Raw | AudioContainer::Wav, _) => {
+            to_linear_pcm_wav(format, &audio).unwrap_or(audio)
+        }
+        _ => audio,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alaw_matches_reference_values() {
+        // Values from the G.711 A-law decoding table
+        for (sample, expected) in [
+            (0xd5, 8),
+            (0x55, -8),
+            (0xd4, 24),
+            (0xc5, 264),
+            (0x80, 5504),
+            (0x00, -5504),
+            (0xaa, 32256),
+            (0x2a, -32256),
+        ] {
+            assert_eq!(decode_alaw(sample), expected, "A-law sample {sample:#04x}");
+        }
+    }
+
+    #[test]
+    fn mulaw_matches_reference_values() {
+        // Values from the G.711 μ-law decoding table
+        for (sample, expected) in [
+            (0xff, 0),
+            (0x7f, 0),
+            (0xfe, 8),
+            (0x7e, -8),
+            (0xef, 132),
+            (0x80, 32124),
+            (0x00, -32124),
+        ] {
+            assert_eq!(decode_mulaw(sample), expected, "μ-law sample {sample:#04x}");
+        }
+    }
+
+    #[test]
+    fn g711_decoding_is_symmetric() {
+        for sample in 0..=u8::MAX {
+            assert_eq!(decode_alaw(sample), -decode_alaw(sample ^ 0x80));
+            assert_eq!(decode_mulaw(sample), -decode_mulaw(sample ^ 0x80));
+        }
+    }
+
+    #[test]
+    fn pcm_header_has_no_fact_chunk() {
+        let header = wav_header(AudioFormat::Raw16Khz16BitMonoPcm, 100).unwrap();
+        assert_eq!(header.len(), WAV_HEADER_LEN);
+        assert_eq!(&header[4..8], &136u32.to_le_bytes());
+        assert_eq!(&header[16..20], &16u32.to_le_bytes());
+        assert_eq!(&header[36..40], b"data");
+        assert_eq!(riff_data_offset(&header), Some(WAV_HEADER_LEN));
+    }
+
+    #[test]
+    fn g711_header_has_cb_size_and_fact_chunk() {
+        let header = wav_header(AudioFormat::Raw8Khz8BitMonoALaw, 100).unwrap();
+        assert_eq!(header.len(), 58);
+        assert_eq!(&header[4..8], &150u32.to_le_bytes());
+        assert_eq!(&header[16..20], &18u32.to_le_bytes());
+        assert_eq!(&header[20..22], &WAVE_FORMAT_ALAW.to_le_bytes());
+        assert_eq!(&header[36..38], &0u16.to_le_bytes());
+        assert_eq!(&header[38..42], b"fact");
+        assert_eq!(&header[46..50], &100u32.to_le_bytes());
+        assert_eq!(&header[50..54], b"data");
+        assert_eq!(&header[54..58], &100u32.to_le_bytes());
+        assert_eq!(riff_data_offset(&header), Some(58));
+    }
+
+    #[test]
+    fn g711_wav_decodes_to_linear_pcm() {
+        let wav = wav_header(AudioFormat::Riff8Khz8BitMonoMULaw, 2)
+            .map(|mut wav| {
+                wav.extend_from_slice(&[0x80, 0x00]);
+                wav
+            })
+            .unwrap();
+        let pcm = to_linear_pcm_wav(AudioFormat::Riff8Khz8BitMonoMULaw, &wav).unwrap();
+        assert_eq!(pcm.len(), WAV_HEADER_LEN + 4);
+        assert_eq!(&pcm[20..22], &WAVE_FORMAT_PCM.to_le_bytes());
+        assert_eq!(&pcm[WAV_HEADER_LEN..], &[0x7c, 0x7d, 0x84, 0x82]);
+    }
+
+    #[test]
+    fn odd_sized_chunks_are_padded() {
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        wav.extend_from_slice(b"LIST\x03\0\0\0abc\0");
+        wav.extend_from_slice(b"data\x02\0\0\0\x01\x02");
+        assert_eq!(riff_data_offset(&wav), Some(32));
+        assert_eq!(riff_data(&wav), Some(&[1, 2][..]));
+    }
+
+    #[test]
+    fn huge_chunk_sizes_do_not_overflow() {
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        wav.extend_from_slice(b"LIST\xff\xff\xff\xff");
+        assert_eq!(riff_data_offset(&wav), None);
+        assert_eq!(riff_data(&wav), None);
+    }
+}
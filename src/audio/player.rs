@@ -0,0 +1,704 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex, Weak,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::{debug, warn};
+use rodio::{Decoder, Sink, Source};
+use tokio::sync::watch;
+
+use super::{open_output_stream, AudioError, PlaybackOptions};
+
+/// Number of frames the decoding thread of a stream hands over to the audio thread at once
+const DECODED_FRAMES_PER_BATCH: usize = 512;
+
+/// A reusable audio player that owns an output stream.
+///
+/// Audio is played back-to-back without reopening the output device,
+/// so consecutive clips are gapless.
+///
+/// The output stream lives on a dedicated thread, so a [`Player`] is `Send` and `Sync`.
+/// Decoding also happens on that thread, in the same order as the audio is enqueued.
+/// Streams are decoded on their own threads as their data arrives, and silence is played
+/// if the data of a stream does not arrive in time, so a slow network never stalls the output device.
+pub struct Player {
+    sink: Arc<Sink>,
+    commands: Option<mpsc::Sender<Command>>,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+enum Command {
+    Append {
+        generation: u64,
+        start_offset: Duration,
+        input: Input,
+    },
+}
+
+enum Input {
+    Buffer(Vec<u8>),
+    Stream(ChunkReader),
+}
+
+struct Shared {
+    /// Number of enqueued items that are not yet handed to the sink
+    pending: AtomicUsize,
+    /// Number of sources that are handed to the sink and not yet finished
+    playing: AtomicUsize,
+    /// Streams that are not finished yet, closed when the player is cleared or dropped
+    streams: Mutex<Vec<Weak<ChunkBuffer>>>,
+    /// Incremented by [`Player::clear`] to discard items that are not yet handed to the sink
+    generation: AtomicU64,
+    idle: Mutex<bool>,
+    idle_changed: Condvar,
+    idle_tx: watch::Sender<bool>,
+    error: Mutex<Option<AudioError>>,
+}
+
+impl Shared {
+    /// Update the idle state. The idle lock is held while `f` is evaluated,
+    /// so that it is serialized with [`Shared::add_pending`].
+    fn update_idle(&self, f: impl FnOnce() -> bool) {
+        let mut guard = self.idle.lock().unwrap();
+        let idle = f();
+        if *guard != idle {
+            *guard = idle;
+            self.idle_changed.notify_all();
+            self.idle_tx.send_replace(idle);
+        }
+    }
+
+    fn add_pending(&self) {
+        self.update_idle(|| {
+            self.pending.fetch_add(1, Ordering::SeqCst);
+            false
+        });
+    }
+
+    fn is_drained(&self) -> bool {
+        self.pending.load(Ordering::SeqCst) == 0 && self.playing.load(Ordering::SeqCst) == 0
+    }
+
+    /// Called when a source handed to the sink has finished playing or is discarded.
+    fn remove_playing(&self) {
+        self.update_idle(|| {
+            self.playing.fetch_sub(1, Ordering::SeqCst);
+            self.is_drained()
+        });
+    }
+
+    /// Close the streams that are still being written, so nothing waits for their data anymore.
+    fn close_streams(&self) {
+        for buffer in self.streams.lock().unwrap().drain(..) {
+            if let Some(buffer) = buffer.upgrade() {
+                buffer.close();
+            }
+        }
+    }
+}
+
+impl Player {
+    /// Open the output device selected by `options` and start the player.
+    ///
+    /// The volume and speed in `options` are applied to everything played by this player.
+    /// The start offset is ignored, use [`Player::enqueue_from`] instead.
+    pub fn new(options: &PlaybackOptions<'_>) -> Result<Self, AudioError> {
+        options.validate()?;
+        let device = options.device().map(String::from);
+        let (handle_tx, handle_rx) = mpsc::sync_channel(1);
+        let (commands, command_rx) = mpsc::channel();
+        let (idle_tx, _) = watch::channel(true);
+        let shared = Arc::new(Shared {
+            pending: AtomicUsize::new(0),
+            playing: AtomicUsize::new(0),
+            streams: Mutex::new(Vec::new()),
+            generation: AtomicU64::new(0),
+            idle: Mutex::new(true),
+            idle_changed: Condvar::new(),
+            idle_tx,
+            error: Mutex::new(None),
+        });
+        let thread_shared = shared.clone();
+        let thread = thread::Builder::new()
+            .name("aspeak-player".to_string())
+            .spawn(move || {
+                // The output stream is not `Send`, so it must be created on this thread.
+                let (_stream, handle) = match open_output_stream(device.as_deref()) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        let _ = handle_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let sink = match Sink::try_new(&handle) {
+                    Ok(sink) => Arc::new(sink),
+                    Err(e) => {
+                        let _ = handle_tx.send(Err(e.into()));
+                        return;
+                    }
+                };
+                if handle_tx.send(Ok(sink.clone())).is_err() {
+                    return;
+                }
+                run_player_thread(&sink, &thread_shared, command_rx);
+            })
+            .map_err(|e| AudioError {
+                kind: super::AudioErrorKind::Stream,
+                source: Some(e.into()),
+            })?;
+        let sink = handle_rx.recv().map_err(|e| AudioError {
+            kind: super::AudioErrorKind::Stream,
+            source: Some(e.into()),
+        })??;
+        sink.set_volume(options.volume());
+        sink.set_speed(options.speed());
+        Ok(Self {
+            sink,
+            commands: Some(commands),
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// Enqueue a complete audio buffer.
+    pub fn enqueue(&self, buffer: Vec<u8>) -> Result<(), AudioError> {
+        self.enqueue_from(buffer, Duration::ZERO)
+    }
+
+    /// Enqueue a complete audio buffer, skipping `start_offset` of audio at its beginning.
+    pub fn enqueue_from(&self, buffer: Vec<u8>, start_offset: Duration) -> Result<(), AudioError> {
+        self.send(start_offset, Input::Buffer(buffer))
+    }
+
+    /// Enqueue audio whose data is not available yet.
+    ///
+    /// Write the audio to the returned [`PlayerStream`] as it is produced.
+    /// Playback starts as soon as enough data is available to decode the audio.
+    /// The audio enqueued after a stream waits until the beginning of the stream is written.
+    pub fn enqueue_stream(&self) -> Result<PlayerStream, AudioError> {
+        let buffer = Arc::new(ChunkBuffer::default());
+        {
+            let mut streams = self.shared.streams.lock().unwrap();
+            streams.retain(|stream| stream.strong_count() > 0);
+            streams.push(Arc::downgrade(&buffer));
+        }
+        self.send(
+            Duration::ZERO,
+            Input::Stream(ChunkReader {
+                buffer: buffer.clone(),
+                position: 0,
+            }),
+        )?;
+        Ok(PlayerStream { buffer })
+    }
+
+    fn send(&self, start_offset: Duration, input: Input) -> Result<(), AudioError> {
+        self.shared.add_pending();
+        self.commands
+            .as_ref()
+            .expect("the player thread is only stopped on drop")
+            .send(Command::Append {
+                generation: self.shared.generation.load(Ordering::SeqCst),
+                start_offset,
+                input,
+            })
+            .map_err(|e| {
+                self.shared.pending.fetch_sub(1, Ordering::SeqCst);
+                AudioError {
+                    kind: super::AudioErrorKind::Stream,
+                    source: Some(anyhow::anyhow!("the player thread has exited: {e}")),
+                }
+            })
+    }
+
+    /// Pause playback.
+    pub fn pause(&self) {
+        self.sink.pause();
+    }
+
+    /// Resume paused playback.
+    pub fn resume(&self) {
+        self.sink.play();
+    }
+
+    /// Whether the playback is paused.
+    pub fn is_paused(&self) -> bool {
+        self.sink.is_paused()
+    }
+
+    /// Skip the audio that is currently playing.
+    pub fn skip(&self) {
+        self.sink.skip_one();
+    }
+
+    /// Remove everything from the queue, including the audio that is currently playing.
+    ///
+    /// Streams that are still being written are finished, the data written to them afterwards is discarded.
+    pub fn clear(&self) {
+        self.shared.generation.fetch_add(1, Ordering::SeqCst);
+        self.shared.close_streams();
+        let paused = self.sink.is_paused();
+        self.sink.clear();
+        if !paused {
+            self.sink.play();
+        }
+    }
+
+    /// Number of enqueued audio clips that have not finished playing.
+    pub fn len(&self) -> usize {
+        self.shared.pending.load(Ordering::SeqCst) + self.sink.len()
+    }
+
+    /// Whether there is nothing left to play.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Playback volume, where `1.0` is the original volume
+    pub fn set_volume(&self, volume: f32) {
+        self.sink.set_volume(volume);
+    }
+
+    /// Playback speed, where `1.0` is the original speed
+    pub fn set_speed(&self, speed: f32) {
+        self.sink.set_speed(speed);
+    }
+
+    /// Wait until everything enqueued so far has finished playing.
+    ///
+    /// Returns the first error that occurred while decoding the enqueued audio, if any.
+    pub async fn finished(&self) -> Result<(), AudioError> {
+        let mut idle = self.shared.idle_tx.subscribe();
+        // The sender is owned by `self`, so it can't be dropped while we are waiting.
+        let _ = idle.wait_for(|idle| *idle).await;
+        self.take_error()
+    }
+
+    /// Block the current thread until everything enqueued so far has finished playing.
+    ///
+    /// Returns the first error that occurred while decoding the enqueued audio, if any.
+    pub fn wait_until_finished(&self) -> Result<(), AudioError> {
+        let mut idle = self.shared.idle.lock().unwrap();
+        while !*idle {
+            idle = self.shared.idle_changed.wait(idle).unwrap();
+        }
+        drop(idle);
+        self.take_error()
+    }
+
+    fn take_error(&self) -> Result<(), AudioError> {
+        match self.shared.error.lock().unwrap().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        self.sink.stop();
+        // The player thread may be waiting for the data of a stream
+        self.shared.close_streams();
+        // Closing the channel stops the player thread.
+        self.commands.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run_player_thread(sink: &Sink, shared: &Arc<Shared>, commands: mpsc::Receiver<Command>) {
+    while let Ok(Command::Append {
+        generation,
+        start_offset,
+        input,
+    }) = commands.recv()
+    {
+        if generation == shared.generation.load(Ordering::SeqCst) {
+            let result = match input {
+                Input::Buffer(buffer) => Decoder::new(Cursor::new(buffer))
+                    .map(|source| append(sink, shared, source, start_offset)),
+                Input::Stream(reader) => StreamSource::new(reader)
+                    .map(|source| append(sink, shared, source, start_offset)),
+            };
+            if let Err(e) = result {
+                warn!("Failed to decode audio: {e}");
+                shared.error.lock().unwrap().get_or_insert(e.into());
+            }
+        } else {
+            debug!("Discarding audio that was enqueued before the player was cleared");
+        }
+        shared.update_idle(|| {
+            shared.pending.fetch_sub(1, Ordering::SeqCst);
+            shared.is_drained()
+        });
+    }
+}
+
+fn append(
+    sink: &Sink,
+    shared: &Arc<Shared>,
+    source: impl Source<Item = i16> + Send + 'static,
+    start_offset: Duration,
+) {
+    shared.playing.fetch_add(1, Ordering::SeqCst);
+    sink.append(Tracked {
+        source: source.skip_duration(start_offset),
+        shared: shared.clone(),
+        finished: false,
+    });
+}
+
+/// A source that updates the idle state of the player when it has finished playing or is discarded.
+struct Tracked<S> {
+    source: S,
+    shared: Arc<Shared>,
+    finished: bool,
+}
+
+impl<S> Tracked<S> {
+    fn finish(&mut self) {
+        if !self.finished {
+            self.finished = true;
+            self.shared.remove_playing();
+        }
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for Tracked<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let sample = self.source.next();
+        if sample.is_none() {
+            self.finish();
+        }
+        sample
+    }
+}
+
+impl<S: Source<Item = i16>> Source for Tracked<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+impl<S> Drop for Tracked<S> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[derive(Default)]
+struct DecodedSamples {
+    state: Mutex<DecodedState>,
+    /// Set when the [`StreamSource`] is dropped, to stop decoding
+    discarded: AtomicBool,
+}
+
+#[derive(Default)]
+struct DecodedState {
+    /// Whole frames of decoded samples
+    samples: VecDeque<i16>,
+    finished: bool,
+}
+
+/// A source that plays a stream decoded by a separate thread.
+///
+/// It never blocks the audio thread: silence is played while the decoded samples run out.
+struct StreamSource {
+    decoded: Arc<DecodedSamples>,
+    channels: u16,
+    sample_rate: u32,
+    /// Remaining samples of a silent frame
+    silence: u16,
+}
+
+impl StreamSource {
+    /// Wait for the beginning of the stream and start decoding it.
+    fn new(reader: ChunkReader) -> Result<Self, rodio::decoder::DecoderError> {
+        let decoder = Decoder::new(reader)?;
+        let channels = decoder.channels();
+        let sample_rate = decoder.sample_rate();
+        let decoded = Arc::new(DecodedSamples::default());
+        let thread_decoded = decoded.clone();
+        let batch_len = DECODED_FRAMES_PER_BATCH * channels as usize;
+        let spawned = thread::Builder::new()
+            .name("aspeak-decoder".to_string())
+            .spawn(move || {
+                let mut batch = Vec::with_capacity(batch_len);
+                for sample in decoder {
+                    batch.push(sample);
+                    if batch.len() == batch_len {
+                        if thread_decoded.discarded.load(Ordering::SeqCst) {
+                            break;
+                        }
+                        let mut state = thread_decoded.state.lock().unwrap();
+                        state.samples.extend(batch.drain(..));
+                    }
+                }
+                let mut state = thread_decoded.state.lock().unwrap();
+                // Drop an incomplete frame at the end, so the samples stay aligned to frames
+                let complete = batch.len() - batch.len() % channels as usize;
+                state.samples.extend(batch.drain(..complete));
+                state.finished = true;
+            });
+        if let Err(e) = spawned {
+            warn!("Failed to spawn a thread for decoding the stream: {e}");
+            decoded.state.lock().unwrap().finished = true;
+        }
+        Ok(Self {
+            decoded,
+            channels,
+            sample_rate,
+            silence: 0,
+        })
+    }
+}
+
+impl Iterator for StreamSource {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.silence > 0 {
+            self.silence -= 1;
+            return Some(0);
+        }
+        let mut state = self.decoded.state.lock().unwrap();
+        match state.samples.pop_front() {
+            Some(sample) => Some(sample),
+            None if state.finished => None,
+            None => {
+                // Underrun at a frame boundary, play a silent frame
+                self.silence = self.channels - 1;
+                Some(0)
+            }
+        }
+    }
+}
+
+impl Source for StreamSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Drop for StreamSource {
+    fn drop(&mut self) {
+        self.decoded.discarded.store(true, Ordering::SeqCst);
+    }
+}
+#[derive(Default)]
+struct ChunkBuffer {
+    state: Mutex<ChunkBufferState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct ChunkBufferState {
+    data: Vec<u8>,
+    closed: bool,
+}
+
+impl ChunkBuffer {
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        self.changed.notify_all();
+    }
+}
+
+/// A handle for writing streaming audio to a [`Player`].
+///
+/// The stream is finished when this handle is dropped.
+pub struct PlayerStream {
+    buffer: Arc<ChunkBuffer>,
+}
+
+impl PlayerStream {
+    /// Append a chunk of audio data.
+    ///
+    /// The chunk is discarded if the player has been cleared or dropped.
+    pub fn write(&self, chunk: &[u8]) {
+        let mut state = self.buffer.state.lock().unwrap();
+        if !state.closed {
+            state.data.extend_from_slice(chunk);
+            self.buffer.changed.notify_all();
+        }
+    }
+
+    /// Mark the end of the audio stream.
+    pub fn finish(self) {
+        // The stream is closed in `drop`
+    }
+}
+
+impl Drop for PlayerStream {
+    fn drop(&mut self) {
+        self.buffer.close();
+    }
+}
+
+/// The reading side of a [`PlayerStream`].
+///
+/// Reads block until enough data is written or the stream is finished,
+/// so it is only read by the player thread and the decoding thread of the stream, never by the audio thread.
+struct ChunkReader {
+    buffer: Arc<ChunkBuffer>,
+    position: u64,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.buffer.state.lock().unwrap();
+        while self.position >= state.data.len() as u64 && !state.closed {
+            state = self.buffer.changed.wait(state).unwrap();
+        }
+        let available = state.data.get(self.position as usize..).unwrap_or_default();
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for ChunkReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => {
+                // The length is only known after the stream is finished,
+                // waiting for it would delay the playback until the whole stream is written.
+                let state = self.buffer.state.lock().unwrap();
+                if !state.closed {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "the length of an unfinished stream is unknown",
+                    ));
+                }
+                (state.data.len() as u64).checked_add_signed(offset)
+            }
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader(buffer: &Arc<ChunkBuffer>) -> ChunkReader {
+        ChunkReader {
+            buffer: buffer.clone(),
+            position: 0,
+        }
+    }
+
+    #[test]
+    fn closing_a_stream_unblocks_its_reader() {
+        let buffer = Arc::new(ChunkBuffer::default());
+        let mut reader = reader(&buffer);
+        let thread = thread::spawn(move || {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).map(|_| data)
+        });
+        PlayerStream {
+            buffer: buffer.clone(),
+        }
+        .write(b"abc");
+        // Like `Player::drop`, while the writer is still alive
+        buffer.close();
+        assert_eq!(thread.join().unwrap().unwrap(), b"abc");
+    }
+
+    #[test]
+    fn writes_after_closing_are_discarded() {
+        let buffer = Arc::new(ChunkBuffer::default());
+        let stream = PlayerStream {
+            buffer: buffer.clone(),
+        };
+        buffer.close();
+        stream.write(b"abc");
+        assert!(buffer.state.lock().unwrap().data.is_empty());
+    }
+
+    #[test]
+    fn seeking_from_the_end_of_an_unfinished_stream_fails() {
+        let buffer = Arc::new(ChunkBuffer::default());
+        let stream = PlayerStream {
+            buffer: buffer.clone(),
+        };
+        stream.write(b"abcd");
+        let mut reader = reader(&buffer);
+        assert_eq!(
+            reader.seek(SeekFrom::End(-1)).unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+        assert_eq!(reader.seek(SeekFrom::Start(2)).unwrap(), 2);
+        stream.finish();
+        assert_eq!(reader.seek(SeekFrom::End(-1)).unwrap(), 3);
+    }
+
+    #[test]
+    fn stream_source_plays_silent_frames_on_underrun() {
+        let decoded = Arc::new(DecodedSamples::default());
+        let mut source = StreamSource {
+            decoded: decoded.clone(),
+            channels: 2,
+            sample_rate: 16000,
+            silence: 0,
+        };
+        decoded.state.lock().unwrap().samples.extend([1, 2]);
+        assert_eq!(source.next(), Some(1));
+        assert_eq!(source.next(), Some(2));
+        // The decoder is behind
+        assert_eq!(source.next(), Some(0));
+        decoded.state.lock().unwrap().samples.extend([3, 4]);
+        // The silent frame is completed before the new samples are played
+        assert_eq!(source.next(), Some(0));
+        assert_eq!(source.next(), Some(3));
+        assert_eq!(source.next(), Some(4));
+        decoded.state.lock().unwrap().finished = true;
+        assert_eq!(source.next(), None);
+        drop(source);
+        assert!(decoded.discarded.load(Ordering::SeqCst));
+    }
+}
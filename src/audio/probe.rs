@@ -0,0 +1,492 @@
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+
+use super::{AudioCodec, AudioContainer, AudioFormat};
+
+/// Sample rate of the granule positions in Ogg Opus streams
+const OPUS_GRANULE_RATE: u32 = 48000;
+/// Every AMR-WB frame contains 20 ms of audio
+const AMR_WB_SAMPLES_PER_FRAME: u64 = 320;
+const AMR_WB_MAGIC: &[u8] = b"#!AMR-WB\n";
+
+/// Information about a piece of synthesized audio
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AudioInfo {
+    /// Duration of the audio
+    pub duration: Duration,
+    /// Number of samples per channel
+    pub samples: u64,
+    /// Sample rate in Hz that [`AudioInfo::samples`] is counted in
+    pub sample_rate: u32,
+    /// Number of codec frames.
+    ///
+    /// For PCM, A-law and μ-law this is the number of samples per channel,
+    /// for compressed formats it is the number of MP3 frames, Ogg packets or AMR frames.
+    pub frames: u64,
+}
+
+impl AudioInfo {
+    fn new(samples: u64, sample_rate: u32, frames: u64) -> Self {
+        Self {
+            duration: Duration::from_secs_f64(samples as f64 / sample_rate as f64),
+            samples,
+            sample_rate,
+            frames,
+        }
+    }
+}
+
+/// Inspect synthesized audio in `format` without decoding it.
+///
+/// RIFF headers are parsed, the duration of raw PCM, A-law and μ-law audio is computed from its length,
+/// MP3 frames and AMR-WB frames are walked, and Ogg streams are measured by their granule positions.
+pub fn probe(format: AudioFormat, audio: &[u8]) -> Result<AudioInfo, ProbeError> {
+    match (format.container(), format.codec()) {
+        (AudioContainer::Wav, _) => probe_riff(audio),
+        (AudioContainer::Raw, AudioCodec::Pcm | AudioCodec::ALaw | AudioCodec::MuLaw) => {
+            let bytes_per_frame =
+                (format.bits_per_sample().unwrap_or(16) / 8 * format.channels()) as u64;
+            let samples = audio.len() as u64 / bytes_per_frame;
+            Ok(AudioInfo::new(samples, format.sample_rate(), samples))
+        }
+        (AudioContainer::Mp3, _) => probe_mp3(audio),
+        (AudioContainer::Ogg, _) => probe_ogg(audio),
+        (AudioContainer::Amr, _) => probe_amr_wb(audio),
+        _ => Err(ProbeError {
+            kind: ProbeErrorKind::UnsupportedFormat(format),
+            source: None,
+        }),
+    }
+}
+
+fn invalid(message: &'static str) -> ProbeError {
+    ProbeError {
+        kind: ProbeErrorKind::InvalidData,
+        source: Some(anyhow::anyhow!(message)),
+    }
+}
+
+fn read_u16_le(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn read_u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn probe_riff(audio: &[u8]) -> Result<AudioInfo, ProbeError> {
+    if audio.len() < 12 || &audio[0..4] != b"RIFF" || &audio[8..12] != b"WAVE" {
+        return Err(invalid("missing RIFF/WAVE header"));
+    }
+    let mut chunks = &audio[12..];
+    // Sample rate and bytes per frame from the fmt chunk
+    let mut fmt = None;
+    while chunks.len() >= 8 {
+        let size = read_u32_le(&chunks[4..8]) as usize;
+        let body = &chunks[8..];
+        match &chunks[0..4] {
+            b"fmt " if body.len() >= 16 => {
+                fmt = Some((read_u32_le(&body[4..8]), read_u16_le(&body[12..14])));
+            }
+            b"data" => {
+                let (sample_rate, block_align) = fmt.ok_or_else(|| invalid("missing fmt chunk"))?;
+                if sample_rate == 0 || block_align == 0 {
+                    return Err(invalid("invalid fmt chunk"));
+                }
+                // Streamed files may not contain the correct size of the data chunk
+                let len = size.min(body.len()) as u64;
+                let samples = len / block_align as u64;
+                return Ok(AudioInfo::new(samples, sample_rate, samples));
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even size
+        chunks = size
+            .checked_add(size & 1)
+            .and_then(|size| body.get(size..))
+            .unwrap_or_default();
+    }
+    Err(invalid("missing data chunk"))
+}
+
+/// Size of an ID3v2 tag at the start of `audio`, including its header and footer
+pub(super) fn id3v2_len(audio: &[u8]) -> usize {
+    if audio.len() < 10 || &audio[0..3] != b"ID3" {
+        return 0;
+    }
+    let size = audio[6..10]
+        .iter()
+        .fold(0usize, |size, &b| (size << 7) | (b & 0x7f) as usize);
+    let footer = if audio[5] & 0x10 != 0 { 10 } else { 0 };
+    10 + size + footer
+}
+
+/// A parsed MPEG audio layer III frame header
+struct Mp3FrameHeader {
+    len: usize,
+    samples: u64,
+    sample_rate: u32,
+    /// Offset of the Xing/Info tag from the start of the frame, if the frame contains one
+    tag_offset: usize,
+}
+
+fn parse_mp3_frame_header(header: &[u8]) -> Option<Mp3FrameHeader> {
+    const MPEG1_BITRATES: [u32; 15] = [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ];
+    const MPEG2_BITRATES: [u32; 15] =
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+    const SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+    if header.len() < 4 || header[0] != 0xff || header[1] & 0xe0 != 0xe0 {
+        return None;
+    }
+    let version = (header[1] >> 3) & 0b11;
+    let layer = (header[1] >> 1) & 0b11;
+    let bitrate_index = (header[2] >> 4) as usize;
+    let sample_rate_index = ((header[2] >> 2) & 0b11) as usize;
+    let padding = ((header[2] >> 1) & 1) as usize;
+    let mono = header[3] >> 6 == 0b11;
+    // Only layer III, the free bitrate is not supported
+    if version == 0b01 || layer != 0b01 || bitrate_index == 0 || bitrate_index == 15 {
+        return None;
+    }
+    let sample_rate = SAMPLE_RATES.get(sample_rate_index)?
+        >> match version {
+            0b11 => 0, // MPEG 1
+            0b10 => 1, // MPEG 2
+            _ => 2,    // MPEG 2.5
+        };
+    let mpeg1 = version == 0b11;
+    let (bitrate, samples) = if mpeg1 {
+        (MPEG1_BITRATES[bitrate_index], 1152)
+    } else {
+        (MPEG2_BITRATES[bitrate_index], 576)
+    };
+    let len = (samples / 8 * bitrate as usize * 1000) / sample_rate as usize + padding;
+    // The tag follows the side information
+    let side_info = match (mpeg1, mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+    Some(Mp3FrameHeader {
+        len,
+        samples: samples as u64,
+        sample_rate,
+        tag_offset: 4 + side_info,
+    })
+}
+
+fn probe_mp3(audio: &[u8]) -> Result<AudioInfo, ProbeError> {
+    let mut position = id3v2_len(audio);
+    let mut frames = 0u64;
+    let mut samples = 0u64;
+    let mut sample_rate = None;
+    while let Some(header) = audio.get(position..).and_then(parse_mp3_frame_header) {
+        let frame = match audio.get(position..position + header.len) {
+            Some(frame) => frame,
+            // Truncated frame at the end of the stream
+            None => break,
+        };
+        let is_tag = frames == 0
+            && matches!(
+                frame.get(header.tag_offset..header.tag_offset + 4),
+                Some(b"Xing" | b"Info")
+            );
+        // The Xing/Info frame doesn't contain any audio
+        if !is_tag {
+            frames += 1;
+            samples += header.samples;
+        }
+        sample_rate.get_or_insert(header.sample_rate);
+        position += header.len;
+    }
+    match sample_rate {
+        Some(sample_rate) => Ok(AudioInfo::new(samples, sample_rate, frames)),
+        None => Err(invalid("no MP3 frame found")),
+    }
+}
+
+fn probe_ogg(audio: &[u8]) -> Result<AudioInfo, ProbeError> {
+    let mut position = 0;
+    let mut packets = 0u64;
+    let mut granule_position = None;
+    let mut pre_skip = 0u64;
+    while let Some(header) = audio.get(position..position + 27) {
+        if &header[0..4] != b"OggS" {
+            return Err(invalid("missing Ogg page header"));
+        }
+        let segment_count = header[26] as usize;
+        let segments = audio
+            .get(position + 27..position + 27 + segment_count)
+            .ok_or_else(|| invalid("truncated Ogg page"))?;
+        let body_start = position + 27 + segment_count;
+        let body_len: usize = segments.iter().map(|&s| s as usize).sum();
+        if position == 0 {
+            let body = audio
+                .get(body_start..body_start + body_len)
+                .ok_or_else(|| invalid("truncated Ogg page"))?;
+            if body.len() >= 12 && &body[0..8] == b"OpusHead" {
+                pre_skip = read_u16_le(&body[10..12]) as u64;
+            }
+        }
+        // A packet ends with a segment shorter than 255 bytes
+        packets += segments.iter().filter(|&&s| s < 255).count() as u64;
+        // Pages where no packet ends have a granule position of -1
+        let granule = i64::from_le_bytes(header[6..14].try_into().unwrap());
+        if granule >= 0 {
+            granule_position = Some(granule as u64);
+        }
+        position = body_start + body_len;
+    }
+    let granule_position = granule_position.ok_or_else(|| invalid("no Ogg page found"))?;
+    // The identification header and the comment header are not audio
+    let frames = packets.saturating_sub(2);
+    let samples = granule_position.saturating_sub(pre_skip);
+    Ok(AudioInfo::new(samples, OPUS_GRANULE_RATE, frames))
+}
+
+fn probe_amr_wb(audio: &[u8]) -> Result<AudioInfo, ProbeError> {
+    // Frame sizes in bytes by frame type, including the table of contents byte
+    const FRAME_SIZES: [usize; 16] = [18, 24, 33, 37, 41, 47, 51, 59, 61, 6, 1, 1, 1, 1, 1, 1];
+    let mut frames_data = audio
+        .strip_prefix(AMR_WB_MAGIC)
+        .ok_or_else(|| invalid("missing AMR-WB header"))?;
+    let mut frames = 0u64;
+    while let Some(&toc) = frames_data.first() {
+        let size = FRAME_SIZES[((toc >> 3) & 0x0f) as usize];
+        frames_data = match frames_data.get(size..) {
+            Some(rest) => rest,
+            None => break,
+        };
+        frames += 1;
+    }
+    Ok(AudioInfo::new(
+        frames * AMR_WB_SAMPLES_PER_FRAME,
+        16000,
+        frames,
+    ))
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+/// An error that can occur when inspecting audio
+pub struct ProbeError {
+    pub kind: ProbeErrorKind,
+    pub(crate) source: Option<anyhow::Error>,
+}
+
+impl Display for ProbeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "probe error: ")?;
+        match &self.kind {
+            ProbeErrorKind::UnsupportedFormat(format) => write!(
+                f,
+                "audio format {} can not be inspected",
+                Into::<&str>::into(format)
+            ),
+            ProbeErrorKind::InvalidData => write!(f, "invalid audio data"),
+        }
+    }
+}
+
+impl Error for ProbeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|e| e.as_ref() as _)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
+pub enum ProbeErrorKind {
+    /// Inspecting this format is not supported
+    UnsupportedFormat(AudioFormat),
+    /// The audio is malformed or doesn't match the format
+    InvalidData,
+}
+
+#[cfg(feature = "python")]
+impl From<ProbeError> for pyo3::PyErr {
+    fn from(value: ProbeError) -> Self {
+        pyo3::exceptions::PyOSError::new_err(format!("{:?}", color_eyre::Report::from(value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An MPEG 2 layer III frame at 24 kHz and 48 kbps, 144 bytes long
+    fn mpeg2_frame(tag: Option<&[u8; 4]>) -> Vec<u8> {
+        let mut frame = vec![0u8; 144];
+        frame[..4].copy_from_slice(&[0xff, 0xf3, 0x64, 0xc0]);
+        if let Some(tag) = tag {
+            // After the 9 bytes of side information of a mono MPEG 2 frame
+            frame[13..17].copy_from_slice(tag);
+        }
+        frame
+    }
+
+    /// An MPEG 1 layer III frame at 48 kHz and 96 kbps, 288 bytes long
+    fn mpeg1_frame(tag: Option<&[u8; 4]>) -> Vec<u8> {
+        let mut frame = vec![0u8; 288];
+        frame[..4].copy_from_slice(&[0xff, 0xfb, 0x74, 0xc0]);
+        if let Some(tag) = tag {
+            frame[21..25].copy_from_slice(tag);
+        }
+        frame
+    }
+
+    #[test]
+    fn mp3_frames_are_counted() {
+        let audio = mpeg2_frame(None).repeat(50);
+        let info = probe(AudioFormat::Audio24Khz48KBitRateMonoMp3, &audio).unwrap();
+        assert_eq!(info.frames, 50);
+        assert_eq!(info.samples, 50 * 576);
+        assert_eq!(info.sample_rate, 24000);
+        assert_eq!(info.duration, Duration::from_millis(1200));
+    }
+
+    #[test]
+    fn mp3_id3_tag_and_xing_frame_are_skipped() {
+        // An ID3v2 tag with a syncsafe size of 200 bytes
+        let mut audio = b"ID3\x04\x00\x00\x00\x00\x01\x48".to_vec();
+        audio.resize(210, 0);
+        audio.extend(mpeg1_frame(Some(b"Xing")));
+        for _ in 0..10 {
+            audio.extend(mpeg1_frame(None));
+        }
+        // A truncated frame at the end
+        audio.extend(&mpeg1_frame(None)[..100]);
+        let info = probe(AudioFormat::Audio48Khz96KBitRateMonoMp3, &audio).unwrap();
+        assert_eq!(info.frames, 10);
+        assert_eq!(info.samples, 11520);
+        assert_eq!(info.duration, Duration::from_millis(240));
+    }
+
+    #[test]
+    fn mp3_info_tag_is_only_skipped_in_the_first_frame() {
+        let mut audio = mpeg2_frame(Some(b"Info"));
+        audio.extend(mpeg2_frame(None));
+        audio.extend(mpeg2_frame(Some(b"Info")));
+        let info = probe(AudioFormat::Audio24Khz48KBitRateMonoMp3, &audio).unwrap();
+        assert_eq!(info.frames, 2);
+    }
+
+    #[test]
+    fn mp3_without_frames_is_invalid() {
+        let error = probe(AudioFormat::Audio24Khz48KBitRateMonoMp3, &[0; 100]).unwrap_err();
+        assert_eq!(error.kind, ProbeErrorKind::InvalidData);
+    }
+
+    fn ogg_page(granule_position: i64, packets: &[&[u8]]) -> Vec<u8> {
+        let mut lacing = Vec::new();
+        for packet in packets {
+            lacing.extend(std::iter::repeat(255).take(packet.len() / 255));
+            lacing.push((packet.len() % 255) as u8);
+        }
+        let mut page = b"OggS\0\0".to_vec();
+        page.extend(granule_position.to_le_bytes());
+        // Serial number, sequence number and checksum are not inspected
+        page.extend([0; 12]);
+        page.push(lacing.len() as u8);
+        page.extend(lacing);
+        for packet in packets {
+            page.extend_from_slice(packet);
+        }
+        page
+    }
+
+    fn opus_head(pre_skip: u16) -> Vec<u8> {
+        let mut head = b"OpusHead\x01\x01".to_vec();
+        head.extend(pre_skip.to_le_bytes());
+        head.extend(24000u32.to_le_bytes());
+        head.extend([0, 0, 0]);
+        head
+    }
+
+    #[test]
+    fn ogg_duration_is_the_last_granule_position_minus_pre_skip() {
+        let packet: &[u8] = &[0; 60];
+        let mut audio = ogg_page(0, &[&opus_head(312)]);
+        audio.extend(ogg_page(0, &[b"OpusTags"]));
+        // 20 ms packets at the 48 kHz granule rate
+        audio.extend(ogg_page(312 + 25 * 960, &[packet; 25]));
+        audio.extend(ogg_page(312 + 50 * 960, &[packet; 25]));
+        let info = probe(AudioFormat::Ogg24Khz16BitMonoOpus, &audio).unwrap();
+        assert_eq!(info.frames, 50);
+        assert_eq!(info.samples, 48000);
+        assert_eq!(info.sample_rate, 48000);
+        assert_eq!(info.duration, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn ogg_packets_spanning_pages_are_counted_once() {
+        let long_packet = [0u8; 600];
+        let mut audio = ogg_page(0, &[&opus_head(0)]);
+        audio.extend(ogg_page(0, &[b"OpusTags"]));
+        // The first 510 bytes of the packet, the page ends with two 255 byte segments
+        let mut page = ogg_page(-1, &[&long_packet[..510]]);
+        // Remove the terminating empty segment, so the packet continues on the next page
+        page[26] -= 1;
+        page.remove(27 + 2);
+        audio.extend(page);
+        audio.extend(ogg_page(960, &[&long_packet[510..]]));
+        let info = probe(AudioFormat::Ogg24Khz16BitMonoOpus, &audio).unwrap();
+        assert_eq!(info.frames, 1);
+        assert_eq!(info.samples, 960);
+    }
+
+    #[test]
+    fn ogg_without_pages_is_invalid() {
+        let error = probe(AudioFormat::Ogg24Khz16BitMonoOpus, b"RIFF").unwrap_err();
+        assert_eq!(error.kind, ProbeErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn amr_wb_frames_last_20_ms() {
+        let mut audio = AMR_WB_MAGIC.to_vec();
+        // 23.85 kbps speech frames, 61 bytes each
+        for _ in 0..48 {
+            let mut frame = vec![0u8; 61];
+            frame[0] = (8 << 3) | 0x04;
+            audio.extend(frame);
+        }
+        // A comfort noise frame and a frame without data
+        audio.extend([(9 << 3) | 0x04, 0, 0, 0, 0, 0]);
+        audio.push((15 << 3) | 0x04);
+        // A truncated frame
+        audio.extend([(8 << 3) | 0x04, 0, 0]);
+        let info = probe(AudioFormat::AmrWb16000Hz, &audio).unwrap();
+        assert_eq!(info.frames, 50);
+        assert_eq!(info.samples, 16000);
+        assert_eq!(info.duration, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn amr_wb_without_magic_is_invalid() {
+        let error = probe(AudioFormat::AmrWb16000Hz, &[0; 10]).unwrap_err();
+        assert_eq!(error.kind, ProbeErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn riff_duration_comes_from_the_data_chunk() {
+        let mut audio =
+            crate::audio::pcm::wav_header(AudioFormat::Riff8Khz8BitMonoALaw, 4000).unwrap();
+        audio.resize(audio.len() + 4000, 0xd5);
+        let info = probe(AudioFormat::Riff8Khz8BitMonoALaw, &audio).unwrap();
+        assert_eq!(info.samples, 4000);
+        assert_eq!(info.duration, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn raw_duration_comes_from_the_length() {
+        let info = probe(AudioFormat::Raw24Khz16BitMonoPcm, &[0; 4800]).unwrap();
+        assert_eq!(info.samples, 2400);
+        assert_eq!(info.duration, Duration::from_millis(100));
+    }
+}
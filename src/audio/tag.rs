@@ -0,0 +1,866 @@
+//! Embedding metadata into synthesized audio.
+//!
+//! [`AudioTags`] record how a piece of audio was synthesized: the voice, the options and a hash of the SSML.
+//! They are written as an ID3v2 tag into MP3 files, as Vorbis comments into Ogg/Opus and FLAC files,
+//! and as a `LIST`/`INFO` chunk into RIFF/WAVE files.
+
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+use sha2::{Digest, Sha256};
+use xml::reader::{EventReader, XmlEvent};
+
+use super::{probe::id3v2_len, AudioContainer};
+use crate::TextOptions;
+
+/// The software that produced the audio, written into every tag
+const ENCODER: &str = concat!("aspeak ", env!("CARGO_PKG_VERSION"));
+
+/// Metadata describing how a piece of audio was synthesized
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AudioTags {
+    /// The voice that spoke the audio
+    pub(crate) voice: Option<String>,
+    /// Date and time of the synthesis
+    pub(crate) date: Option<String>,
+    /// Other fields as pairs of upper case names and values
+    pub(crate) fields: Vec<(String, String)>,
+}
+
+impl AudioTags {
+    /// The voice that spoke the audio
+    pub fn voice(&self) -> Option<&str> {
+        self.voice.as_deref()
+    }
+
+    /// The voice that spoke the audio
+    pub fn voice_mut(&mut self) -> &mut Option<String> {
+        &mut self.voice
+    }
+
+    /// Date and time of the synthesis
+    pub fn date(&self) -> Option<&str> {
+        self.date.as_deref()
+    }
+
+    /// Date and time of the synthesis
+    pub fn date_mut(&mut self) -> &mut Option<String> {
+        &mut self.date
+    }
+
+    /// Other fields as pairs of upper case names and values
+    pub fn fields(&self) -> &[(String, String)] {
+        &self.fields
+    }
+
+    /// Other fields as pairs of upper case names and values
+    pub fn fields_mut(&mut self) -> &mut Vec<(String, String)> {
+        &mut self.fields
+    }
+
+    /// Create a builder for [`AudioTags`]
+    pub fn builder() -> AudioTagsBuilder {
+        AudioTagsBuilder::new()
+    }
+
+    /// Pairs of Vorbis comment field names and values
+    fn vorbis_comments(&self) -> Vec<(&str, &str)> {
+        let mut comments = Vec::with_capacity(self.fields.len() + 3);
+        if let Some(voice) = self.voice.as_deref() {
+            comments.push(("ARTIST", voice));
+        }
+        if let Some(date) = self.date.as_deref() {
+            comments.push(("DATE", date));
+        }
+        comments.push(("ENCODER", ENCODER));
+        comments.extend(self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        comments
+    }
+
+    /// All fields in a single line, for formats without custom fields
+    fn fields_as_comment(&self) -> Option<String> {
+        (!self.fields.is_empty()).then(|| {
+            self.fields
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect::<Vec<_>>()
+                .join("; ")
+        })
+    }
+}
+
+/// Builder for [`AudioTags`]
+#[derive(Debug, Default)]
+pub struct AudioTagsBuilder {
+    voice: Option<String>,
+    date: Option<String>,
+    fields: Vec<(String, String)>,
+}
+
+impl AudioTagsBuilder {
+    /// Create a new builder
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The voice that spoke the audio
+    pub fn voice(mut self, voice: impl Into<String>) -> Self {
+        self.voice = Some(voice.into());
+        self
+    }
+
+    /// The voice that spoke the audio
+    pub fn optional_voice(mut self, voice: Option<impl Into<String>>) -> Self {
+        self.voice = voice.map(Into::into);
+        self
+    }
+
+    /// Date and time of the synthesis, preferably in ISO 8601 format
+    pub fn date(mut self, date: impl Into<String>) -> Self {
+        self.date = Some(date.into());
+        self
+    }
+
+    /// Date and time of the synthesis, preferably in ISO 8601 format
+    pub fn optional_date(mut self, date: Option<impl Into<String>>) -> Self {
+        self.date = date.map(Into::into);
+        self
+    }
+
+    /// Add a custom field. The name is converted to upper case.
+    pub fn field(mut self, name: impl AsRef<str>, value: impl Into<String>) -> Self {
+        self.fields
+            .push((name.as_ref().to_uppercase(), value.into()));
+        self
+    }
+
+    /// Record the voice, style, role, style degree, rate and pitch of [`TextOptions`]
+    pub fn text_options(mut self, options: &TextOptions) -> Self {
+        self.voice = Some(options.voice().to_string());
+        if let Some(rich) = options.rich_ssml_options() {
+            if let Some(style) = rich.style() {
+                self = self.field("STYLE", style);
+            }
+            if let Some(role) = rich.role() {
+                self = self.field("ROLE", Into::<&str>::into(role));
+            }
+            if let Some(style_degree) = rich.style_degree() {
+                self = self.field("STYLE_DEGREE", style_degree.to_string());
+            }
+        }
+        if let Some(rate) = options.rate() {
+            self = self.field("RATE", rate);
+        }
+        if let Some(pitch) = options.pitch() {
+            self = self.field("PITCH", pitch);
+        }
+        self
+    }
+
+    /// Record the SHA-256 hash of the synthesized SSML.
+    ///
+    /// If no voice is recorded yet, the voice of the first `<voice>` element in the SSML is recorded.
+    pub fn ssml(mut self, ssml: &str) -> Self {
+        if self.voice.is_none() {
+            self.voice = ssml_voice(ssml);
+        }
+        let hash: String = Sha256::digest(ssml.as_bytes())
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        self.field("SSML_SHA256", hash)
+    }
+
+    /// Build [`AudioTags`]
+    pub fn build(self) -> AudioTags {
+        AudioTags {
+            voice: self.voice,
+            date: self.date,
+            fields: self.fields,
+        }
+    }
+}
+
+/// The name of the first voice in `ssml`, if the SSML is well-formed up to that voice
+fn ssml_voice(ssml: &str) -> Option<String> {
+    EventReader::from_str(ssml)
+        .into_iter()
+        .map_while(Result::ok)
+        .find_map(|event| match event {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } if name.local_name == "voice" => attributes
+                .into_iter()
+                .find(|attribute| attribute.name.local_name == "name")
+                .map(|attribute| attribute.value),
+            _ => None,
+        })
+}
+
+/// Embed `tags` into audio in `container`.
+///
+/// MP3 files get an ID3v2.4 tag, replacing any existing ID3v2 tag.
+/// Ogg/Opus files get the tags appended to their Vorbis comments.
+/// RIFF/WAVE files get a `LIST`/`INFO` chunk before the audio data, replacing any existing one.
+pub fn embed_tags(
+    container: AudioContainer,
+    audio: &[u8],
+    tags: &AudioTags,
+) -> Result<Vec<u8>, TagError> {
+    match container {
+        AudioContainer::Mp3 => Ok(embed_in_mp3(audio, tags)),
+        AudioContainer::Ogg => embed_in_ogg(audio, tags),
+        AudioContainer::Wav => embed_in_riff(audio, tags),
+        _ => Err(TagError {
+            kind: TagErrorKind::UnsupportedContainer(container),
+            source: None,
+        }),
+    }
+}
+
+fn invalid(message: &'static str) -> TagError {
+    TagError {
+        kind: TagErrorKind::InvalidData,
+        source: Some(anyhow::anyhow!(message)),
+    }
+}
+
+fn read_u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Encode a size as an ID3v2 synchsafe integer
+fn synchsafe(size: usize) -> [u8; 4] {
+    [
+        (size >> 21) as u8 & 0x7f,
+        (size >> 14) as u8 & 0x7f,
+        (size >> 7) as u8 & 0x7f,
+        size as u8 & 0x7f,
+    ]
+}
+
+fn id3v2_frame(tag: &mut Vec<u8>, id: &[u8; 4], content: &[&str]) {
+    // UTF-8 encoding, the values are separated by null characters
+    let body: Vec<u8> = std::iter::once(3u8)
+        .chain(content.join("\0").into_bytes())
+        .collect();
+    tag.extend_from_slice(id);
+    tag.extend_from_slice(&synchsafe(body.len()));
+    tag.extend_from_slice(&[0, 0]);
+    tag.extend(body);
+}
+
+fn embed_in_mp3(audio: &[u8], tags: &AudioTags) -> Vec<u8> {
+    let mut frames = Vec::new();
+    if let Some(voice) = tags.voice.as_deref() {
+        id3v2_frame(&mut frames, b"TPE1", &[voice]);
+    }
+    if let Some(date) = tags.date.as_deref() {
+        id3v2_frame(&mut frames, b"TDRC", &[date]);
+    }
+    id3v2_frame(&mut frames, b"TSSE", &[ENCODER]);
+    for (name, value) in tags.fields.iter() {
+        id3v2_frame(&mut frames, b"TXXX", &[name, value]);
+    }
+    let audio = audio.get(id3v2_len(audio)..).unwrap_or_default();
+    let mut output = Vec::with_capacity(10 + frames.len() + audio.len());
+    // ID3v2.4.0 without any flags
+    output.extend_from_slice(b"ID3\x04\x00\x00");
+    output.extend_from_slice(&synchsafe(frames.len()));
+    output.extend(frames);
+    output.extend_from_slice(audio);
+    output
+}
+
+/// Serialize a Vorbis comment block without the framing bit
+fn vorbis_comment(vendor: &[u8], comments: &[Vec<u8>]) -> Vec<u8> {
+    let mut block = Vec::new();
+    block.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    block.extend_from_slice(vendor);
+    block.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for comment in comments {
+        block.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        block.extend_from_slice(comment);
+    }
+    block
+}
+
+/// Parse a Vorbis comment block into its vendor string and comments
+fn parse_vorbis_comment(block: &[u8]) -> Result<(&[u8], Vec<&[u8]>), TagError> {
+    let truncated = || invalid("truncated Vorbis comment");
+    let mut rest = block;
+    let mut take = |len: usize| {
+        let (head, tail) = (rest.get(..len).ok_or_else(truncated)?, &rest[len..]);
+        rest = tail;
+        Ok::<_, TagError>(head)
+    };
+    let vendor_len = read_u32_le(take(4)?) as usize;
+    let vendor = take(vendor_len)?;
+    let count = read_u32_le(take(4)?) as usize;
+    let comments = (0..count)
+        .map(|_| {
+            let len = read_u32_le(take(4)?) as usize;
+            take(len)
+        })
+        .collect::<Result<_, _>>()?;
+    Ok((vendor, comments))
+}
+
+/// Merge `tags` into existing comments, replacing comments of the same fields
+fn merge_vorbis_comments(existing: Vec<&[u8]>, tags: &AudioTags) -> Vec<Vec<u8>> {
+    let new = tags.vorbis_comments();
+    existing
+        .into_iter()
+        .filter(|comment| {
+            let name = comment.split(|&b| b == b'=').next().unwrap_or_default();
+            !new.iter()
+                .any(|(field, _)| field.as_bytes().eq_ignore_ascii_case(name))
+        })
+        .map(<[u8]>::to_vec)
+        .chain(new.iter().map(|(k, v)| format!("{k}={v}").into_bytes()))
+        .collect()
+}
+
+fn ogg_crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |mut crc, &byte| {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// An Ogg page borrowed from a stream
+struct OggPage<'a> {
+    header_type: u8,
+    granule_position: u64,
+    serial: u32,
+    sequence: u32,
+    segments: &'a [u8],
+    body: &'a [u8],
+}
+
+impl OggPage<'_> {
+    fn write(&self, output: &mut Vec<u8>) {
+        let start = output.len();
+        output.extend_from_slice(b"OggS\0");
+        output.push(self.header_type);
+        output.extend_from_slice(&self.granule_position.to_le_bytes());
+        output.extend_from_slice(&self.serial.to_le_bytes());
+        output.extend_from_slice(&self.sequence.to_le_bytes());
+        // The checksum is computed with the checksum field set to zero
+        output.extend_from_slice(&[0; 4]);
+        output.push(self.segments.len() as u8);
+        output.extend_from_slice(self.segments);
+        output.extend_from_slice(self.body);
+        let crc = ogg_crc32(&output[start..]);
+        output[start + 22..start + 26].copy_from_slice(&crc.to_le_bytes());
+    }
+}
+
+fn parse_ogg_pages(audio: &[u8]) -> Result<Vec<OggPage<'_>>, TagError> {
+    let truncated = || invalid("truncated Ogg page");
+    let mut pages = Vec::new();
+    let mut position = 0;
+    while position < audio.len() {
+        let header = audio.get(position..position + 27).ok_or_else(truncated)?;
+        if &header[0..4] != b"OggS" {
+            return Err(invalid("missing Ogg page header"));
+        }
+        let segment_count = header[26] as usize;
+        let segments = audio
+            .get(position + 27..position + 27 + segment_count)
+            .ok_or_else(truncated)?;
+        let body_start = position + 27 + segment_count;
+        let body_len: usize = segments.iter().map(|&s| s as usize).sum();
+        let body = audio
+            .get(body_start..body_start + body_len)
+            .ok_or_else(truncated)?;
+        pages.push(OggPage {
+            header_type: header[5],
+            granule_position: u64::from_le_bytes(header[6..14].try_into().unwrap()),
+            serial: read_u32_le(&header[14..18]),
+            sequence: read_u32_le(&header[18..22]),
+            segments,
+            body,
+        });
+        position = body_start + body_len;
+    }
+    Ok(pages)
+}
+
+fn embed_in_ogg(audio: &[u8], tags: &AudioTags) -> Result<Vec<u8>, TagError> {
+    let pages = parse_ogg_pages(audio)?;
+    let head = pages.first().ok_or_else(|| invalid("no Ogg page found"))?;
+    if !head.body.starts_with(b"OpusHead") {
+        return Err(invalid("only Ogg/Opus streams can be tagged"));
+    }
+    // The comment header starts on the second page, and the audio starts on a fresh page after it
+    let mut packet = Vec::new();
+    let mut comment_pages = 0;
+    for page in pages[1..].iter() {
+        packet.extend_from_slice(page.body);
+        comment_pages += 1;
+        if let Some(end) = page.segments.iter().position(|&s| s < 255) {
+            if end + 1 != page.segments.len() {
+                return Err(invalid("audio data on the comment header page"));
+            }
+            break;
+        }
+    }
+    let comment = packet
+        .strip_prefix(b"OpusTags")
+        .ok_or_else(|| invalid("missing OpusTags header"))?;
+    let (vendor, existing) = parse_vorbis_comment(comment)?;
+    let mut packet = b"OpusTags".to_vec();
+    packet.extend(vorbis_comment(
+        vendor,
+        &merge_vorbis_comments(existing, tags),
+    ));
+
+    let mut output = Vec::with_capacity(audio.len() + packet.len());
+    output.extend_from_slice(&audio[..27 + head.segments.len() + head.body.len()]);
+    // Lacing values: 255 for every full segment, followed by the remainder
+    let mut lacing = vec![255u8; packet.len() / 255];
+    lacing.push((packet.len() % 255) as u8);
+    let mut body = packet.as_slice();
+    let new_comment_pages = lacing.chunks(255).len();
+    for (i, segments) in lacing.chunks(255).enumerate() {
+        let len: usize = segments.iter().map(|&s| s as usize).sum();
+        let last = i + 1 == new_comment_pages;
+        OggPage {
+            header_type: if i == 0 { 0 } else { 1 },
+            // Pages where no packet ends have a granule position of -1
+            granule_position: if last { 0 } else { u64::MAX },
+            serial: head.serial,
+            sequence: 1 + i as u32,
+            segments,
+            body: &body[..len],
+        }
+        .write(&mut output);
+        body = &body[len..];
+    }
+    for page in pages[1 + comment_pages..].iter() {
+        OggPage {
+            sequence: (page.sequence as usize + new_comment_pages - comment_pages) as u32,
+            ..*page
+        }
+        .write(&mut output);
+    }
+    Ok(output)
+}
+
+fn riff_info_chunk(tags: &AudioTags) -> Vec<u8> {
+    let comment = tags.fields_as_comment();
+    let entries = [
+        (b"IART", tags.voice.as_deref()),
+        (b"ICRD", tags.date.as_deref()),
+        (b"ISFT", Some(ENCODER)),
+        (b"ICMT", comment.as_deref()),
+    ];
+    let mut chunk = b"LIST\0\0\0\0INFO".to_vec();
+    for (id, value) in entries {
+        if let Some(value) = value {
+            // Null terminated strings, padded to an even size
+            let len = value.len() + 1;
+            chunk.extend_from_slice(id);
+            chunk.extend_from_slice(&(len as u32).to_le_bytes());
+            chunk.extend_from_slice(value.as_bytes());
+            chunk.push(0);
+            if len % 2 == 1 {
+                chunk.push(0);
+            }
+        }
+    }
+    let len = (chunk.len() - 8) as u32;
+    chunk[4..8].copy_from_slice(&len.to_le_bytes());
+    chunk
+}
+
+fn embed_in_riff(audio: &[u8], tags: &AudioTags) -> Result<Vec<u8>, TagError> {
+    if audio.len() < 12 || &audio[0..4] != b"RIFF" || &audio[8..12] != b"WAVE" {
+        return Err(invalid("missing RIFF/WAVE header"));
+    }
+    let info = riff_info_chunk(tags);
+    let mut output = Vec::with_capacity(audio.len() + info.len());
+    output.extend_from_slice(&audio[..12]);
+    let mut position = 12;
+    loop {
+        let header = audio
+            .get(position..position + 8)
+            .ok_or_else(|| invalid("missing data chunk"))?;
+        if &header[0..4] == b"data" {
+            break;
+        }
+        let size = read_u32_le(&header[4..8]) as usize;
+        // Chunks are padded to an even size
+        let end = (position + 8)
+            .checked_add(size)
+            .and_then(|end| end.checked_add(size & 1))
+            .map_or(audio.len(), |end| end.min(audio.len()));
+        let is_info =
+            &header[0..4] == b"LIST" && audio.get(position + 8..position + 12) == Some(b"INFO");
+        if !is_info {
+            output.extend_from_slice(&audio[position..end]);
+        }
+        position = end;
+    }
+    output.extend(info);
+    // The data chunk is copied as is, since streamed files may not contain its correct size
+    output.extend_from_slice(&audio[position..]);
+    let riff_len = u32::try_from(output.len() - 8).unwrap_or(u32::MAX);
+    output[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    Ok(output)
+}
+
+/// Embed `tags` as Vorbis comments into a FLAC stream, replacing any existing Vorbis comments
+#[cfg(feature = "transcode")]
+pub(crate) fn embed_in_flac(audio: &[u8], tags: &AudioTags) -> Result<Vec<u8>, TagError> {
+    const VORBIS_COMMENT: u8 = 4;
+    if !audio.starts_with(b"fLaC") {
+        return Err(invalid("missing FLAC stream marker"));
+    }
+    let mut output = Vec::with_capacity(audio.len());
+    output.extend_from_slice(b"fLaC");
+    let mut position = 4;
+    loop {
+        let header = audio
+            .get(position..position + 4)
+            .ok_or_else(|| invalid("truncated FLAC metadata"))?;
+        let last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7f;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let end = position + 4 + len;
+        let body = audio
+            .get(position + 4..end)
+            .ok_or_else(|| invalid("truncated FLAC metadata"))?;
+        if block_type != VORBIS_COMMENT {
+            // Our block is the last one now
+            output.push(block_type);
+            output.extend_from_slice(&header[1..4]);
+            output.extend_from_slice(body);
+        }
+        position = end;
+        if last {
+            break;
+        }
+    }
+    let block = vorbis_comment(ENCODER.as_bytes(), &merge_vorbis_comments(vec![], tags));
+    output.push(0x80 | VORBIS_COMMENT);
+    output.extend_from_slice(&(block.len() as u32).to_be_bytes()[1..]);
+    output.extend(block);
+    output.extend_from_slice(&audio[position..]);
+    Ok(output)
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+/// An error that can occur when embedding tags into audio
+pub struct TagError {
+    pub kind: TagErrorKind,
+    pub(crate) source: Option<anyhow::Error>,
+}
+
+impl Display for TagError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "tag error: ")?;
+        match &self.kind {
+            TagErrorKind::UnsupportedContainer(container) => write!(
+                f,
+                "{} audio can not be tagged",
+                Into::<&str>::into(container)
+            ),
+            TagErrorKind::InvalidData => write!(f, "invalid audio data"),
+        }
+    }
+}
+
+impl Error for TagError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|e| e.as_ref() as _)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
+pub enum TagErrorKind {
+    /// Tagging this container is not supported
+    UnsupportedContainer(AudioContainer),
+    /// The audio is malformed or doesn't match the container
+    InvalidData,
+}
+
+#[cfg(feature = "python")]
+impl From<TagError> for pyo3::PyErr {
+    fn from(value: TagError) -> Self {
+        pyo3::exceptions::PyOSError::new_err(format!("{:?}", color_eyre::Report::from(value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags() -> AudioTags {
+        AudioTags::builder()
+            .voice("en-US-JennyNeural")
+            .date("2023-05-01T12:00:00Z")
+            .field("style", "cheerful")
+            .build()
+    }
+
+    #[test]
+    fn voice_is_read_from_ssml() {
+        let ssml = r#"<speak version="1.0" xmlns="http://www.w3.org/2001/10/synthesis" xml:lang="en-US"><voice name="en-US-GuyNeural">Hello</voice></speak>"#;
+        let tags = AudioTags::builder().ssml(ssml).build();
+        assert_eq!(tags.voice(), Some("en-US-GuyNeural"));
+        assert_eq!(tags.fields()[0].0, "SSML_SHA256");
+        // A voice that is already known is kept
+        let tags = AudioTags::builder().voice("other").ssml(ssml).build();
+        assert_eq!(tags.voice(), Some("other"));
+        let tags = AudioTags::builder().ssml("<speak>unclosed").build();
+        assert_eq!(tags.voice(), None);
+    }
+
+    #[test]
+    fn synchsafe_integers_use_7_bits_per_byte() {
+        assert_eq!(synchsafe(200), [0, 0, 1, 0x48]);
+        assert_eq!(synchsafe(0x0fff_ffff), [0x7f; 4]);
+    }
+
+    #[test]
+    fn mp3_gets_an_id3v2_4_tag_replacing_the_existing_one() {
+        let frame = [0xff, 0xf3, 0x64, 0xc0, 1, 2, 3];
+        let mut audio = b"ID3\x03\x00\x00\x00\x00\x00\x02\xaa\xbb".to_vec();
+        audio.extend(frame);
+        let tagged = embed_tags(AudioContainer::Mp3, &audio, &tags()).unwrap();
+        assert_eq!(&tagged[..6], b"ID3\x04\x00\x00");
+        let len = id3v2_len(&tagged);
+        assert_eq!(&tagged[len..], frame);
+        let mut expected = Vec::new();
+        id3v2_frame(&mut expected, b"TPE1", &["en-US-JennyNeural"]);
+        id3v2_frame(&mut expected, b"TDRC", &["2023-05-01T12:00:00Z"]);
+        id3v2_frame(&mut expected, b"TSSE", &[ENCODER]);
+        id3v2_frame(&mut expected, b"TXXX", &["STYLE", "cheerful"]);
+        assert_eq!(&tagged[10..len], expected);
+        // The frame header is followed by the UTF-8 encoding byte
+        assert_eq!(&expected[..11], b"TPE1\x00\x00\x00\x12\x00\x00\x03");
+        assert_eq!(&expected[expected.len() - 15..], b"\x03STYLE\0cheerful");
+    }
+
+    #[test]
+    fn ogg_crc_matches_the_reference() {
+        // CRC-32 with polynomial 0x04c11db7, no reflection, zero initial value and no final xor
+        assert_eq!(ogg_crc32(b"123456789"), 0x89a1_897f);
+    }
+
+    fn ogg_stream(comment: &[u8], audio_packets: &[&[u8]]) -> Vec<u8> {
+        let mut head = b"OpusHead\x01\x01\x38\x01".to_vec();
+        head.extend(24000u32.to_le_bytes());
+        head.extend([0, 0, 0]);
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(comment);
+        let mut output = Vec::new();
+        let mut sequence = 0;
+        let mut page = |packets: &[&[u8]], granule_position: u64, header_type: u8| {
+            let mut segments = Vec::new();
+            for packet in packets {
+                segments.extend(std::iter::repeat(255).take(packet.len() / 255));
+                segments.push((packet.len() % 255) as u8);
+            }
+            OggPage {
+                header_type,
+                granule_position,
+                serial: 0x1234,
+                sequence,
+                segments: &segments,
+                body: &packets.concat(),
+            }
+            .write(&mut output);
+            sequence += 1;
+        };
+        page(&[&head], 0, 2);
+        page(&[&tags], 0, 0);
+        page(audio_packets, 960 * audio_packets.len() as u64, 4);
+        output
+    }
+
+    /// Check the checksums stored in every page of `stream`
+    fn assert_valid_crcs(stream: &[u8]) {
+        let mut position = 0;
+        for page in parse_ogg_pages(stream).unwrap() {
+            let len = 27 + page.segments.len() + page.body.len();
+            let mut bytes = stream[position..position + len].to_vec();
+            let crc = read_u32_le(&bytes[22..26]);
+            bytes[22..26].fill(0);
+            assert_eq!(ogg_crc32(&bytes), crc);
+            position += len;
+        }
+    }
+
+    #[test]
+    fn ogg_comment_header_is_rewritten() {
+        let comment = vorbis_comment(
+            b"Azure",
+            &[b"TITLE=hello".to_vec(), b"artist=someone".to_vec()],
+        );
+        let audio = ogg_stream(&comment, &[&[1; 10], &[2; 20]]);
+        assert_valid_crcs(&audio);
+        let tagged = embed_tags(AudioContainer::Ogg, &audio, &tags()).unwrap();
+        assert_valid_crcs(&tagged);
+        let pages = parse_ogg_pages(&tagged).unwrap();
+        assert_eq!(pages.len(), 3);
+        let (vendor, comments) =
+            parse_vorbis_comment(pages[1].body.strip_prefix(b"OpusTags").unwrap()).unwrap();
+        assert_eq!(vendor, b"Azure");
+        assert_eq!(
+            comments,
+            [
+                &b"TITLE=hello"[..],
+                b"ARTIST=en-US-JennyNeural",
+                b"DATE=2023-05-01T12:00:00Z",
+                format!("ENCODER={ENCODER}").as_bytes(),
+                b"STYLE=cheerful",
+            ]
+        );
+        assert_eq!(pages[2].body, [&[1; 10][..], &[2; 20]].concat());
+        assert_eq!(pages[2].sequence, 2);
+        assert_eq!(pages[2].granule_position, 1920);
+    }
+
+    #[test]
+    fn long_ogg_comment_headers_span_several_pages() {
+        let audio = ogg_stream(&vorbis_comment(b"Azure", &[]), &[&[1; 10]]);
+        let tags = AudioTags::builder()
+            .field("LYRICS", "a".repeat(70000))
+            .build();
+        let tagged = embed_tags(AudioContainer::Ogg, &audio, &tags).unwrap();
+        assert_valid_crcs(&tagged);
+        let pages = parse_ogg_pages(&tagged).unwrap();
+        assert_eq!(pages.len(), 4);
+        // The comment packet continues on the second comment page
+        assert_eq!(pages[1].segments, [255; 255]);
+        assert_eq!(pages[1].granule_position, u64::MAX);
+        assert_eq!(pages[2].header_type, 1);
+        assert_eq!(pages[2].granule_position, 0);
+        assert_eq!([pages[1].body, pages[2].body].concat().len(), {
+            let comment = vorbis_comment(b"Azure", &merge_vorbis_comments(vec![], &tags));
+            8 + comment.len()
+        });
+        assert_eq!(pages[3].sequence, 3);
+        assert_eq!(pages[3].body, [1; 10]);
+    }
+
+    #[test]
+    fn only_ogg_opus_can_be_tagged() {
+        let mut audio = ogg_stream(&vorbis_comment(b"Azure", &[]), &[&[1; 10]]);
+        audio[28..36].copy_from_slice(b"NotOpus!");
+        let error = embed_tags(AudioContainer::Ogg, &audio, &tags()).unwrap_err();
+        assert_eq!(error.kind, TagErrorKind::InvalidData);
+    }
+
+    fn wav(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        for (id, body) in chunks {
+            wav.extend_from_slice(*id);
+            wav.extend((body.len() as u32).to_le_bytes());
+            wav.extend_from_slice(body);
+            if body.len() % 2 == 1 {
+                wav.push(0);
+            }
+        }
+        let len = (wav.len() - 8) as u32;
+        wav[4..8].copy_from_slice(&len.to_le_bytes());
+        wav
+    }
+
+    /// Chunk ids and bodies of a RIFF/WAVE file
+    fn riff_chunks(wav: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let mut chunks = Vec::new();
+        let mut rest = &wav[12..];
+        while rest.len() >= 8 {
+            let size = read_u32_le(&rest[4..8]) as usize;
+            chunks.push((rest[0..4].try_into().unwrap(), &rest[8..8 + size]));
+            rest = &rest[(8 + size + (size & 1)).min(rest.len())..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn riff_gets_an_info_list_before_the_data() {
+        let fmt = [0u8; 16];
+        let old_info = b"INFOISFT\x04\0\0\0old\0";
+        let audio = wav(&[
+            (b"fmt ", &fmt),
+            (b"LIST", old_info),
+            (b"fact", &[1, 0, 0, 0]),
+            (b"data", &[1, 2, 3, 4]),
+        ]);
+        let tagged = embed_tags(AudioContainer::Wav, &audio, &tags()).unwrap();
+        assert_eq!(read_u32_le(&tagged[4..8]) as usize, tagged.len() - 8);
+        let chunks = riff_chunks(&tagged);
+        let ids: Vec<_> = chunks.iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, [b"fmt ", b"fact", b"LIST", b"data"]);
+        assert_eq!(chunks[3].1, [1, 2, 3, 4]);
+        let info = chunks[2].1;
+        assert_eq!(&info[..4], b"INFO");
+        let mut expected = b"INFO".to_vec();
+        for (id, value) in [
+            (b"IART", "en-US-JennyNeural"),
+            (b"ICRD", "2023-05-01T12:00:00Z"),
+            (b"ISFT", ENCODER),
+            (b"ICMT", "STYLE=cheerful"),
+        ] {
+            let len = value.len() + 1;
+            expected.extend_from_slice(id);
+            expected.extend((len as u32).to_le_bytes());
+            expected.extend_from_slice(value.as_bytes());
+            expected.resize(expected.len() + 1 + len % 2, 0);
+        }
+        assert_eq!(info, expected);
+    }
+
+    #[test]
+    fn riff_without_data_chunk_is_invalid() {
+        let audio = wav(&[(b"fmt ", &[0; 16])]);
+        let error = embed_tags(AudioContainer::Wav, &audio, &tags()).unwrap_err();
+        assert_eq!(error.kind, TagErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "transcode")]
+    #[test]
+    fn flac_gets_vorbis_comments_as_the_last_metadata_block() {
+        use crate::{transcode, AudioFormat, Encoding, TranscodeOptions};
+
+        let pcm: Vec<u8> = (0..1600i16).flat_map(|s| (s * 10).to_le_bytes()).collect();
+        let options = TranscodeOptions::builder()
+            .encoding(Encoding::Flac)
+            .tags(tags())
+            .build();
+        let flac = transcode(AudioFormat::Raw16Khz16BitMonoPcm, &pcm, &options).unwrap();
+        // Tagging again replaces the comments instead of adding another block
+        let flac = embed_in_flac(&flac, &tags()).unwrap();
+        let mut reader = claxon::FlacReader::new(flac.as_slice()).unwrap();
+        assert_eq!(reader.vendor(), Some(ENCODER));
+        let comments: Vec<_> = reader.tags().collect();
+        assert_eq!(
+            comments,
+            [
+                ("ARTIST", "en-US-JennyNeural"),
+                ("DATE", "2023-05-01T12:00:00Z"),
+                ("ENCODER", ENCODER),
+                ("STYLE", "cheerful"),
+            ]
+        );
+        let samples: Vec<i32> = reader.samples().map(Result::unwrap).collect();
+        assert_eq!(samples.len(), 1600);
+        assert_eq!(samples[1], 10);
+    }
+}
@@ -0,0 +1,873 @@
+//! Local transcoding of PCM audio.
+//!
+//! Azure only provides mono audio at a fixed set of sample rates.
+//! This module converts the PCM output of Azure to other sample rates, channel counts and bit depths,
+//! and encodes it to WAV or FLAC, optionally with [`AudioTags`].
+
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    io::Cursor,
+    iter,
+    time::Duration,
+};
+
+#[cfg(feature = "binary")]
+use clap::ValueEnum;
+use hound::{Error as WavError, SampleFormat, WavSpec, WavWriter};
+use rubato::{
+    ResampleError, Resampler, ResamplerConstructionError, SincFixedIn, SincInterpolationParameters,
+    SincInterpolationType, WindowFunction,
+};
+use strum::{EnumString, IntoStaticStr};
+
+use super::{
+    pcm,
+    tag::{self, AudioTags, TagError},
+    AudioContainer, AudioFormat,
+};
+
+mod flac;
+mod loudness;
+
+/// Number of frames fed to the resampler at once
+const RESAMPLER_CHUNK_SIZE: usize = 1024;
+/// Peak limit in dBFS used when normalizing loudness without an explicit peak limit
+pub const DEFAULT_PEAK_LIMIT: f64 = -1.0;
+/// Release time of the peak limiter
+const LIMITER_RELEASE_SECONDS: f64 = 0.05;
+/// The limiter starts reducing the gain this long before a peak
+const LIMITER_ATTACK_SECONDS: f64 = 0.005;
+
+fn db_to_amplitude(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+/// The encoding of transcoded audio
+#[cfg_attr(feature = "binary", derive(ValueEnum))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, IntoStaticStr, EnumString)]
+#[strum(serialize_all = "lowercase")]
+#[non_exhaustive]
+pub enum Encoding {
+    /// RIFF/WAVE with integer samples
+    #[default]
+    Wav,
+    /// Free Lossless Audio Codec
+    Flac,
+    /// Raw little-endian integer samples without any header
+    Pcm,
+}
+
+impl Encoding {
+    /// The usual file extension of this encoding
+    pub fn file_extension(self) -> &'static str {
+        self.into()
+    }
+}
+
+/// Options for transcoding audio
+#[derive(Debug, Clone, Default)]
+pub struct TranscodeOptions {
+    /// Target sample rate in Hz. The sample rate is kept if it is `None`.
+    pub(crate) sample_rate: Option<u32>,
+    /// Target number of channels. The channels are kept if it is `None`.
+    pub(crate) channels: Option<u16>,
+    /// Target bits per sample. 16 bits are used if it is `None`.
+    pub(crate) bits_per_sample: Option<u16>,
+    /// Target encoding
+    pub(crate) encoding: Encoding,
+    /// Normalize the integrated loudness to this target in LUFS
+    pub(crate) loudness: Option<f64>,
+    /// Limit the sample peaks to this level in dBFS.
+    /// [`DEFAULT_PEAK_LIMIT`] is used when normalizing loudness if it is `None`.
+    pub(crate) peak_limit: Option<f64>,
+    /// Trim leading and trailing audio quieter than this threshold in dBFS
+    pub(crate) trim_silence: Option<f64>,
+    /// Silence added before the audio
+    pub(crate) leading_padding: Duration,
+    /// Silence added after the audio
+    pub(crate) trailing_padding: Duration,
+    /// Tags embedded into WAV and FLAC files
+    pub(crate) tags: Option<AudioTags>,
+}
+
+impl TranscodeOptions {
+    /// Target sample rate in Hz. The sample rate is kept if it is `None`.
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.sample_rate
+    }
+
+    /// Target sample rate in Hz. The sample rate is kept if it is `None`.
+    pub fn sample_rate_mut(&mut self) -> &mut Option<u32> {
+        &mut self.sample_rate
+    }
+
+    /// Target number of channels. The channels are kept if it is `None`.
+    pub fn channels(&self) -> Option<u16> {
+        self.channels
+    }
+
+    /// Target number of channels. The channels are kept if it is `None`.
+    pub fn channels_mut(&mut self) -> &mut Option<u16> {
+        &mut self.channels
+    }
+
+    /// Target bits per sample. 16 bits are used if it is `None`.
+    pub fn bits_per_sample(&self) -> Option<u16> {
+        self.bits_per_sample
+    }
+
+    /// Target bits per sample. 16 bits are used if it is `None`.
+    pub fn bits_per_sample_mut(&mut self) -> &mut Option<u16> {
+        &mut self.bits_per_sample
+    }
+
+    /// Target encoding
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Target encoding
+    pub fn encoding_mut(&mut self) -> &mut Encoding {
+        &mut self.encoding
+    }
+
+    /// Normalize the integrated loudness to this target in LUFS
+    pub fn loudness(&self) -> Option<f64> {
+        self.loudness
+    }
+
+    /// Normalize the integrated loudness to this target in LUFS
+    pub fn loudness_mut(&mut self) -> &mut Option<f64> {
+        &mut self.loudness
+    }
+
+    /// Limit the sample peaks to this level in dBFS
+    pub fn peak_limit(&self) -> Option<f64> {
+        self.peak_limit
+    }
+
+    /// Limit the sample peaks to this level in dBFS
+    pub fn peak_limit_mut(&mut self) -> &mut Option<f64> {
+        &mut self.peak_limit
+    }
+
+    /// Trim leading and trailing audio quieter than this threshold in dBFS
+    pub fn trim_silence(&self) -> Option<f64> {
+        self.trim_silence
+    }
+
+    /// Trim leading and trailing audio quieter than this threshold in dBFS
+    pub fn trim_silence_mut(&mut self) -> &mut Option<f64> {
+        &mut self.trim_silence
+    }
+
+    /// Silence added before the audio
+    pub fn leading_padding(&self) -> Duration {
+        self.leading_padding
+    }
+
+    /// Silence added before the audio
+    pub fn leading_padding_mut(&mut self) -> &mut Duration {
+        &mut self.leading_padding
+    }
+
+    /// Silence added after the audio
+    pub fn trailing_padding(&self) -> Duration {
+        self.trailing_padding
+    }
+
+    /// Silence added after the audio
+    pub fn trailing_padding_mut(&mut self) -> &mut Duration {
+        &mut self.trailing_padding
+    }
+
+    /// Tags embedded into WAV and FLAC files
+    pub fn tags(&self) -> Option<&AudioTags> {
+        self.tags.as_ref()
+    }
+
+    /// Tags embedded into WAV and FLAC files
+    pub fn tags_mut(&mut self) -> &mut Option<AudioTags> {
+        &mut self.tags
+    }
+
+    /// Create a builder for [`TranscodeOptions`]
+    pub fn builder() -> TranscodeOptionsBuilder {
+        TranscodeOptionsBuilder::new()
+    }
+}
+
+/// Builder for [`TranscodeOptions`]
+#[derive(Debug, Default)]
+pub struct TranscodeOptionsBuilder {
+    sample_rate: Option<u32>,
+    channels: Option<u16>,
+    bits_per_sample: Option<u16>,
+    encoding: Encoding,
+    loudness: Option<f64>,
+    peak_limit: Option<f64>,
+    trim_silence: Option<f64>,
+    leading_padding: Duration,
+    trailing_padding: Duration,
+    tags: Option<AudioTags>,
+}
+
+impl TranscodeOptionsBuilder {
+    /// Create a new builder
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Target sample rate in Hz
+    pub fn sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    /// Target sample rate in Hz. The sample rate is kept if it is `None`.
+    pub fn optional_sample_rate(mut self, sample_rate: Option<u32>) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    /// Target number of channels
+    pub fn channels(mut self, channels: u16) -> Self {
+        self.channels = Some(channels);
+        self
+    }
+
+    /// Target number of channels. The channels are kept if it is `None`.
+    pub fn optional_channels(mut self, channels: Option<u16>) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    /// Target bits per sample
+    pub fn bits_per_sample(mut self, bits_per_sample: u16) -> Self {
+        self.bits_per_sample = Some(bits_per_sample);
+        self
+    }
+
+    /// Target bits per sample. 16 bits are used if it is `None`.
+    pub fn optional_bits_per_sample(mut self, bits_per_sample: Option<u16>) -> Self {
+        self.bits_per_sample = bits_per_sample;
+        self
+    }
+
+    /// Target encoding
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Normalize the integrated loudness to this target in LUFS
+    pub fn loudness(mut self, loudness: f64) -> Self {
+        self.loudness = Some(loudness);
+        self
+    }
+
+    /// Normalize the integrated loudness to this target in LUFS. The loudness is kept if it is `None`.
+    pub fn optional_loudness(mut self, loudness: Option<f64>) -> Self {
+        self.loudness = loudness;
+        self
+    }
+
+    /// Limit the sample peaks to this level in dBFS
+    pub fn peak_limit(mut self, peak_limit: f64) -> Self {
+        self.peak_limit = Some(peak_limit);
+        self
+    }
+
+    /// Limit the sample peaks to this level in dBFS.
+    /// [`DEFAULT_PEAK_LIMIT`] is used when normalizing loudness if it is `None`.
+    pub fn optional_peak_limit(mut self, peak_limit: Option<f64>) -> Self {
+        self.peak_limit = peak_limit;
+        self
+    }
+
+    /// Trim leading and trailing audio quieter than this threshold in dBFS
+    pub fn trim_silence(mut self, threshold: f64) -> Self {
+        self.trim_silence = Some(threshold);
+        self
+    }
+
+    /// Trim leading and trailing audio quieter than this threshold in dBFS.
+    /// Nothing is trimmed if it is `None`.
+    pub fn optional_trim_silence(mut self, threshold: Option<f64>) -> Self {
+        self.trim_silence = threshold;
+        self
+    }
+
+    /// Silence added before and after the audio
+    pub fn padding(mut self, leading: Duration, trailing: Duration) -> Self {
+        self.leading_padding = leading;
+        self.trailing_padding = trailing;
+        self
+    }
+
+    /// Tags embedded into WAV and FLAC files
+    pub fn tags(mut self, tags: AudioTags) -> Self {
+        self.tags = Some(tags);
+        self
+    }
+
+    /// Tags embedded into WAV and FLAC files. Nothing is embedded if it is `None`.
+    pub fn optional_tags(mut self, tags: Option<AudioTags>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Build [`TranscodeOptions`]
+    pub fn build(self) -> TranscodeOptions {
+        TranscodeOptions {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            bits_per_sample: self.bits_per_sample,
+            encoding: self.encoding,
+            loudness: self.loudness,
+            peak_limit: self.peak_limit,
+            trim_silence: self.trim_silence,
+            leading_padding: self.leading_padding,
+            trailing_padding: self.trailing_padding,
+            tags: self.tags,
+        }
+    }
+}
+
+/// Decoded audio with interleaved samples in the range `[-1.0, 1.0]`
+#[derive(Debug, Clone)]
+pub struct PcmAudio {
+    sample_rate: u32,
+    channels: u16,
+    samples: Vec<f32>,
+}
+
+impl PcmAudio {
+    /// Decode PCM, A-law or μ-law audio in `format`.
+    pub fn decode(format: AudioFormat, audio: &[u8]) -> Result<Self, TranscodeError> {
+        let wav = pcm::to_linear_pcm_wav(format, audio).ok_or_else(|| TranscodeError {
+            kind: TranscodeErrorKind::UnsupportedFormat(format),
+            source: None,
+        })?;
+        let samples = wav[pcm::WAV_HEADER_LEN..]
+            .chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]) as f32 / 32768.0)
+            .collect();
+        Ok(Self {
+            sample_rate: format.sample_rate(),
+            channels: format.channels(),
+            samples,
+        })
+    }
+
+    /// Sample rate in Hz
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Number of channels
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Interleaved samples in the range `[-1.0, 1.0]`
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    /// Number of samples per channel
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    /// Resample the audio to `sample_rate` Hz.
+    pub fn resample(self, sample_rate: u32) -> Result<Self, TranscodeError> {
+        if sample_rate == 0 {
+            return Err(TranscodeError {
+                kind: TranscodeErrorKind::InvalidOptions,
+                source: Some(anyhow::anyhow!("sample rate must be positive")),
+            });
+        }
+        if sample_rate == self.sample_rate {
+            return Ok(self);
+        }
+        let channels = self.channels as usize;
+        let frames = self.frames();
+        let ratio = sample_rate as f64 / self.sample_rate as f64;
+        let mut resampler = SincFixedIn::<f32>::new(
+            ratio,
+            1.0,
+            SincInterpolationParameters {
+                sinc_len: 256,
+                f_cutoff: 0.95,
+                interpolation: SincInterpolationType::Linear,
+                oversampling_factor: 256,
+                window: WindowFunction::BlackmanHarris2,
+            },
+            RESAMPLER_CHUNK_SIZE,
+            channels,
+        )?;
+        let input: Vec<Vec<f32>> = (0..channels)
+            .map(|c| {
+                self.samples
+                    .iter()
+                    .skip(c)
+                    .step_by(channels)
+                    .copied()
+                    .collect()
+            })
+            .collect();
+        let expected_frames = (frames as f64 * ratio).round() as usize;
+        let mut output = vec![Vec::with_capacity(expected_frames); channels];
+        let mut position = 0;
+        // The sinc filter needs samples after the current one, so the resampler has to be
+        // fed with silence at the end until all the audio has come out.
+        while output[0].len() < expected_frames {
+            let chunk = if position + RESAMPLER_CHUNK_SIZE <= frames {
+                let chunk: Vec<&[f32]> = input
+                    .iter()
+                    .map(|c| &c[position..position + RESAMPLER_CHUNK_SIZE])
+                    .collect();
+                resampler.process(&chunk, None)?
+            } else if position < frames {
+                let chunk: Vec<&[f32]> = input.iter().map(|c| &c[position..]).collect();
+                resampler.process_partial(Some(&chunk), None)?
+            } else {
+                resampler.process_partial::<&[f32]>(None, None)?
+            };
+            position += RESAMPLER_CHUNK_SIZE;
+            for (output, chunk) in output.iter_mut().zip(chunk) {
+                output.extend(chunk);
+            }
+        }
+        let mut samples = Vec::with_capacity(expected_frames * channels);
+        for frame in 0..expected_frames {
+            samples.extend(output.iter().map(|c| c[frame]));
+        }
+        Ok(Self {
+            sample_rate,
+            channels: self.channels,
+            samples,
+        })
+    }
+
+    /// Change the number of channels.
+    ///
+    /// Mono audio is up-mixed by duplicating it to every channel,
+    /// and audio is down-mixed to mono by averaging all channels.
+    pub fn remix(self, channels: u16) -> Result<Self, TranscodeError> {
+        if channels == self.channels {
+            return Ok(self);
+        }
+        let samples = match (self.channels, channels) {
+            (_, 0) => None,
+            (1, _) => Some(
+                self.samples
+                    .iter()
+                    .flat_map(|&s| iter::repeat(s).take(channels as usize))
+                    .collect(),
+            ),
+            (_, 1) => Some(
+                self.samples
+                    .chunks_exact(self.channels as usize)
+                    .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+                    .collect(),
+            ),
+            _ => None,
+        };
+        let samples = samples.ok_or_else(|| TranscodeError {
+            kind: TranscodeErrorKind::InvalidOptions,
+            source: Some(anyhow::anyhow!(
+                "can not convert {} channels to {} channels",
+                self.channels,
+                channels
+            )),
+        })?;
+        Ok(Self {
+            sample_rate: self.sample_rate,
+            channels,
+            samples,
+        })
+    }
+
+    /// Integrated loudness in LUFS, measured according to EBU R128.
+    ///
+    /// Returns `None` if the audio is silent.
+    pub fn loudness(&self) -> Option<f64> {
+        loudness::integrated_loudness(&self.samples, self.channels as usize, self.sample_rate)
+    }
+
+    /// Sample peak in dBFS
+    pub fn peak(&self) -> f64 {
+        let peak = self.samples.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+        20.0 * (peak as f64).log10()
+    }
+
+    /// Apply a gain in dB.
+    pub fn gain(mut self, db: f64) -> Self {
+        let factor = db_to_amplitude(db) as f32;
+        self.samples.iter_mut().for_each(|s| *s *= factor);
+        self
+    }
+
+    /// Change the gain so that the integrated loudness is `target` LUFS.
+    ///
+    /// Silent audio is left unchanged. Note that the peaks may exceed full scale afterwards,
+    /// so a [limiter](PcmAudio::limit_peaks) should usually be applied.
+    pub fn normalize_loudness(self, target: f64) -> Self {
+        match self.loudness() {
+            Some(loudness) => self.gain(target - loudness),
+            None => self,
+        }
+    }
+
+    /// Limit the sample peaks to `ceiling` dBFS.
+    ///
+    /// The gain is reduced smoothly around peaks instead of clipping them.
+    pub fn limit_peaks(mut self, ceiling: f64) -> Self {
+        let ceiling = db_to_amplitude(ceiling);
+        let channels = self.channels as usize;
+        let rate = self.sample_rate as f64;
+        // The gain needed by each frame to stay below the ceiling.
+        // Gains are computed in double precision, otherwise rounding would stop their recovery
+        // slightly below unity and attenuate the whole audio.
+        let mut gains: Vec<f64> = self
+            .samples
+            .chunks_exact(channels)
+            .map(|frame| {
+                let peak = frame.iter().fold(0f32, |peak, s| peak.max(s.abs())) as f64;
+                if peak > ceiling {
+                    ceiling / peak
+                } else {
+                    1.0
+                }
+            })
+            .collect();
+        // Recover from gain reduction after peaks, and start reducing the gain before them.
+        // The gain never exceeds the needed gain, so the ceiling is never exceeded.
+        let release = (-1.0 / (LIMITER_RELEASE_SECONDS * rate)).exp();
+        for i in 1..gains.len() {
+            gains[i] = gains[i].min(1.0 - (1.0 - gains[i - 1]) * release);
+        }
+        let attack = (-1.0 / (LIMITER_ATTACK_SECONDS * rate)).exp();
+        for i in (0..gains.len().saturating_sub(1)).rev() {
+            gains[i] = gains[i].min(1.0 - (1.0 - gains[i + 1]) * attack);
+        }
+        for (frame, gain) in self.samples.chunks_exact_mut(channels).zip(gains) {
+            frame.iter_mut().for_each(|s| *s *= gain as f32);
+        }
+        self
+    }
+
+    /// Remove leading and trailing audio whose samples are all quieter than `threshold` dBFS.
+    pub fn trim_silence(mut self, threshold: f64) -> Self {
+        let threshold = db_to_amplitude(threshold) as f32;
+        let channels = self.channels as usize;
+        let is_loud = |frame: &[f32]| frame.iter().any(|s| s.abs() > threshold);
+        let start = self.samples.chunks_exact(channels).position(is_loud);
+        let end = self.samples.chunks_exact(channels).rposition(is_loud);
+        self.samples = match (start, end) {
+            (Some(start), Some(end)) => {
+                self.samples[start * channels..(end + 1) * channels].to_vec()
+            }
+            _ => Vec::new(),
+        };
+        self
+    }
+
+    /// Add silence before and after the audio.
+    pub fn pad(mut self, leading: Duration, trailing: Duration) -> Self {
+        let frames = |duration: Duration| {
+            (duration.as_secs_f64() * self.sample_rate as f64).round() as usize
+                * self.channels as usize
+        };
+        let (leading, trailing) = (frames(leading), frames(trailing));
+        let mut samples = Vec::with_capacity(leading + self.samples.len() + trailing);
+        samples.resize(leading, 0.0);
+        samples.extend_from_slice(&self.samples);
+        samples.resize(samples.len() + trailing, 0.0);
+        self.samples = samples;
+        self
+    }
+
+    /// Quantize the samples to signed integers of `bits_per_sample` bits
+    fn quantize(&self, bits_per_sample: u16) -> Vec<i32> {
+        let max = ((1i64 << (bits_per_sample - 1)) - 1) as f64;
+        self.samples
+            .iter()
+            .map(|&s| (s as f64 * max).round().clamp(-max - 1.0, max) as i32)
+            .collect()
+    }
+
+    /// Encode the audio with `bits_per_sample` bits per sample.
+    pub fn encode(
+        &self,
+        encoding: Encoding,
+        bits_per_sample: u16,
+    ) -> Result<Vec<u8>, TranscodeError> {
+        let supported = match encoding {
+            Encoding::Wav | Encoding::Pcm => matches!(bits_per_sample, 8 | 16 | 24 | 32),
+            Encoding::Flac => matches!(bits_per_sample, 8 | 16 | 24),
+        };
+        if encoding == Encoding::Flac && !(1..=flac::MAX_SAMPLE_RATE).contains(&self.sample_rate) {
+            return Err(TranscodeError {
+                kind: TranscodeErrorKind::InvalidOptions,
+                source: Some(anyhow::anyhow!(
+                    "FLAC does not support a sample rate of {} Hz",
+                    self.sample_rate
+                )),
+            });
+        }
+        if !supported || self.channels == 0 || (encoding == Encoding::Flac && self.channels > 8) {
+            return Err(TranscodeError {
+                kind: TranscodeErrorKind::InvalidOptions,
+                source: Some(anyhow::anyhow!(
+                    "{} does not support {} channels with {} bits per sample",
+                    Into::<&str>::into(encoding),
+                    self.channels,
+                    bits_per_sample
+                )),
+            });
+        }
+        let samples = self.quantize(bits_per_sample);
+        match encoding {
+            Encoding::Wav => {
+                let mut cursor = Cursor::new(Vec::new());
+                let mut writer = WavWriter::new(
+                    &mut cursor,
+                    WavSpec {
+                        channels: self.channels,
+                        sample_rate: self.sample_rate,
+                        bits_per_sample,
+                        sample_format: SampleFormat::Int,
+                    },
+                )?;
+                for sample in samples {
+                    writer.write_sample(sample)?;
+                }
+                writer.finalize()?;
+                Ok(cursor.into_inner())
+            }
+            Encoding::Pcm => Ok(match bits_per_sample {
+                // 8-bit PCM is unsigned, like in WAV files
+                8 => samples.iter().map(|&s| (s + 128) as u8).collect(),
+                _ => samples
+                    .iter()
+                    .flat_map(|s| s.to_le_bytes()[..bits_per_sample as usize / 8].to_vec())
+                    .collect(),
+            }),
+            Encoding::Flac => Ok(flac::encode(
+                &samples,
+                self.sample_rate,
+                self.channels,
+                bits_per_sample,
+            )),
+        }
+    }
+}
+
+/// Transcode PCM, A-law or μ-law audio in `format` according to `options`.
+///
+/// Silence is trimmed, then the loudness is normalized and the peaks are limited,
+/// then padding is added, before the audio is resampled, remixed, encoded and tagged.
+pub fn transcode(
+    format: AudioFormat,
+    audio: &[u8],
+    options: &TranscodeOptions,
+) -> Result<Vec<u8>, TranscodeError> {
+    let mut decoded = PcmAudio::decode(format, audio)?;
+    if let Some(threshold) = options.trim_silence {
+        decoded = decoded.trim_silence(threshold);
+    }
+    if let Some(loudness) = options.loudness {
+        decoded = decoded.normalize_loudness(loudness);
+    }
+    if let Some(ceiling) = options
+        .peak_limit
+        .or(options.loudness.map(|_| DEFAULT_PEAK_LIMIT))
+    {
+        decoded = decoded.limit_peaks(ceiling);
+    }
+    decoded = decoded.pad(options.leading_padding, options.trailing_padding);
+    if let Some(sample_rate) = options.sample_rate {
+        decoded = decoded.resample(sample_rate)?;
+    }
+    if let Some(channels) = options.channels {
+        decoded = decoded.remix(channels)?;
+    }
+    let encoded = decoded.encode(options.encoding, options.bits_per_sample.unwrap_or(16))?;
+    Ok(match (&options.tags, options.encoding) {
+        (None, _) => encoded,
+        (Some(tags), Encoding::Wav) => tag::embed_tags(AudioContainer::Wav, &encoded, tags)?,
+        (Some(tags), Encoding::Flac) => tag::embed_in_flac(&encoded, tags)?,
+        (Some(_), Encoding::Pcm) => {
+            return Err(TranscodeError {
+                kind: TranscodeErrorKind::InvalidOptions,
+                source: Some(anyhow::anyhow!("raw PCM audio can not be tagged")),
+            })
+        }
+    })
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+/// An error that can occur when transcoding audio
+pub struct TranscodeError {
+    pub kind: TranscodeErrorKind,
+    pub(crate) source: Option<anyhow::Error>,
+}
+
+impl Display for TranscodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "transcode error: ")?;
+        match &self.kind {
+            TranscodeErrorKind::UnsupportedFormat(format) => write!(
+                f,
+                "audio format {} can not be transcoded, only PCM, A-law and μ-law are supported",
+                Into::<&str>::into(format)
+            ),
+            TranscodeErrorKind::InvalidOptions => write!(f, "invalid transcode options"),
+            TranscodeErrorKind::Resample => write!(f, "failed to resample the audio"),
+            TranscodeErrorKind::Encode => write!(f, "failed to encode the audio"),
+        }
+    }
+}
+
+impl Error for TranscodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|e| e.as_ref() as _)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
+pub enum TranscodeErrorKind {
+    /// The audio format can not be decoded for transcoding
+    UnsupportedFormat(AudioFormat),
+    InvalidOptions,
+    Resample,
+    Encode,
+}
+
+macro_rules! impl_from_for_transcode_error {
+    ($error_type:ident, $error_kind:ident) => {
+        impl From<$error_type> for TranscodeError {
+            fn from(e: $error_type) -> Self {
+                Self {
+                    kind: TranscodeErrorKind::$error_kind,
+                    source: Some(e.into()),
+                }
+            }
+        }
+    };
+}
+
+impl_from_for_transcode_error!(ResampleError, Resample);
+impl_from_for_transcode_error!(ResamplerConstructionError, Resample);
+impl_from_for_transcode_error!(WavError, Encode);
+impl_from_for_transcode_error!(TagError, Encode);
+
+#[cfg(feature = "python")]
+impl From<TranscodeError> for pyo3::PyErr {
+    fn from(value: TranscodeError) -> Self {
+        pyo3::exceptions::PyOSError::new_err(format!("{:?}", color_eyre::Report::from(value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    #[test]
+    fn flac_rejects_sample_rates_it_can_not_code() {
+        let audio = PcmAudio {
+            sample_rate: flac::MAX_SAMPLE_RATE + 10,
+            channels: 1,
+            samples: vec![0.0; 16],
+        };
+        let error = audio.encode(Encoding::Flac, 16).unwrap_err();
+        assert_eq!(error.kind, TranscodeErrorKind::InvalidOptions);
+        let audio = PcmAudio {
+            sample_rate: flac::MAX_SAMPLE_RATE,
+            ..audio
+        };
+        assert!(audio.encode(Encoding::Flac, 16).is_ok());
+    }
+
+    fn sine(amplitude: f32, seconds: f64) -> PcmAudio {
+        let sample_rate = 24000;
+        let frames = (seconds * sample_rate as f64) as usize;
+        PcmAudio {
+            sample_rate,
+            channels: 1,
+            samples: (0..frames)
+                .map(|i| amplitude * (i as f32 * 997.0 / sample_rate as f32 * 2.0 * PI).sin())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn normalizing_reaches_the_target_loudness() {
+        let normalized = sine(0.05, 3.0).normalize_loudness(-16.0);
+        let loudness = normalized.loudness().unwrap();
+        assert!((loudness + 16.0).abs() < 0.01, "{loudness}");
+    }
+
+    #[test]
+    fn normalizing_silence_does_nothing() {
+        let silence = PcmAudio {
+            sample_rate: 24000,
+            channels: 1,
+            samples: vec![0.0; 24000],
+        };
+        assert_eq!(silence.normalize_loudness(-16.0).samples, vec![0.0; 24000]);
+    }
+
+    #[test]
+    fn limited_peaks_stay_below_the_ceiling() {
+        let mut audio = sine(0.5, 2.0);
+        // A single loud click in the middle
+        audio.samples[24000] = 1.0;
+        let limited = audio.clone().limit_peaks(-1.0);
+        assert!(limited.peak() <= -1.0 + 1e-4, "{}", limited.peak());
+        // The gain is reduced shortly before the click, and recovers after it
+        assert_eq!(limited.samples[..20000], audio.samples[..20000]);
+        assert!(limited.samples[36000..]
+            .iter()
+            .zip(&audio.samples[36000..])
+            .all(|(limited, original)| (limited - original).abs() < 1e-3));
+    }
+
+    #[test]
+    fn audio_below_the_ceiling_is_not_limited() {
+        let audio = sine(0.5, 1.0);
+        assert_eq!(audio.clone().limit_peaks(-1.0).samples, audio.samples);
+    }
+
+    #[test]
+    fn silence_is_trimmed() {
+        let audio = PcmAudio {
+            sample_rate: 24000,
+            channels: 2,
+            samples: vec![0.0, 0.001, 0.0, 0.5, -0.5, 0.0, 0.002, 0.0, 0.0, 0.0],
+        };
+        let trimmed = audio.clone().trim_silence(-50.0);
+        assert_eq!(trimmed.samples, [0.0, 0.5, -0.5, 0.0]);
+        let trimmed = audio.trim_silence(0.0);
+        assert!(trimmed.samples.is_empty());
+    }
+
+    #[test]
+    fn mono_is_duplicated_to_every_channel() {
+        let audio = PcmAudio {
+            sample_rate: 16000,
+            channels: 1,
+            samples: vec![0.25, -0.5],
+        };
+        let remixed = audio.remix(3).unwrap();
+        assert_eq!(remixed.samples, [0.25, 0.25, 0.25, -0.5, -0.5, -0.5]);
+    }
+}
@@ -0,0 +1,359 @@
+//! A minimal FLAC encoder.
+//!
+//! Every channel is coded independently with the best fixed linear predictor
+//! and a single Rice partition, which is simple but compresses speech reasonably well.
+
+/// Number of inter-channel samples in a frame
+const BLOCK_SIZE: usize = 4096;
+/// Highest sample rate that can be coded in a frame header
+pub(super) const MAX_SAMPLE_RATE: u32 = 655350;
+/// Largest Rice parameter that can be coded with the 4-bit parameter field
+const MAX_RICE_PARAMETER: u32 = 14;
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            buffer: 0,
+            bits: 0,
+        }
+    }
+
+    fn write(&mut self, value: u64, bits: u32) {
+        for i in (0..bits).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        self.buffer = (self.buffer << 1) | bit as u64;
+        self.bits += 1;
+        if self.bits == 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer = 0;
+            self.bits = 0;
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64 & ((1 << bits) - 1), bits);
+    }
+
+    fn write_unary(&mut self, zeros: u64) {
+        for _ in 0..zeros {
+            self.write_bit(false);
+        }
+        self.write_bit(true);
+    }
+
+    /// Pad with zero bits up to the next byte boundary
+    fn align(&mut self) {
+        while self.bits != 0 {
+            self.write_bit(false);
+        }
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// Residuals of the fixed predictor of the given order
+fn fixed_residuals(samples: &[i64], order: usize) -> Vec<i64> {
+    let mut residuals = samples.to_vec();
+    for _ in 0..order {
+        residuals = residuals.windows(2).map(|w| w[1] - w[0]).collect();
+    }
+    residuals
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Find the Rice parameter that needs the fewest bits, and that number of bits.
+fn best_rice_parameter(residuals: &[i64]) -> (u32, u64) {
+    (0..=MAX_RICE_PARAMETER)
+        .map(|k| {
+            let bits = residuals
+                .iter()
+                .map(|&r| (zigzag(r) >> k) + 1 + k as u64)
+                .sum();
+            (k, bits)
+        })
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap_or((0, 0))
+}
+
+fn write_subframe(writer: &mut BitWriter, samples: &[i64], bits_per_sample: u32) {
+    let verbatim_bits = samples.len() as u64 * bits_per_sample as u64;
+    let best_fixed = (0..=4usize.min(samples.len().saturating_sub(1)))
+        .map(|order| {
+            let residuals = fixed_residuals(samples, order);
+            let (parameter, bits) = best_rice_parameter(&residuals);
+            let bits = bits + 6 + order as u64 * bits_per_sample as u64;
+            (order, residuals, parameter, bits)
+        })
+        .min_by_key(|(_, _, _, bits)| *bits);
+    match best_fixed {
+        Some((order, residuals, parameter, bits)) if bits < verbatim_bits => {
+            // Subframe header: zero padding, FIXED type with the order and no wasted bits
+            writer.write(0, 1);
+            writer.write(0b001000 | order as u64, 6);
+            writer.write(0, 1);
+            for &sample in &samples[..order] {
+                writer.write_signed(sample, bits_per_sample);
+            }
+            // Rice coding with 4-bit parameters, a single partition
+            writer.write(0, 2);
+            writer.write(0, 4);
+            writer.write(parameter as u64, 4);
+            for residual in residuals {
+                let value = zigzag(residual);
+                writer.write_unary(value >> parameter);
+                writer.write(value & ((1 << parameter) - 1), parameter);
+            }
+        }
+        _ => {
+            writer.write(0, 1);
+            writer.write(0b000001, 6);
+            writer.write(0, 1);
+            for &sample in samples {
+                writer.write_signed(sample, bits_per_sample);
+            }
+        }
+    }
+}
+
+/// Frame numbers are coded like UTF-8
+fn write_frame_number(writer: &mut BitWriter, number: u64) {
+    let continuation_bytes = match number {
+        0..=0x7f => {
+            writer.write(number, 8);
+            return;
+        }
+        0x80..=0x7ff => 1,
+        0x800..=0xffff => 2,
+        0x1_0000..=0x1f_ffff => 3,
+        0x20_0000..=0x3ff_ffff => 4,
+        0x400_0000..=0x7fff_ffff => 5,
+        _ => 6,
+    };
+    let leading = (0xffu64 << (7 - continuation_bytes)) & 0xff;
+    writer.write(leading | (number >> (6 * continuation_bytes)), 8);
+    for i in (0..continuation_bytes).rev() {
+        writer.write(0x80 | ((number >> (6 * i)) & 0x3f), 8);
+    }
+}
+
+/// The sample rate code of a frame header, and the value that follows the header if needed
+fn sample_rate_code(sample_rate: u32) -> (u8, Option<(u32, u32)>) {
+    match sample_rate {
+        88200 => (0b0001, None),
+        176400 => (0b0010, None),
+        192000 => (0b0011, None),
+        8000 => (0b0100, None),
+        16000 => (0b0101, None),
+        22050 => (0b0110, None),
+        24000 => (0b0111, None),
+        32000 => (0b1000, None),
+        44100 => (0b1001, None),
+        48000 => (0b1010, None),
+        96000 => (0b1011, None),
+        rate if rate % 1000 == 0 && rate / 1000 < 256 => (0b1100, Some((rate / 1000, 8))),
+        rate if rate < 65536 => (0b1101, Some((rate, 16))),
+        rate if rate % 10 == 0 && rate / 10 < 65536 => (0b1110, Some((rate / 10, 16))),
+        // Read the sample rate from STREAMINFO
+        _ => (0b0000, None),
+    }
+}
+
+/// Encode interleaved integer samples into a FLAC stream.
+///
+/// `bits_per_sample` must be between 4 and 24, `channels` between 1 and 8,
+/// and `sample_rate` between 1 and [`MAX_SAMPLE_RATE`].
+pub(super) fn encode(
+    samples: &[i32],
+    sample_rate: u32,
+    channels: u16,
+    bits_per_sample: u16,
+) -> Vec<u8> {
+    let channels = channels as usize;
+    let frames = samples.len() / channels;
+    let mut output = Vec::with_capacity(samples.len() * bits_per_sample as usize / 8);
+    output.extend_from_slice(b"fLaC");
+    // STREAMINFO is the last metadata block
+    let mut info = BitWriter::new();
+    info.write(1, 1);
+    info.write(0, 7);
+    info.write(34, 24);
+    info.write(BLOCK_SIZE as u64, 16);
+    info.write(BLOCK_SIZE as u64, 16);
+    // Unknown minimum and maximum frame sizes
+    info.write(0, 24);
+    info.write(0, 24);
+    info.write(sample_rate as u64, 20);
+    info.write(channels as u64 - 1, 3);
+    info.write(bits_per_sample as u64 - 1, 5);
+    info.write(frames as u64, 36);
+    // The MD5 signature is optional
+    info.write(0, 64);
+    info.write(0, 64);
+    output.extend(info.into_bytes());
+
+    let (sample_rate_code, sample_rate_extra) = sample_rate_code(sample_rate);
+    // Some decoders require the sample size in every frame header
+    let sample_size_code = match bits_per_sample {
+        8 => 0b001,
+        12 => 0b010,
+        16 => 0b100,
+        20 => 0b101,
+        24 => 0b110,
+        _ => 0b000,
+    };
+    let mut channel_samples = vec![Vec::with_capacity(BLOCK_SIZE); channels];
+    for (number, block) in samples.chunks(BLOCK_SIZE * channels).enumerate() {
+        let block_size = block.len() / channels;
+        let mut writer = BitWriter::new();
+        // Sync code and fixed block size strategy
+        writer.write(0b1111_1111_1111_1000, 16);
+        // Block size as a 16-bit number at the end of the header
+        writer.write(0b0111, 4);
+        writer.write(sample_rate_code as u64, 4);
+        // Independent channels
+        writer.write(channels as u64 - 1, 4);
+        writer.write(sample_size_code, 3);
+        writer.write(0, 1);
+        write_frame_number(&mut writer, number as u64);
+        writer.write(block_size as u64 - 1, 16);
+        if let Some((value, bits)) = sample_rate_extra {
+            writer.write(value as u64, bits);
+        }
+        let crc = crc8(&writer.bytes);
+        writer.write(crc as u64, 8);
+        for (channel, buffer) in channel_samples.iter_mut().enumerate() {
+            buffer.clear();
+            buffer.extend(
+                block
+                    .iter()
+                    .skip(channel)
+                    .step_by(channels)
+                    .map(|&s| s as i64),
+            );
+            write_subframe(&mut writer, buffer, bits_per_sample as u32);
+        }
+        let mut frame = writer.into_bytes();
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        output.extend(frame);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sine wave with some deterministic noise, so that the residuals are not trivial
+    fn signal(frames: usize, channels: usize, bits_per_sample: u32) -> Vec<i32> {
+        let amplitude = ((1i64 << (bits_per_sample - 1)) - 1) as f64 * 0.8;
+        let mut noise = 0x1234_5678u32;
+        (0..frames * channels)
+            .map(|i| {
+                noise ^= noise << 13;
+                noise ^= noise >> 17;
+                noise ^= noise << 5;
+                let (frame, channel) = (i / channels, i % channels);
+                let phase = frame as f64 * (0.01 + channel as f64 * 0.003);
+                let jitter = (noise % 64) as f64 - 32.0;
+                (phase.sin() * amplitude + jitter).clamp(-amplitude, amplitude) as i32
+            })
+            .collect()
+    }
+
+    fn round_trip(samples: &[i32], sample_rate: u32, channels: u16, bits_per_sample: u16) {
+        let flac = encode(samples, sample_rate, channels, bits_per_sample);
+        let mut reader = claxon::FlacReader::new(flac.as_slice()).unwrap();
+        let info = reader.streaminfo();
+        assert_eq!(info.sample_rate, sample_rate);
+        assert_eq!(info.channels, channels as u32);
+        assert_eq!(info.bits_per_sample, bits_per_sample as u32);
+        assert_eq!(
+            info.samples,
+            Some((samples.len() / channels as usize) as u64)
+        );
+        let decoded = reader.samples().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(decoded, samples);
+    }
+
+    #[test]
+    fn mono_16khz_round_trips() {
+        round_trip(&signal(10_000, 1, 16), 16000, 1, 16);
+    }
+
+    #[test]
+    fn stereo_48khz_round_trips() {
+        round_trip(&signal(10_000, 2, 16), 48000, 2, 16);
+    }
+
+    #[test]
+    fn final_block_shorter_than_block_size_round_trips() {
+        let frames = BLOCK_SIZE * 2 + 123;
+        round_trip(&signal(frames, 2, 16), 48000, 2, 16);
+        // A stream shorter than a single block
+        round_trip(&signal(100, 1, 16), 16000, 1, 16);
+    }
+
+    #[test]
+    fn other_sample_sizes_round_trip() {
+        round_trip(&signal(5000, 1, 8), 16000, 1, 8);
+        round_trip(&signal(5000, 2, 24), 48000, 2, 24);
+    }
+
+    #[test]
+    fn silence_round_trips() {
+        round_trip(&vec![0; BLOCK_SIZE + 1], 24000, 1, 16);
+    }
+
+    #[test]
+    fn uncommon_sample_rates_round_trip() {
+        for sample_rate in [11025, 64000, 100_000, 123_457, MAX_SAMPLE_RATE] {
+            round_trip(&signal(1000, 1, 16), sample_rate, 1, 16);
+        }
+    }
+}
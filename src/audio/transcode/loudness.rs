@@ -0,0 +1,176 @@
+//! Loudness measurement according to ITU-R BS.1770 and EBU R128.
+
+/// Length of a gating block
+const BLOCK_SECONDS: f64 = 0.4;
+/// Gating blocks overlap by 75%
+const STEP_SECONDS: f64 = 0.1;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// A second order IIR filter in direct form I
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// The K-weighting filter: a high shelf followed by a high pass filter.
+///
+/// The coefficients are derived for any sample rate from the analog prototypes of BS.1770.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = sample_rate as f64;
+
+    let k = (std::f64::consts::PI * 1681.974450955533 / fs).tan();
+    let q = 0.7071752369554196;
+    let vh = 10f64.powf(3.999843853973347 / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        x: [0.0; 2],
+        y: [0.0; 2],
+    };
+
+    let k = (std::f64::consts::PI * 38.13547087602444 / fs).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        x: [0.0; 2],
+        y: [0.0; 2],
+    };
+    [shelf, high_pass]
+}
+
+fn to_lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+/// Integrated loudness of interleaved samples in LUFS.
+///
+/// All channels are weighted equally, which is correct for mono and stereo audio.
+/// Returns `None` if the audio is silent.
+pub(super) fn integrated_loudness(
+    samples: &[f32],
+    channels: usize,
+    sample_rate: u32,
+) -> Option<f64> {
+    let frames = samples.len() / channels;
+    // Sum of the squared K-weighted samples of all channels in each frame
+    let mut powers = vec![0f64; frames];
+    for channel in 0..channels {
+        let [mut shelf, mut high_pass] = k_weighting(sample_rate);
+        for (frame, power) in powers.iter_mut().enumerate() {
+            let weighted =
+                high_pass.process(shelf.process(samples[frame * channels + channel] as f64));
+            *power += weighted * weighted;
+        }
+    }
+    let block = ((BLOCK_SECONDS * sample_rate as f64) as usize).max(1);
+    let step = ((STEP_SECONDS * sample_rate as f64) as usize).max(1);
+    // Audio shorter than a block is measured as a whole
+    let blocks: Vec<f64> = if frames < block {
+        vec![powers.iter().sum::<f64>() / frames.max(1) as f64]
+    } else {
+        (0..=frames - block)
+            .step_by(step)
+            .map(|start| powers[start..start + block].iter().sum::<f64>() / block as f64)
+            .collect()
+    };
+    let gated_mean = |threshold: f64| {
+        let gated: Vec<f64> = blocks
+            .iter()
+            .copied()
+            .filter(|&z| z > 0.0 && to_lufs(z) > threshold)
+            .collect();
+        (!gated.is_empty()).then(|| gated.iter().sum::<f64>() / gated.len() as f64)
+    };
+    let relative_gate = to_lufs(gated_mean(ABSOLUTE_GATE_LUFS)?) + RELATIVE_GATE_LU;
+    gated_mean(relative_gate.max(ABSOLUTE_GATE_LUFS)).map(to_lufs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, amplitude: f64, seconds: f64, sample_rate: u32) -> Vec<f32> {
+        let frames = (seconds * sample_rate as f64) as usize;
+        (0..frames)
+            .map(|i| {
+                let t = i as f64 / sample_rate as f64;
+                (amplitude * (2.0 * std::f64::consts::PI * frequency * t).sin()) as f32
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64, tolerance: f64) {
+        let actual = actual.expect("the audio is not silent");
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "measured {actual} LUFS, expected {expected} LUFS"
+        );
+    }
+
+    #[test]
+    fn full_scale_sine_measures_minus_3_lufs() {
+        // The reference signal of BS.1770: a 997 Hz sine at 0 dBFS in one channel
+        for sample_rate in [16000, 24000, 48000] {
+            let samples = sine(997.0, 1.0, 5.0, sample_rate);
+            assert_close(integrated_loudness(&samples, 1, sample_rate), -3.01, 0.05);
+        }
+    }
+
+    #[test]
+    fn loudness_follows_gain() {
+        let samples = sine(997.0, 0.1, 5.0, 48000);
+        assert_close(integrated_loudness(&samples, 1, 48000), -23.01, 0.05);
+    }
+
+    #[test]
+    fn channels_are_summed() {
+        let samples: Vec<f32> = sine(997.0, 1.0, 5.0, 48000)
+            .into_iter()
+            .flat_map(|s| [s, s])
+            .collect();
+        assert_close(integrated_loudness(&samples, 2, 48000), 0.0, 0.05);
+    }
+
+    #[test]
+    fn quiet_passages_are_gated() {
+        // The quiet part is 30 LU below the loud part, so the relative gate excludes it
+        let mut samples = sine(997.0, 1.0, 3.0, 48000);
+        samples.extend(sine(997.0, 10f64.powf(-30.0 / 20.0), 10.0, 48000));
+        assert_close(integrated_loudness(&samples, 1, 48000), -3.01, 0.3);
+    }
+
+    #[test]
+    fn audio_below_the_absolute_gate_is_silent() {
+        let samples = sine(997.0, 10f64.powf(-75.0 / 20.0), 5.0, 48000);
+        assert_eq!(integrated_loudness(&samples, 1, 48000), None);
+        assert_eq!(integrated_loudness(&[0.0; 48000], 1, 48000), None);
+    }
+
+    #[test]
+    fn audio_shorter_than_a_block_is_measured() {
+        let samples = sine(997.0, 1.0, 0.2, 48000);
+        assert_close(integrated_loudness(&samples, 1, 48000), -3.01, 0.1);
+    }
+}
@@ -1,25 +1,90 @@
 use std::borrow::Cow;
 
 use hyper::{header::HeaderName, http::HeaderValue};
+use serde::{Deserialize, Serialize};
 
 /// Options for authentication
-#[derive(Debug, Clone)]
+///
+/// With serde, the additional headers are a list of `[name, value]` pairs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthOptions<'a> {
     /// Endpoint of the service
     /// It is usually a https url if you are using [`crate::synthesizer::RestSynthesizer`] or
     /// a wss url if you are using [`crate::synthesizer::WebsocketSynthesizer`].
     pub(crate) endpoint: Cow<'a, str>,
     /// Authentication token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub(crate) token: Option<Cow<'a, str>>,
     /// Azure Subscription Key for authentication.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub(crate) key: Option<Cow<'a, str>>,
     /// Additional headers
+    #[serde(
+        default,
+        with = "header_pairs",
+        skip_serializing_if = "<[_]>::is_empty"
+    )]
     pub(crate) headers: Cow<'a, [(HeaderName, HeaderValue)]>,
-    /// Proxy server to use. Only http and socks5 proxy are supported by now.
+    /// Proxy server to use. http, https, socks5 and socks5h proxies are supported,
+    /// credentials in the url are used to authenticate to the proxy server.
+    /// If unset, the proxy is taken from the environment, see [`ProxyResolver`](crate::ProxyResolver).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub(crate) proxy: Option<Cow<'a, str>>,
 }
 
+/// (De)serialize headers as a list of `[name, value]` pairs.
+mod header_pairs {
+    use std::borrow::Cow;
+
+    use hyper::{header::HeaderName, http::HeaderValue};
+    use serde::{de::Error as _, ser::Error as _, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        headers: &[(HeaderName, HeaderValue)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let pairs = headers
+            .iter()
+            .map(|(name, value)| {
+                value
+                    .to_str()
+                    .map(|value| (name.as_str(), value))
+                    .map_err(|_| {
+                        S::Error::custom(format!("the value of header {name} is not visible ASCII"))
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        serializer.collect_seq(pairs)
+    }
+
+    pub fn deserialize<'de, 'a, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Cow<'a, [(HeaderName, HeaderValue)]>, D::Error> {
+        Vec::<(String, String)>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(name, value)| {
+                Ok((
+                    HeaderName::try_from(name).map_err(D::Error::custom)?,
+                    HeaderValue::try_from(value).map_err(D::Error::custom)?,
+                ))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Cow::Owned)
+    }
+}
+
 impl<'a> AuthOptions<'a> {
+    /// Convert into [`AuthOptions`] that own their data, e.g. to store them in long-lived structs
+    pub fn into_owned(self) -> AuthOptions<'static> {
+        AuthOptions {
+            endpoint: Cow::Owned(self.endpoint.into_owned()),
+            token: self.token.map(|token| Cow::Owned(token.into_owned())),
+            key: self.key.map(|key| Cow::Owned(key.into_owned())),
+            headers: Cow::Owned(self.headers.into_owned()),
+            proxy: self.proxy.map(|proxy| Cow::Owned(proxy.into_owned())),
+        }
+    }
+
     /// Endpoint of the service
     /// It is usually a https url if you are using [`crate::synthesizer::RestSynthesizer`] or
     /// a wss url if you are using [`crate::synthesizer::WebsocketSynthesizer`].
@@ -64,12 +129,16 @@ impl<'a> AuthOptions<'a> {
         &mut self.headers
     }
 
-    /// Proxy server to use. Only http and socks5 proxy are supported by now.
+    /// Proxy server to use. http, https, socks5 and socks5h proxies are supported,
+    /// credentials in the url are used to authenticate to the proxy server.
+    /// If unset, the proxy is taken from the environment, see [`ProxyResolver`](crate::ProxyResolver).
     pub fn proxy(&self) -> Option<&str> {
         self.proxy.as_deref()
     }
 
-    /// Proxy server to use. Only http and socks5 proxy are supported by now.
+    /// Proxy server to use. http, https, socks5 and socks5h proxies are supported,
+    /// credentials in the url are used to authenticate to the proxy server.
+    /// If unset, the proxy is taken from the environment, see [`ProxyResolver`](crate::ProxyResolver).
     pub fn proxy_mut(&mut self) -> &mut Option<Cow<'a, str>> {
         &mut self.proxy
     }
@@ -95,8 +164,8 @@ impl<'a> AuthOptionsBuilder<'a> {
     /// # Arguments
     ///
     /// * `endpoint` - Endpoint of the service.
-    /// It is usually a https url if you are using [`crate::synthesizer::RestSynthesizer`] or
-    /// a wss url if you are using [`crate::synthesizer::WebsocketSynthesizer`].
+    ///   It is usually a https url if you are using [`crate::synthesizer::RestSynthesizer`] or
+    ///   a wss url if you are using [`crate::synthesizer::WebsocketSynthesizer`].
     pub fn new(endpoint: impl Into<Cow<'a, str>>) -> Self {
         Self {
             endpoint: endpoint.into(),
@@ -137,13 +206,17 @@ impl<'a> AuthOptionsBuilder<'a> {
         self
     }
 
-    /// Proxy server to use. Only http and socks5 proxy are supported by now.
+    /// Proxy server to use. http, https, socks5 and socks5h proxies are supported,
+    /// credentials in the url are used to authenticate to the proxy server.
+    /// If unset, the proxy is taken from the environment, see [`ProxyResolver`](crate::ProxyResolver).
     pub fn proxy(mut self, proxy: impl Into<Cow<'a, str>>) -> Self {
         self.proxy = Some(proxy.into());
         self
     }
 
-    /// Proxy server to use. Only http and socks5 proxy are supported by now.
+    /// Proxy server to use. http, https, socks5 and socks5h proxies are supported,
+    /// credentials in the url are used to authenticate to the proxy server.
+    /// If unset, the proxy is taken from the environment, see [`ProxyResolver`](crate::ProxyResolver).
     pub fn optional_proxy(mut self, proxy: Option<impl Into<Cow<'a, str>>>) -> Self {
         self.proxy = proxy.map(Into::into);
         self
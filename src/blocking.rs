@@ -0,0 +1,245 @@
+//! A blocking API for synchronous code.
+//!
+//! The synthesizers of this module wrap the async ones and run them on an internal tokio runtime,
+//! so synchronous programs don't need to set up a runtime themselves.
+//!
+//! Like `reqwest::blocking`, the types and functions of this module must not be used within an async runtime,
+//! or they will panic.
+//!
+//! ```no_run
+//! use aspeak::{get_rest_endpoint_by_region, AudioFormat, AuthOptionsBuilder, SynthesizerConfig, TextOptions};
+//!
+//! let auth = AuthOptionsBuilder::new(get_rest_endpoint_by_region("eastus"))
+//!     .key("YOUR_AZURE_SUBSCRIPTION_KEY")
+//!     .build();
+//! let config = SynthesizerConfig::new(auth, AudioFormat::Riff16Khz16BitMonoPcm);
+//! let synthesizer = config.rest_synthesizer_blocking()?;
+//! let audio = synthesizer.synthesize_text("Hello, world!", &TextOptions::default())?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::{io::Read, time::Duration};
+
+use bytes::Bytes;
+use tokio::runtime::{Builder, Runtime};
+use tokio_util::io::SyncIoBridge;
+
+use crate::{
+    CancellationToken, RestSynthesizer, RestSynthesizerError, SynthesizerConfig, TextOptions,
+    Timeouts, TlsOptions, Voice, VoiceListAPIAuth, VoiceListAPIEndpoint, VoiceListAPIError,
+    WebsocketSynthesizer, WebsocketSynthesizerError,
+};
+
+/// Create the runtime of a blocking synthesizer.
+///
+/// Its worker thread keeps the background tasks of websocket connections, like keepalive pings,
+/// running between the calls.
+fn runtime() -> Runtime {
+    Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("aspeak-blocking")
+        .enable_all()
+        .build()
+        .expect("failed to create the runtime of the blocking API")
+}
+
+impl SynthesizerConfig<'_> {
+    /// Construct a [`BlockingRestSynthesizer`] from this [`SynthesizerConfig`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal runtime cannot be created or if it is called within an async runtime.
+    pub fn rest_synthesizer_blocking(
+        &self,
+    ) -> Result<BlockingRestSynthesizer, RestSynthesizerError> {
+        Ok(BlockingRestSynthesizer {
+            synthesizer: self.rest_synthesizer()?,
+            runtime: runtime(),
+        })
+    }
+
+    /// Connect to the Azure Speech Service and return a [`BlockingWebsocketSynthesizer`] on success.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal runtime cannot be created or if it is called within an async runtime.
+    pub fn connect_websocket_blocking(
+        self,
+    ) -> Result<BlockingWebsocketSynthesizer, WebsocketSynthesizerError> {
+        let runtime = runtime();
+        let synthesizer = runtime.block_on(self.connect_websocket())?;
+        Ok(BlockingWebsocketSynthesizer {
+            synthesizer,
+            runtime,
+        })
+    }
+}
+
+/// The blocking version of [`RestSynthesizer`].
+pub struct BlockingRestSynthesizer {
+    synthesizer: RestSynthesizer,
+    runtime: Runtime,
+}
+
+impl BlockingRestSynthesizer {
+    /// Synthesize the given SSML into audio([`Vec<u8>`]).
+    pub fn synthesize_ssml(&self, ssml: &str) -> Result<Vec<u8>, RestSynthesizerError> {
+        self.runtime
+            .block_on(self.synthesizer.synthesize_ssml(ssml))
+    }
+
+    /// Synthesize the given SSML into audio([`bytes::Bytes`]).
+    pub fn synthesize_ssml_to_bytes(&self, ssml: &str) -> Result<Bytes, RestSynthesizerError> {
+        self.runtime
+            .block_on(self.synthesizer.synthesize_ssml_to_bytes(ssml))
+    }
+
+    /// Synthesize the given SSML and read the audio as it arrives through [`Read`].
+    ///
+    /// Errors that occur while reading, including timeouts, are reported as [`std::io::Error`]s
+    /// whose inner error is the [`RestSynthesizerError`].
+    pub fn synthesize_ssml_reader(
+        &self,
+        ssml: &str,
+    ) -> Result<impl Read + Send + 'static, RestSynthesizerError> {
+        let reader = self
+            .runtime
+            .block_on(self.synthesizer.synthesize_ssml_reader(ssml))?;
+        Ok(SyncIoBridge::new_with_handle(
+            reader,
+            self.runtime.handle().clone(),
+        ))
+    }
+
+    /// This is a convenience method that interpolates the SSML for you.
+    pub fn synthesize_text(
+        &self,
+        text: impl AsRef<str>,
+        options: &TextOptions<'_>,
+    ) -> Result<Vec<u8>, RestSynthesizerError> {
+        self.runtime
+            .block_on(self.synthesizer.synthesize_text(text, options))
+    }
+
+    /// This is a convenience method that interpolates the SSML for you.
+    pub fn synthesize_text_to_bytes(
+        &self,
+        text: impl AsRef<str>,
+        options: &TextOptions<'_>,
+    ) -> Result<Bytes, RestSynthesizerError> {
+        self.runtime
+            .block_on(self.synthesizer.synthesize_text_to_bytes(text, options))
+    }
+}
+
+/// The blocking version of [`WebsocketSynthesizer`].
+///
+/// Its connection is kept alive and read between the calls by a worker thread of its internal runtime.
+pub struct BlockingWebsocketSynthesizer {
+    // The synthesizer must be dropped before the runtime that runs its background tasks.
+    synthesizer: WebsocketSynthesizer,
+    runtime: Runtime,
+}
+
+impl BlockingWebsocketSynthesizer {
+    /// The ID of the websocket connection, which Azure support asks for.
+    pub fn connection_id(&self) -> &str {
+        self.synthesizer.connection_id()
+    }
+
+    /// Whether the connection is still usable, see [`WebsocketSynthesizer::is_healthy`].
+    pub fn is_healthy(&self) -> bool {
+        self.synthesizer.is_healthy()
+    }
+
+    /// The round-trip time of the last successful ping, if any.
+    pub fn latency(&self) -> Option<Duration> {
+        self.synthesizer.latency()
+    }
+
+    /// Send a ping to the server and wait for the pong, returning the round-trip time.
+    pub fn ping(&self) -> Result<Duration, WebsocketSynthesizerError> {
+        self.runtime.block_on(self.synthesizer.ping())
+    }
+
+    /// Synthesize the given SSML into audio([`Vec<u8>`]).
+    pub fn synthesize_ssml(&self, ssml: &str) -> Result<Vec<u8>, WebsocketSynthesizerError> {
+        self.runtime
+            .block_on(self.synthesizer.synthesize_ssml(ssml))
+    }
+
+    /// Synthesize the given SSML into audio([`Vec<u8>`]), unless `cancellation` is cancelled first,
+    /// e.g. by another thread.
+    pub fn synthesize_ssml_with_cancellation(
+        &self,
+        ssml: &str,
+        cancellation: &CancellationToken,
+    ) -> Result<Vec<u8>, WebsocketSynthesizerError> {
+        self.runtime.block_on(
+            self.synthesizer
+                .synthesize_ssml_with_cancellation(ssml, cancellation),
+        )
+    }
+
+    /// This is a convenience method that interpolates the SSML for you.
+    pub fn synthesize_text(
+        &self,
+        text: impl AsRef<str>,
+        options: &TextOptions<'_>,
+    ) -> Result<Vec<u8>, WebsocketSynthesizerError> {
+        self.runtime
+            .block_on(self.synthesizer.synthesize_text(text, options))
+    }
+
+    /// This is a convenience method that interpolates the SSML for you.
+    pub fn synthesize_text_with_cancellation(
+        &self,
+        text: impl AsRef<str>,
+        options: &TextOptions<'_>,
+        cancellation: &CancellationToken,
+    ) -> Result<Vec<u8>, WebsocketSynthesizerError> {
+        self.runtime
+            .block_on(self.synthesizer.synthesize_text_with_cancellation(
+                text,
+                options,
+                cancellation,
+            ))
+    }
+}
+
+/// Query available voices from the voice list API, see [`Voice::request_available_voices`].
+///
+/// # Panics
+///
+/// Panics if the internal runtime cannot be created or if it is called within an async runtime.
+pub fn request_available_voices(
+    endpoint: VoiceListAPIEndpoint<'_>,
+    auth: Option<VoiceListAPIAuth<'_>>,
+    proxy: Option<&str>,
+) -> Result<Vec<Voice>, VoiceListAPIError> {
+    runtime().block_on(Voice::request_available_voices(endpoint, auth, proxy))
+}
+
+/// Query available voices from the voice list API with the given [`Timeouts`] and [`TlsOptions`],
+/// see [`Voice::request_available_voices_with_options`].
+///
+/// # Panics
+///
+/// Panics if the internal runtime cannot be created or if it is called within an async runtime.
+pub fn request_available_voices_with_options(
+    endpoint: VoiceListAPIEndpoint<'_>,
+    auth: Option<VoiceListAPIAuth<'_>>,
+    proxy: Option<&str>,
+    additional_headers: Option<reqwest::header::HeaderMap>,
+    timeouts: &Timeouts,
+    tls: &TlsOptions,
+) -> Result<Vec<Voice>, VoiceListAPIError> {
+    runtime().block_on(Voice::request_available_voices_with_options(
+        endpoint,
+        auth,
+        proxy,
+        additional_headers,
+        timeouts,
+        tls,
+    ))
+}
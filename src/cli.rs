@@ -1,17 +1,23 @@
 use clap::{ArgAction, Parser};
-use log::{debug, info, warn};
-use rodio::{Decoder, OutputStream, Sink};
 
 use self::{
-    args::{AuthArgs, Color, InputArgs, ProfileArgs, SynthesizerMode, TextArgs},
+    args::{
+        AuthArgs, Color, ContainerFormat, InputArgs, OutputArgs, ProfileArgs, SynthesizerMode,
+        TextArgs,
+    },
     commands::Command,
-    config::{Config, TextConfig},
+    config::{OutputConfig, Profile, TextConfig},
+    output::{FileSink, OutputProcessor, OutputSink, PlaybackSink, TagSink, TranscodeSink},
 };
-use aspeak::{get_default_voice_by_locale, RichSsmlOptions, TextOptions};
+use aspeak::{
+    get_default_voice_by_locale, AudioContainer, AudioFormat, AudioTags, AudioTagsBuilder,
+    Encoding, RichSsmlOptions, TextOptions,
+};
+use log::warn;
 use std::{
     borrow::Cow,
-    fs::{File, OpenOptions},
-    io::{self, Cursor, Read, Write},
+    fs::File,
+    io::{self, Read},
     path::Path,
 };
 
@@ -24,6 +30,7 @@ use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
 pub(crate) mod args;
 pub(crate) mod commands;
 pub(crate) mod config;
+pub(crate) mod output;
 mod parse;
 
 #[derive(Parser, Debug)]
@@ -46,8 +53,6 @@ pub(crate) struct Cli {
     pub auth: AuthArgs,
 }
 
-type OutputProcessor = Box<dyn FnOnce(Vec<u8>) -> color_eyre::Result<()> + Send>;
-
 impl Cli {
     fn log_level_by_verbosity(verbosity: u8) -> log::LevelFilter {
         match verbosity {
@@ -60,7 +65,7 @@ impl Cli {
 
     pub(crate) fn get_synthesizer_mode(
         input_args: &InputArgs,
-        config: &Option<Config>,
+        config: &Option<Profile>,
     ) -> SynthesizerMode {
         input_args
             .mode
@@ -70,7 +75,7 @@ impl Cli {
                     .and_then(|c| c.auth.as_ref())
                     .and_then(|a| a.mode)
             })
-            .unwrap_or(SynthesizerMode::Rest)
+            .unwrap_or_default()
     }
     pub(crate) fn get_log_level(&self, verbosity_config: Option<u8>) -> log::LevelFilter {
         match self.verbose {
@@ -104,54 +109,62 @@ impl Cli {
         Ok(s)
     }
 
-    pub(crate) fn process_output(
-        output: Option<String>,
-        overwrite: bool,
-    ) -> color_eyre::Result<OutputProcessor> {
-        Ok(if let Some(file) = output.as_deref() {
-            let file = Path::new(file);
-            let mut file = match (file.exists(), overwrite) {
-                (_, true) => File::create(file)?,
-                (false, false) => OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .create_new(true)
-                    .open(file)?,
-                (true, false) => {
-                    return Err(anyhow!("File {} already exists!", file.display())
-                        .suggestion("You can use --overwrite to overwrite this file."))
+    /// Create a builder for the tags of the output files, dated with the current time in UTC in RFC 3339 format
+    pub(crate) fn audio_tags_builder() -> AudioTagsBuilder {
+        AudioTags::builder()
+            .date(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+    }
+
+    pub(crate) fn process_output<'a>(
+        args: &'a OutputArgs,
+        config: Option<&'a OutputConfig>,
+        format: AudioFormat,
+        tags: Option<AudioTags>,
+    ) -> color_eyre::Result<OutputProcessor<'a>> {
+        let mut sinks = args
+            .output
+            .iter()
+            .map(|file| {
+                if let Some(mut options) = args.get_transcode_options(file, format)? {
+                    match (&tags, options.encoding()) {
+                        (None, _) => {}
+                        (Some(_), Encoding::Pcm) => {
+                            warn!("Raw PCM audio can not be tagged, saving {file} without tags")
+                        }
+                        (Some(tags), _) => *options.tags_mut() = Some(tags.clone()),
+                    }
+                    let sink =
+                        TranscodeSink::create(Path::new(file), args.overwrite, format, options)?;
+                    return Ok(Box::new(sink) as Box<dyn OutputSink>);
                 }
-            };
-            Box::new(move |buffer| {
-                file.write_all(&buffer)?;
-                Ok(())
-            })
-        } else {
-            Box::new(|buffer| {
-                info!("Playing audio... ({} bytes)", buffer.len());
-                if buffer.is_empty()
-                    || (
-                        buffer.starts_with(b"RIFF")
-                            && buffer.len() >= 44
-                            && buffer[8..16] == *b"WAVEfmt "
-                            && buffer[24..28] == *b"\0\0\0\0"
-                        // Sample Rate is zero
-                    )
-                {
-                    // Empty buffer, do nothing
-                    warn!("Got empty audio buffer, nothing to play");
-                    return Ok(());
+                let wrap_in_riff = format.container() == AudioContainer::Raw
+                    && ContainerFormat::from_path(file) == Some(ContainerFormat::Wav);
+                if let Some(tags) = &tags {
+                    if TagSink::supports(format, wrap_in_riff) {
+                        let sink =
+                            TagSink::create(Path::new(file), args.overwrite, format, tags.clone())?;
+                        return Ok(Box::new(sink) as Box<dyn OutputSink>);
+                    }
+                    warn!(
+                        "Audio format {} can not be tagged, saving {file} without tags",
+                        Into::<&str>::into(format)
+                    );
                 }
-                let (_stream, stream_handle) = OutputStream::try_default()?;
-                let sink = Sink::try_new(&stream_handle).unwrap();
-                let cursor = Cursor::new(buffer);
-                let source = Decoder::new(cursor)?;
-                sink.append(source);
-                sink.sleep_until_end();
-                debug!("Done playing audio");
-                Ok(())
+                let mut sink = FileSink::create(Path::new(file), args.overwrite)?;
+                if wrap_in_riff {
+                    sink.wrap_in_riff(format)?;
+                }
+                Ok(Box::new(sink) as Box<dyn OutputSink>)
             })
-        })
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+        if args.play || sinks.is_empty() {
+            sinks.push(Box::new(PlaybackSink::new(
+                format,
+                args.get_playback_options(config)?,
+                args.get_playback_post_processing(),
+            )?));
+        }
+        Ok(OutputProcessor::new(sinks, format, args.print_duration))
     }
 
     pub(crate) fn process_text_options<'a>(
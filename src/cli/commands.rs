@@ -27,6 +27,8 @@ pub(crate) enum Command {
     ListQualities,
     #[command(about = "List available formats (for experts)")]
     ListFormats,
+    #[command(about = "List available audio output devices")]
+    ListDevices,
     #[command(about = "Speak text")]
     Text {
         #[command(flatten)]
@@ -0,0 +1,557 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use aspeak::{
+    audio::{
+        pcm::{into_playable, riff_data_offset, wav_header, wrap_in_riff},
+        Player, PlayerStream,
+    },
+    embed_tags, play_owned_audio_blocking_with_options, probe, transcode, AudioContainer,
+    AudioFormat, AudioTags, PlaybackOptions, TranscodeOptions,
+};
+use color_eyre::{eyre::anyhow, Help};
+use log::{debug, info, log_enabled, warn, Level};
+
+/// A destination that synthesized audio is written to.
+pub(crate) trait OutputSink: Send {
+    /// Consume the next chunk of synthesized audio.
+    fn write_chunk(&mut self, chunk: &[u8]) -> color_eyre::Result<()>;
+    /// Called once after the last chunk has been written.
+    fn finish(self: Box<Self>) -> color_eyre::Result<()>;
+}
+
+/// Writes audio to a file as it arrives.
+///
+/// The file is only created when the first chunk arrives or the sink is finished,
+/// so a synthesis that fails before does not leave an empty file behind.
+pub(crate) struct FileSink {
+    path: PathBuf,
+    overwrite: bool,
+    file: Option<File>,
+    /// Format and number of audio bytes following a RIFF header written by [`FileSink::wrap_in_riff`]
+    riff: Option<(AudioFormat, u32)>,
+}
+
+impl FileSink {
+    pub(crate) fn create(path: &Path, overwrite: bool) -> color_eyre::Result<Self> {
+        if path.exists() && !overwrite {
+            return Err(anyhow!("File {} already exists!", path.display())
+                .suggestion("You can use --overwrite to overwrite this file."));
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            overwrite,
+            file: None,
+            riff: None,
+        })
+    }
+
+    /// Wrap the raw audio written to this file into RIFF.
+    ///
+    /// A RIFF header is written when the file is created, and its sizes are updated once the audio is complete.
+    pub(crate) fn wrap_in_riff(&mut self, format: AudioFormat) -> color_eyre::Result<()> {
+        if wav_header(format, 0).is_none() {
+            return Err(anyhow!(
+                "Audio format {} can not be saved as a WAV file",
+                Into::<&str>::into(format)
+            ));
+        }
+        self.riff = Some((format, 0));
+        Ok(())
+    }
+
+    /// The file, which is created on first use
+    fn file(&mut self) -> color_eyre::Result<&mut File> {
+        if self.file.is_none() {
+            let mut file = if self.overwrite {
+                File::create(&self.path)?
+            } else {
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create_new(true)
+                    .open(&self.path)?
+            };
+            if let Some((format, _)) = self.riff {
+                file.write_all(&wav_header(format, 0).expect("checked by wrap_in_riff"))?;
+            }
+            self.file = Some(file);
+        }
+        Ok(self.file.as_mut().expect("the file was just created"))
+    }
+
+    /// Rewrite the RIFF header with the sizes of the audio written so far
+    fn update_riff_header(&mut self) -> color_eyre::Result<()> {
+        if let (Some(file), Some((format, len))) = (self.file.as_mut(), self.riff) {
+            let header = wav_header(format, len).expect("checked by wrap_in_riff");
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&header)?;
+        }
+        Ok(())
+    }
+}
+
+impl OutputSink for FileSink {
+    fn write_chunk(&mut self, chunk: &[u8]) -> color_eyre::Result<()> {
+        self.file()?.write_all(chunk)?;
+        if let Some((_, len)) = self.riff.as_mut() {
+            *len = u32::try_from(chunk.len())
+                .ok()
+                .and_then(|chunk_len| len.checked_add(chunk_len))
+                .ok_or_else(|| anyhow!("The audio is too large for a WAV file"))?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> color_eyre::Result<()> {
+        // The file is created even if there is no audio
+        self.file()?;
+        self.update_riff_header()?;
+        let mut file = self.file.take().expect("the file was created");
+        file.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for FileSink {
+    /// Keep the audio received before a failure playable
+    fn drop(&mut self) {
+        if let Err(e) = self.update_riff_header() {
+            warn!(
+                "Failed to update the RIFF header of {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Embeds tags into the complete audio before writing it to a file.
+pub(crate) struct TagSink {
+    file: FileSink,
+    buffer: Vec<u8>,
+    format: AudioFormat,
+    tags: AudioTags,
+}
+
+impl TagSink {
+    /// Whether audio in `format` can be tagged, after raw audio is wrapped into RIFF if `wrap_in_riff` is set
+    pub(crate) fn supports(format: AudioFormat, wrap_in_riff: bool) -> bool {
+        match format.container() {
+            AudioContainer::Mp3 | AudioContainer::Ogg | AudioContainer::Wav => true,
+            AudioContainer::Raw => wrap_in_riff && wav_header(format, 0).is_some(),
+            _ => false,
+        }
+    }
+
+    /// Raw audio is wrapped into RIFF before it is tagged.
+    pub(crate) fn create(
+        path: &Path,
+        overwrite: bool,
+        format: AudioFormat,
+        tags: AudioTags,
+    ) -> color_eyre::Result<Self> {
+        Ok(Self {
+            file: FileSink::create(path, overwrite)?,
+            buffer: Vec::new(),
+            format,
+            tags,
+        })
+    }
+}
+
+impl OutputSink for TagSink {
+    fn write_chunk(&mut self, chunk: &[u8]) -> color_eyre::Result<()> {
+        self.buffer.extend_from_slice(chunk);
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> color_eyre::Result<()> {
+        let Self {
+            mut file,
+            buffer,
+            format,
+            tags,
+        } = *self;
+        let (container, audio) = match format.container() {
+            AudioContainer::Raw => (
+                AudioContainer::Wav,
+                wrap_in_riff(format, &buffer)
+                    .ok_or_else(|| anyhow!("The audio is too large for a WAV file"))?,
+            ),
+            container => (container, buffer),
+        };
+        // Never lose the audio because of its tags
+        let audio = match embed_tags(container, &audio, &tags) {
+            Ok(tagged) => tagged,
+            Err(e) => {
+                warn!("Failed to tag the audio, saving it without tags: {e}");
+                audio
+            }
+        };
+        file.write_chunk(&audio)?;
+        Box::new(file).finish()
+    }
+}
+
+fn check_transcodable(format: AudioFormat) -> color_eyre::Result<()> {
+    if wav_header(format, 0).is_none() {
+        return Err(anyhow!(
+            "Audio format {} can not be processed, only PCM, A-law and μ-law are supported",
+            Into::<&str>::into(format)
+        )
+        .suggestion("Use the WAV container format with `-c wav`, or choose another format."));
+    }
+    Ok(())
+}
+
+/// Transcodes the complete audio before writing it to a file.
+pub(crate) struct TranscodeSink {
+    file: FileSink,
+    buffer: Vec<u8>,
+    format: AudioFormat,
+    options: TranscodeOptions,
+}
+
+impl TranscodeSink {
+    pub(crate) fn create(
+        path: &Path,
+        overwrite: bool,
+        format: AudioFormat,
+        options: TranscodeOptions,
+    ) -> color_eyre::Result<Self> {
+        check_transcodable(format)?;
+        Ok(Self {
+            file: FileSink::create(path, overwrite)?,
+            buffer: Vec::new(),
+            format,
+            options,
+        })
+    }
+}
+
+impl OutputSink for TranscodeSink {
+    fn write_chunk(&mut self, chunk: &[u8]) -> color_eyre::Result<()> {
+        self.buffer.extend_from_slice(chunk);
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> color_eyre::Result<()> {
+        let Self {
+            mut file,
+            buffer,
+            format,
+            options,
+        } = *self;
+        file.write_chunk(&transcode(format, &buffer, &options)?)?;
+        Box::new(file).finish()
+    }
+}
+
+/// Plays audio on an output device.
+///
+/// The audio is played as it arrives, unless it has to be post-processed or
+/// played from an offset, which needs the complete audio.
+pub(crate) struct PlaybackSink<'a> {
+    format: AudioFormat,
+    options: PlaybackOptions<'a>,
+    post_processing: Option<TranscodeOptions>,
+    playback: Playback,
+}
+
+enum Playback {
+    /// The audio is played once it is complete
+    Buffered(Vec<u8>),
+    /// Compressed audio is decoded by the player as it arrives
+    Stream {
+        player: Player,
+        stream: PlayerStream,
+        written: bool,
+    },
+    /// Uncompressed audio is enqueued in blocks of whole samples
+    Blocks {
+        player: Player,
+        /// Audio that does not form a whole sample yet, or the incomplete RIFF header
+        pending: Vec<u8>,
+        /// Whether the RIFF header still has to be skipped
+        in_header: bool,
+        block_align: usize,
+        enqueued: bool,
+    },
+}
+
+impl<'a> PlaybackSink<'a> {
+    pub(crate) fn new(
+        format: AudioFormat,
+        options: PlaybackOptions<'a>,
+        post_processing: Option<TranscodeOptions>,
+    ) -> color_eyre::Result<Self> {
+        if !format.is_decodable_locally() {
+            return Err(anyhow!(
+                "Audio format {} can not be played by aspeak",
+                Into::<&str>::into(format)
+            )
+            .suggestion("Save the audio to a file with --output, or choose another format."));
+        }
+        if post_processing.is_some() {
+            check_transcodable(format)?;
+        }
+        let playback = if post_processing.is_some() || !options.start_offset().is_zero() {
+            Playback::Buffered(Vec::new())
+        } else if let Some(bits) = format
+            .bits_per_sample()
+            .filter(|_| wav_header(format, 0).is_some())
+        {
+            Playback::Blocks {
+                player: Player::new(&options)?,
+                pending: Vec::new(),
+                in_header: format.container() == AudioContainer::Wav,
+                block_align: (format.channels() as usize * bits as usize / 8).max(1),
+                enqueued: false,
+            }
+        } else {
+            let player = Player::new(&options)?;
+            let stream = player.enqueue_stream()?;
+            Playback::Stream {
+                player,
+                stream,
+                written: false,
+            }
+        };
+        Ok(Self {
+            format,
+            options,
+            post_processing,
+            playback,
+        })
+    }
+}
+
+impl OutputSink for PlaybackSink<'_> {
+    fn write_chunk(&mut self, chunk: &[u8]) -> color_eyre::Result<()> {
+        match &mut self.playback {
+            Playback::Buffered(buffer) => buffer.extend_from_slice(chunk),
+            Playback::Stream {
+                stream, written, ..
+            } => {
+                stream.write(chunk);
+                *written |= !chunk.is_empty();
+            }
+            Playback::Blocks {
+                player,
+                pending,
+                in_header,
+                block_align,
+                enqueued,
+            } => {
+                pending.extend_from_slice(chunk);
+                if *in_header {
+                    // Wait until the whole header has arrived
+                    let Some(offset) = riff_data_offset(pending) else {
+                        return Ok(());
+                    };
+                    pending.drain(..offset);
+                    *in_header = false;
+                }
+                let len = pending.len() - pending.len() % *block_align;
+                if len > 0 {
+                    let block = pending.drain(..len).collect();
+                    player.enqueue(playable_block(self.format, block)?)?;
+                    *enqueued = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> color_eyre::Result<()> {
+        let (player, played) = match self.playback {
+            Playback::Buffered(buffer) => {
+                if buffer.is_empty()
+                    || (
+                        self.format.container() == AudioContainer::Wav
+                            && buffer.len() >= 44
+                            && buffer[24..28] == *b"\0\0\0\0"
+                        // Sample Rate in the header is zero
+                    )
+                {
+                    // Empty buffer, do nothing
+                    warn!("Got empty audio buffer, nothing to play");
+                    return Ok(());
+                }
+                let buffer = match &self.post_processing {
+                    Some(options) => transcode(self.format, &buffer, options)?,
+                    None => into_playable(self.format, buffer),
+                };
+                play_owned_audio_blocking_with_options(buffer, &self.options)?;
+                return Ok(());
+            }
+            Playback::Stream {
+                player,
+                stream,
+                written,
+            } => {
+                stream.finish();
+                (player, written)
+            }
+            Playback::Blocks {
+                player, enqueued, ..
+            } => (player, enqueued),
+        };
+        if !played {
+            warn!("Got empty audio buffer, nothing to play");
+            return Ok(());
+        }
+        player.wait_until_finished()?;
+        debug!("Done playing audio");
+        Ok(())
+    }
+}
+
+/// Convert a block of whole samples of uncompressed audio into a playable RIFF/WAVE file.
+fn playable_block(format: AudioFormat, block: Vec<u8>) -> color_eyre::Result<Vec<u8>> {
+    let block = match format.container() {
+        // The header of the synthesized audio has been skipped
+        AudioContainer::Wav => {
+            let len = u32::try_from(block.len())?;
+            let mut wav = wav_header(format, len).ok_or_else(|| {
+                anyhow!(
+                    "Audio format {} can not be played",
+                    Into::<&str>::into(format)
+                )
+            })?;
+            wav.extend_from_slice(&block);
+            wav
+        }
+        _ => block,
+    };
+    Ok(into_playable(format, block))
+}
+
+/// Fans synthesized audio out to one or more [`OutputSink`]s.
+pub(crate) struct OutputProcessor<'a> {
+    sinks: Vec<Box<dyn OutputSink + 'a>>,
+    format: AudioFormat,
+    print_duration: bool,
+    /// A copy of the audio, only kept if its duration is reported
+    audio: Option<Vec<u8>>,
+}
+
+impl<'a> OutputProcessor<'a> {
+    pub(crate) fn new(
+        sinks: Vec<Box<dyn OutputSink + 'a>>,
+        format: AudioFormat,
+        print_duration: bool,
+    ) -> Self {
+        let audio = (print_duration || log_enabled!(Level::Info)).then(Vec::new);
+        Self {
+            sinks,
+            format,
+            print_duration,
+            audio,
+        }
+    }
+
+    /// Write a chunk of audio to every sink.
+    pub(crate) fn write_chunk(&mut self, chunk: &[u8]) -> color_eyre::Result<()> {
+        if let Some(audio) = self.audio.as_mut() {
+            audio.extend_from_slice(chunk);
+        }
+        for sink in self.sinks.iter_mut() {
+            sink.write_chunk(chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Report the duration of the audio and finish all sinks in order.
+    ///
+    /// Files are listed before the playback sink, so they are completely written
+    /// before we block on playing the rest of the audio.
+    pub(crate) fn finish(self) -> color_eyre::Result<()> {
+        if let Some(audio) = &self.audio {
+            self.report_duration(audio)?;
+        }
+        for sink in self.sinks {
+            sink.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Log the duration of the audio, and print it if `--print-duration` is given.
+    fn report_duration(&self, buffer: &[u8]) -> color_eyre::Result<()> {
+        match probe(self.format, buffer) {
+            Ok(info) => {
+                info!(
+                    "Synthesized {:.3}s of audio: {} samples at {} Hz, {} frames",
+                    info.duration.as_secs_f64(),
+                    info.samples,
+                    info.sample_rate,
+                    info.frames
+                );
+                if self.print_duration {
+                    println!("{:.3}", info.duration.as_secs_f64());
+                }
+            }
+            Err(e) if self.print_duration => return Err(e.into()),
+            Err(e) => debug!("Failed to inspect the synthesized audio: {e}"),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path in the temporary directory that does not exist yet
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("aspeak-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn file_is_created_on_first_chunk() {
+        let path = temp_path("first-chunk.mp3");
+        let mut sink = Box::new(FileSink::create(&path, false).unwrap());
+        assert!(!path.exists());
+        sink.write_chunk(b"audio").unwrap();
+        assert!(path.exists());
+        sink.finish().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"audio");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failed_synthesis_leaves_files_alone() {
+        let path = temp_path("failed.mp3");
+        drop(FileSink::create(&path, false).unwrap());
+        assert!(!path.exists());
+        let format = AudioFormat::Audio16Khz32KBitRateMonoMp3;
+        drop(TagSink::create(&path, false, format, AudioTags::default()).unwrap());
+        assert!(!path.exists());
+        // An existing file is only overwritten once there is audio
+        std::fs::write(&path, b"old audio").unwrap();
+        assert!(FileSink::create(&path, false).is_err());
+        drop(FileSink::create(&path, true).unwrap());
+        assert_eq!(std::fs::read(&path).unwrap(), b"old audio");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn riff_header_is_updated_after_failure() {
+        let path = temp_path("failed.wav");
+        let format = AudioFormat::Raw16Khz16BitMonoPcm;
+        let mut sink = FileSink::create(&path, false).unwrap();
+        sink.wrap_in_riff(format).unwrap();
+        sink.write_chunk(&[0; 4]).unwrap();
+        sink.write_chunk(&[1; 2]).unwrap();
+        drop(sink);
+        let mut expected = wav_header(format, 6).unwrap();
+        expected.extend_from_slice(&[0, 0, 0, 0, 1, 1]);
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
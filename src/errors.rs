@@ -14,6 +14,7 @@ use strum::AsRefStr;
 /// - Proxy related errors
 /// - Network related errors
 /// - Bad response from the service
+#[cfg_attr(not(feature = "websocket-synthesizer"), allow(dead_code))]
 pub struct ConnectError {
     pub kind: ConnectErrorKind,
     pub(crate) source: Option<anyhow::Error>,
@@ -31,6 +32,9 @@ impl Display for ConnectError {
                 }
             }
             ConnectErrorKind::BadUrl(ref url) => write!(f, "bad url: {url}"),
+            ConnectErrorKind::ProxyAuthentication => write!(f, "proxy authentication failed"),
+            ConnectErrorKind::ProxyConfiguration => write!(f, "invalid proxy configuration"),
+            ConnectErrorKind::Timeout(phase) => write!(f, "{phase} timed out"),
             _ => write!(
                 f,
                 "{} error while connecting to proxy or azure API",
@@ -56,6 +60,46 @@ pub enum ConnectErrorKind {
     RequestConstruction,
     BadResponse,
     Connection,
+    /// The TLS configuration is invalid or the TLS handshake with the proxy server failed
+    Tls,
+    /// The proxy server rejected the credentials or requires some
+    ProxyAuthentication,
+    /// The proxy configuration in the environment is invalid
+    ProxyConfiguration,
+    /// A timeout expired while connecting
+    Timeout(crate::TimeoutPhase),
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+impl From<crate::TlsError> for ConnectError {
+    fn from(e: crate::TlsError) -> Self {
+        Self {
+            kind: ConnectErrorKind::Tls,
+            source: Some(e.into()),
+        }
+    }
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+impl From<crate::timeout::TimeoutError> for ConnectError {
+    fn from(e: crate::timeout::TimeoutError) -> Self {
+        Self {
+            kind: ConnectErrorKind::Timeout(e.phase),
+            source: Some(e.into()),
+        }
+    }
+}
+
+impl From<crate::ProxyError> for ConnectError {
+    fn from(e: crate::ProxyError) -> Self {
+        Self {
+            kind: match &e.kind {
+                crate::ProxyErrorKind::BadUrl(url) => ConnectErrorKind::BadUrl(url.clone()),
+                _ => ConnectErrorKind::ProxyConfiguration,
+            },
+            source: Some(e.into()),
+        }
+    }
 }
 
 #[allow(unused_macros)]
@@ -80,3 +124,81 @@ impl_from_for_connect_error!(std::io::Error, Connection);
 impl_from_for_connect_error!(tokio_socks::Error, Connection);
 #[cfg(feature = "websocket-synthesizer")]
 impl_from_for_connect_error!(hyper::Error, Connection);
+
+/// Details of a failed request to the speech service.
+///
+/// Azure support asks for the request and connection IDs when investigating a problem.
+/// It is attached to the errors of the synthesizers, see e.g.
+/// [`RestSynthesizerError::details`](crate::synthesizer::RestSynthesizerError::details).
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct RequestDetails {
+    /// The HTTP status of the response
+    pub status: Option<u16>,
+    /// The error message or the body of the last response from the service
+    pub message: Option<String>,
+    /// The ID of the request, assigned by aspeak or by the service
+    pub request_id: Option<String>,
+    /// The ID of the websocket connection
+    pub connection_id: Option<String>,
+    pub(crate) source: Option<anyhow::Error>,
+}
+
+impl RequestDetails {
+    #[cfg(any(feature = "rest-synthesizer", feature = "websocket-synthesizer"))]
+    pub(crate) fn new(request_id: Option<String>, connection_id: Option<String>) -> Self {
+        Self {
+            status: None,
+            message: None,
+            request_id,
+            connection_id,
+            source: None,
+        }
+    }
+
+    /// Find the details in the chain of `source`.
+    #[cfg(any(
+        feature = "rest-synthesizer",
+        feature = "websocket-synthesizer",
+        feature = "unified-synthesizer"
+    ))]
+    pub(crate) fn find(source: &Option<anyhow::Error>) -> Option<&Self> {
+        source.as_ref()?.chain().find_map(|e| e.downcast_ref())
+    }
+
+    /// Insert the details into the chain of errors between an error and its `source`.
+    #[cfg(any(feature = "rest-synthesizer", feature = "websocket-synthesizer"))]
+    pub(crate) fn attach_to(mut self, source: &mut Option<anyhow::Error>) {
+        self.source = source.take();
+        *source = Some(self.into());
+    }
+}
+
+impl Display for RequestDetails {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "request details:")?;
+        let mut separator = " ";
+        if let Some(status) = self.status {
+            write!(f, "{separator}status {status}")?;
+            separator = ", ";
+        }
+        if let Some(request_id) = &self.request_id {
+            write!(f, "{separator}request id {request_id}")?;
+            separator = ", ";
+        }
+        if let Some(connection_id) = &self.connection_id {
+            write!(f, "{separator}connection id {connection_id}")?;
+            separator = ", ";
+        }
+        if let Some(message) = &self.message {
+            write!(f, "{separator}message: {message}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for RequestDetails {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|e| e.as_ref() as _)
+    }
+}
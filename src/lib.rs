@@ -48,12 +48,23 @@
 //!
 //! The full code can be found in [examples/03-rest-synthesizer-simple.rs](https://github.com/kxxt/aspeak/blob/v6/examples/03-rest-synthesizer-simple.rs)
 //!
+//! To start playing or forwarding the audio before the synthesis is complete,
+//! receive it as a stream of chunks or read it through an [AsyncRead][tokio::io::AsyncRead].
+//!
+//! ```ignore
+//! let mut stream = pin!(rest_syn.synthesize_ssml_stream(ssml).await?);
+//! while let Some(chunk) = stream.next().await {
+//!     let chunk = chunk?;
+//! }
+//! let reader = rest_syn.synthesize_ssml_reader(ssml).await?;
+//! ```
+//!
 //! ## Websocket Synthesizer
 //!
 //! You can also create a [WebsocketSynthesizer][crate::synthesizer::WebsocketSynthesizer].
 //!
 //! ```ignore
-//! let mut ws_syn = config.connect_websocket().await?;
+//! let ws_syn = config.connect_websocket().await?;
 //! ```
 //!
 //! Then you can synthesize SSML to audio data.
@@ -74,11 +85,104 @@
 //!
 //! The full code can be found in [examples/04-websocket-synthesizer-simple.rs](https://github.com/kxxt/aspeak/blob/v6/examples/04-websocket-synthesizer-simple.rs)
 //!
+//! A long-lived connection may be closed by the server or a middlebox while it is idle.
+//! [SynthesizerConfig::with_keepalive][crate::synthesizer::SynthesizerConfig::with_keepalive] pings the server
+//! in the background, and [WebsocketSynthesizer::is_healthy][crate::synthesizer::WebsocketSynthesizer::is_healthy]
+//! tells you when it is time to reconnect.
+//!
+//! ```ignore
+//! let ws_syn = config.with_keepalive(Duration::from_secs(30)).connect_websocket().await?;
+//! let latency = ws_syn.ping().await?;
+//! ```
+//!
+//! A [WebsocketPool][crate::synthesizer::WebsocketPool] keeps several connections open and serves concurrent requests
+//! through `&self`, so it can be shared between tasks.
+//!
+//! ```ignore
+//! let pool = Arc::new(config.connect_websocket_pool(4).await?);
+//! let audio_data = pool.synthesize_ssml(ssml).await?;
+//! ```
+//!
+//! A request can be cancelled with a [CancellationToken][crate::synthesizer::CancellationToken],
+//! e.g. when the user interrupts the speech. The rest of its audio is discarded and the connection stays usable.
+//!
+//! ```ignore
+//! let cancellation = CancellationToken::new();
+//! let audio_data = ws_syn.synthesize_ssml_with_cancellation(ssml, &cancellation).await;
+//! ```
+//!
+//! # Audio playback
+//!
+//! With the `audio` feature, you can play the synthesized audio with a [Player][crate::audio::Player].
+//! It keeps the output device open, so you can enqueue audio back-to-back without gaps.
+//!
+//! ```ignore
+//! use aspeak::{audio::Player, PlaybackOptions};
+//! let player = Player::new(&PlaybackOptions::default())?;
+//! player.enqueue(audio_data)?;
+//! player.finished().await?;
+//! ```
+//!
+//! # Transcoding
+//!
+//! With the `transcode` feature, PCM output can be resampled, up-mixed, normalized and encoded to WAV or FLAC locally
+//! with [transcode][crate::audio::transcode::transcode].
+//!
+//! ```ignore
+//! use aspeak::{transcode, Encoding, TranscodeOptions};
+//! let options = TranscodeOptions::builder()
+//!     .sample_rate(44100)
+//!     .channels(2)
+//!     .bits_per_sample(24)
+//!     .encoding(Encoding::Flac)
+//!     .build();
+//! let flac = transcode(AudioFormat::Riff24Khz16BitMonoPcm, &audio_data, &options)?;
+//! ```
+//!
 //! # Unified synthesizer trait
 //!
 //! There is also a unified synthesizer trait [Synthesizer][crate::synthesizer::UnifiedSynthesizer] that can be used to
 //! provide a unified interface for both RESTful and Websocket synthesizers.
 //!
+//! Besides the whole audio, it can stream the audio in chunks, or in [events][crate::synthesizer::SynthesisEvent]
+//! that also carry the word and sentence boundaries if the synthesizer supports them.
+//!
+//! ```ignore
+//! let mut events = synthesizer.process_ssml_events(ssml).await?;
+//! while let Some(event) = events.next().await {
+//!     match event? {
+//!         SynthesisEvent::Audio(chunk) => { /* Play or save the chunk */ }
+//!         SynthesisEvent::Boundary(boundary) => println!("{} at {:?}", boundary.text, boundary.offset),
+//!         _ => {}
+//!     }
+//! }
+//! ```
+//!
+//! # Configuration files
+//!
+//! [SynthesizerConfig][crate::synthesizer::SynthesizerConfig], [AuthOptions] and [TextOptions] implement serde's
+//! `Serialize` and `Deserialize`, so they can be loaded from the configuration file of an application.
+//! Their `into_owned` methods turn borrowed options into `'static` ones that can be kept in long-lived state.
+//!
+//! ```ignore
+//! #[derive(Deserialize)]
+//! struct AppConfig {
+//!     tts: SynthesizerConfig<'static>,
+//!     text: TextOptions<'static>,
+//! }
+//! let config: AppConfig = toml::from_str(&std::fs::read_to_string("app.toml")?)?;
+//! let state = Arc::new(config.tts);
+//! ```
+//!
+//! With the `profile` feature, the [profiles](crate::profile) of the aspeak CLI, `~/.aspeak.toml` by default,
+//! can be loaded and resolved into the options of a synthesizer.
+//!
+//! ```ignore
+//! let profile = Profile::load_default()?.unwrap_or_default();
+//! let synthesizer = profile.synthesizer_config(profile.mode())?.rest_synthesizer()?;
+//! let audio = synthesizer.synthesize_text(text, &profile.text_options()?).await?;
+//! ```
+//!
 //! # TLS feature flags
 //!
 //! By default, this crate uses `native-tls`. To use other TLS implementations, you can use the following feature flags:
@@ -95,20 +199,39 @@
 //! - `websocket-synthesizer`: Enable the Websocket synthesizer.
 //! - `unified-synthesizer`: Enable the unified synthesizer trait.
 //! - `synthesizers`: Enable all synthesizers.
+//! - `blocking`: Enable the [blocking](crate::blocking) API for synchronous code.
+//! - `profile`: Enable loading the [profiles](crate::profile) of the aspeak CLI.
+//! - `audio`: Enable audio playback and output device selection.
+//! - `transcode`: Enable local resampling, channel conversion, loudness normalization, silence trimming
+//!   and WAV/FLAC encoding.
+//!
+//! The [tags](crate::audio::tag) written into audio files are available with either `audio` or `transcode`.
 
-mod audio;
+pub mod audio;
 mod auth;
+#[cfg(feature = "blocking")]
+pub mod blocking;
 mod constants;
 mod errors;
+pub use errors::RequestDetails;
 #[cfg(feature = "websocket-synthesizer")]
 mod msg;
 #[cfg(feature = "websocket-synthesizer")]
 mod net;
-#[cfg(feature = "python")]
+#[cfg(any(feature = "python", feature = "profile"))]
+#[cfg_attr(not(feature = "python"), allow(dead_code))]
 mod parse;
+#[cfg(feature = "profile")]
+pub mod profile;
+mod proxy;
+pub use proxy::{NoProxy, ProxyError, ProxyErrorKind, ProxyResolver};
 mod ssml;
 pub mod synthesizer;
 pub use synthesizer::*;
+mod timeout;
+pub use timeout::{TimeoutPhase, Timeouts, TimeoutsBuilder};
+mod tls;
+pub use tls::{parse_pinned_public_key, TlsError, TlsErrorKind, TlsOptions, TlsOptionsBuilder};
 mod types;
 mod utils;
 pub mod voice;
@@ -124,7 +247,23 @@ pub fn get_rest_endpoint_by_region(region: &str) -> String {
     format!("https://{region}.tts.speech.microsoft.com/cognitiveservices/v1")
 }
 
-pub use audio::{AudioFormat, AudioFormatParseError, QUALITY_MAP, QUALITY_RANGE_MAP};
+#[cfg(feature = "transcode")]
+pub use audio::transcode::{
+    transcode, Encoding, PcmAudio, TranscodeError, TranscodeErrorKind, TranscodeOptions,
+    TranscodeOptionsBuilder, DEFAULT_PEAK_LIMIT,
+};
+#[cfg(feature = "audio")]
+pub use audio::{
+    default_output_device, list_output_devices, play_borrowed_audio_blocking,
+    play_owned_audio_blocking, play_owned_audio_blocking_with_options, AudioError, AudioErrorKind,
+    PlaybackOptions, PlaybackOptionsBuilder,
+};
+#[cfg(any(feature = "audio", feature = "transcode"))]
+pub use audio::{embed_tags, AudioTags, AudioTagsBuilder, TagError, TagErrorKind};
+pub use audio::{
+    probe, AudioCodec, AudioContainer, AudioFormat, AudioFormatParseError, AudioInfo, ProbeError,
+    ProbeErrorKind, QUALITY_MAP, QUALITY_RANGE_MAP,
+};
 pub use auth::*;
 use phf::phf_map;
 pub use ssml::*;
@@ -10,17 +10,16 @@ use std::{
 use cli::{commands::Command, Cli};
 
 use aspeak::{
-    AudioFormat, SynthesizerConfig, UnifiedSynthesizer, Voice, VoiceListAPIAuth,
-    VoiceListAPIEndpoint, VoiceListAPIError, VoiceListAPIErrorKind, QUALITY_MAP,
+    default_output_device, interpolate_ssml, list_output_devices, AudioFormat, SynthesizerConfig,
+    UnifiedSynthesizer, Voice, VoiceListAPIAuth, VoiceListAPIEndpoint, VoiceListAPIError,
+    VoiceListAPIErrorKind, QUALITY_MAP,
 };
 use clap::Parser;
-use color_eyre::{
-    eyre::{anyhow, eyre},
-    Help,
-};
+use color_eyre::{eyre::eyre, Help};
 use colored::Colorize;
 
 use env_logger::WriteStyle;
+use futures_util::StreamExt;
 use log::debug;
 
 use reqwest::header::HeaderMap;
@@ -29,7 +28,7 @@ use strum::IntoEnumIterator;
 use crate::cli::{
     args::{Color, SynthesizerMode},
     commands::ConfigCommand,
-    config::{Config, EndpointConfig},
+    config::{self, EndpointConfig, Profile},
 };
 
 #[derive(Debug)]
@@ -99,13 +98,21 @@ async fn main() -> color_eyre::eyre::Result<()> {
             let ssml = ssml
                 .ok_or(CliError::Input)
                 .or_else(|_| Cli::process_input_text(&input_args))?;
-            let audio_format =
-                output_args.get_audio_format(config.as_ref().and_then(|c| c.output.as_ref()))?;
-            let callback = Cli::process_output(output_args.output, output_args.overwrite)?;
-            let conf = SynthesizerConfig::new(auth_options, audio_format);
+            let output_config = config.as_ref().and_then(|c| c.output.as_ref());
+            let audio_format = output_args.get_audio_format(output_config)?;
+            let tags = output_args
+                .should_tag(output_config)
+                .then(|| Cli::audio_tags_builder().ssml(&ssml).build());
+            let mut output = Cli::process_output(&output_args, output_config, audio_format, tags)?;
+            let conf = SynthesizerConfig::new(auth_options, audio_format)
+                .with_timeouts(auth.timeouts(config.as_ref().and_then(|c| c.auth.as_ref()))?)
+                .with_tls(auth.tls_options(config.as_ref().and_then(|c| c.auth.as_ref()))?);
             let mut synthesizer = synthesizer_by_mode(conf, mode).await?;
-            let audio_data = synthesizer.process_ssml(&ssml).await?;
-            callback(audio_data)?;
+            let mut audio = synthesizer.process_ssml_stream(&ssml).await?;
+            while let Some(chunk) = audio.next().await {
+                output.write_chunk(&chunk?)?;
+            }
+            output.finish()?;
         }
         Command::Text {
             text_args,
@@ -122,18 +129,32 @@ async fn main() -> color_eyre::eyre::Result<()> {
                 .map(Cow::Borrowed)
                 .ok_or(CliError::Input)
                 .or_else(|_| Cli::process_input_text(&input_args).map(Cow::Owned))?;
-            let audio_format =
-                output_args.get_audio_format(config.as_ref().and_then(|c| c.output.as_ref()))?;
-            let callback = Cli::process_output(output_args.output, output_args.overwrite)?;
-            let conf = SynthesizerConfig::new(auth_options, audio_format);
-            let mut synthesizer = synthesizer_by_mode(conf, mode).await?;
+            let output_config = config.as_ref().and_then(|c| c.output.as_ref());
+            let audio_format = output_args.get_audio_format(output_config)?;
             let options = &Cli::process_text_options(
                 &text_args,
                 config.as_ref().and_then(|c| c.text.as_ref()),
             )?;
-            let result = synthesizer.process_text(&text, options).await;
-            let audio_data = result?;
-            callback(audio_data)?;
+            let tags = if output_args.should_tag(output_config) {
+                Some(
+                    Cli::audio_tags_builder()
+                        .text_options(options)
+                        .ssml(&interpolate_ssml(&*text, options)?)
+                        .build(),
+                )
+            } else {
+                None
+            };
+            let mut output = Cli::process_output(&output_args, output_config, audio_format, tags)?;
+            let conf = SynthesizerConfig::new(auth_options, audio_format)
+                .with_timeouts(auth.timeouts(config.as_ref().and_then(|c| c.auth.as_ref()))?)
+                .with_tls(auth.tls_options(config.as_ref().and_then(|c| c.auth.as_ref()))?);
+            let mut synthesizer = synthesizer_by_mode(conf, mode).await?;
+            let mut audio = synthesizer.process_text_stream(&text, options).await?;
+            while let Some(chunk) = audio.next().await {
+                output.write_chunk(&chunk?)?;
+            }
+            output.finish()?;
         }
         Command::ListVoices {
             ref voice,
@@ -172,18 +193,22 @@ async fn main() -> color_eyre::eyre::Result<()> {
                         .with_note(|| "The default voice list API that is used in aspeak v4 has been shutdown and is no longer available.")
                         .with_suggestion(|| "You can still use the list-voices command by specifying a region(authentication needed) or a custom voice list API url.")
                 )?;
+            let timeouts = auth.timeouts(config.as_ref().and_then(|c| c.auth.as_ref()))?;
+            let tls = auth.tls_options(config.as_ref().and_then(|c| c.auth.as_ref()))?;
             let auth = match (auth_options.key(), auth_options.token()) {
                 (_, Some(token)) => Some(VoiceListAPIAuth::AuthToken(token)),
                 (Some(key), None) => Some(VoiceListAPIAuth::SubscriptionKey(key)),
                 (None, None) => None,
             };
-            let voices_result = Voice::request_available_voices_with_additional_headers(
+            let voices_result = Voice::request_available_voices_with_options(
                 VoiceListAPIEndpoint::Url(url.as_ref()),
                 auth,
                 auth_options.proxy(),
                 Some(HeaderMap::from_iter(
                     auth_options.headers().iter().map(Clone::clone),
                 )),
+                &timeouts,
+                &tls,
             )
             .await;
             let voices = if let Err(VoiceListAPIError {
@@ -229,25 +254,33 @@ async fn main() -> color_eyre::eyre::Result<()> {
                 println!("{}", Into::<&str>::into(format));
             }
         }
+        Command::ListDevices => {
+            let default_device = default_output_device()?;
+            for device in list_output_devices()? {
+                if Some(&device) == default_device.as_ref() {
+                    println!("{} {}", device, "(default)".green());
+                } else {
+                    println!("{device}");
+                }
+            }
+        }
         Command::Config { command } => match command {
             ConfigCommand::Edit => {
-                let path = Config::default_location()?;
+                let path = Profile::default_location()?;
                 if !path.exists() {
-                    Config::initialize(path.as_path(), false)?;
+                    config::initialize(path.as_path(), false)?;
                 }
                 open::that(path)?;
             }
             ConfigCommand::Init { path, overwrite } => {
-                Config::initialize(
-                    path.map(PathBuf::from)
-                        .ok_or(anyhow!("Unreachable code!"))
-                        .or_else(|_| Config::default_location())?
-                        .as_path(),
-                    overwrite,
-                )?;
+                let path = match path {
+                    Some(path) => PathBuf::from(path),
+                    None => Profile::default_location()?,
+                };
+                config::initialize(path.as_path(), overwrite)?;
             }
             ConfigCommand::Where => {
-                println!("{}", Config::default_location()?.display());
+                println!("{}", Profile::default_location()?.display());
             }
         },
     }
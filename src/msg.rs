@@ -6,80 +6,175 @@ use std::{
 };
 
 use log::trace;
+use strum::AsRefStr;
 
 use tokio_tungstenite::{tungstenite::protocol::CloseFrame, tungstenite::Message};
 
-#[derive(Debug, Clone, Copy)]
+/// The maximum number of bytes of an invalid message that is kept in a [`ParseError`].
+const MAX_PREVIEW_LEN: usize = 256;
+
+#[derive(Debug, Clone)]
 pub(crate) enum WebSocketMessage<'a> {
-    TurnStart,
-    TurnEnd,
-    #[allow(unused)]
+    TurnStart {
+        headers: MessageHeaders<'a>,
+    },
+    TurnEnd {
+        headers: MessageHeaders<'a>,
+    },
     Response {
+        headers: MessageHeaders<'a>,
         body: &'a str,
     },
     Audio {
+        headers: MessageHeaders<'a>,
         data: &'a [u8],
     },
+    AudioMetadata {
+        headers: MessageHeaders<'a>,
+        body: &'a str,
+    },
     Close(Option<&'a CloseFrame<'a>>),
     Ping,
     Pong,
 }
 
+impl<'a> WebSocketMessage<'a> {
+    /// The headers of a message sent by the speech service
+    pub fn headers(&self) -> Option<&MessageHeaders<'a>> {
+        match self {
+            WebSocketMessage::TurnStart { headers }
+            | WebSocketMessage::TurnEnd { headers }
+            | WebSocketMessage::Response { headers, .. }
+            | WebSocketMessage::Audio { headers, .. }
+            | WebSocketMessage::AudioMetadata { headers, .. } => Some(headers),
+            WebSocketMessage::Close(_) | WebSocketMessage::Ping | WebSocketMessage::Pong => None,
+        }
+    }
+}
+
+/// The headers of a message sent by the speech service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MessageHeaders<'a> {
+    /// The `Path` header, which tells the kind of the message
+    pub path: &'a str,
+    /// The `X-RequestId` header
+    pub request_id: Option<&'a str>,
+    /// The `Content-Type` header
+    pub content_type: Option<&'a str>,
+    /// All other headers, in the order they appear in the message
+    pub other: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> MessageHeaders<'a> {
+    /// Parse headers of the form `Name:Value`, separated by CRLF.
+    /// Header names are case-insensitive and empty lines are ignored.
+    pub fn parse(text: &'a str) -> Result<Self, ParseErrorKind> {
+        let mut path = None;
+        let mut request_id = None;
+        let mut content_type = None;
+        let mut other = Vec::new();
+        for line in text.split("\r\n").filter(|line| !line.is_empty()) {
+            trace!("Found header {line}");
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(ParseErrorKind::MalformedHeader)?;
+            let (name, value) = (name.trim(), value.trim());
+            let slot = if name.eq_ignore_ascii_case("Path") {
+                &mut path
+            } else if name.eq_ignore_ascii_case("X-RequestId") {
+                &mut request_id
+            } else if name.eq_ignore_ascii_case("Content-Type") {
+                &mut content_type
+            } else {
+                other.push((name, value));
+                continue;
+            };
+            if slot.replace(value).is_some() {
+                return Err(ParseErrorKind::DuplicateHeader(name.to_string()));
+            }
+        }
+        Ok(Self {
+            path: path.ok_or(ParseErrorKind::MissingPath)?,
+            request_id,
+            content_type,
+            other,
+        })
+    }
+}
+
+/// Split a binary frame into its headers and its payload.
+///
+/// A binary frame starts with the length of the headers as a big-endian `u16`,
+/// followed by the headers and the payload.
+fn split_binary_frame(data: &[u8]) -> Result<(MessageHeaders<'_>, &[u8]), ParseErrorKind> {
+    if data.len() < std::mem::size_of::<u16>() {
+        return Err(ParseErrorKind::MissingHeaderLength);
+    }
+    let (len_bytes, rest) = data.split_at(std::mem::size_of::<u16>());
+    let header_len = u16::from_be_bytes([len_bytes[0], len_bytes[1]]) as usize;
+    if header_len > rest.len() {
+        return Err(ParseErrorKind::HeaderOutOfBounds {
+            declared: header_len,
+            available: rest.len(),
+        });
+    }
+    let (header, payload) = rest.split_at(header_len);
+    let header = str::from_utf8(header).map_err(|_| ParseErrorKind::InvalidUtf8)?;
+    Ok((MessageHeaders::parse(header)?, payload))
+}
+
+/// Split a text frame into its headers and its body, separated by an empty line.
+fn split_text_frame(text: &str) -> Result<(MessageHeaders<'_>, &str), ParseErrorKind> {
+    let (header, body) = text
+        .split_once("\r\n\r\n")
+        .ok_or(ParseErrorKind::MissingHeaderTerminator)?;
+    Ok((MessageHeaders::parse(header)?, body))
+}
+
 impl<'a> TryFrom<&'a Message> for WebSocketMessage<'a> {
     type Error = ParseError;
 
     fn try_from(value: &'a Message) -> Result<Self, Self::Error> {
         Ok(match *value {
-            Message::Binary(ref data) => {
-                let (int_bytes, rest) = data.split_at(std::mem::size_of::<u16>());
-                let header_len = u16::from_be_bytes([int_bytes[0], int_bytes[1]]) as usize;
-                let header = str::from_utf8(&rest[..header_len]).unwrap();
-                let is_audio = {
-                    let headers = header.split("\r\n");
-                    let mut is_audio = false;
-                    for header in headers {
-                        trace!("Found header {header}");
-                        if header.starts_with("Path") && header.ends_with("audio") {
-                            is_audio = true;
-                            break;
-                        }
-                    }
-                    is_audio
+            Message::Binary(ref frame) => {
+                let err_construct = |kind| {
+                    let preview = &frame[..frame.len().min(MAX_PREVIEW_LEN)];
+                    ParseError::new(kind, &String::from_utf8_lossy(preview))
                 };
-                if !is_audio {
-                    return Err(ParseError::new_bare(header.to_string()));
-                }
-                WebSocketMessage::Audio {
-                    data: &rest[header_len..],
+                let (headers, data) = split_binary_frame(frame).map_err(err_construct)?;
+                if headers.path != "audio" {
+                    return Err(err_construct(ParseErrorKind::UnexpectedPath(
+                        headers.path.to_string(),
+                    ))
+                    .with_request_id(headers.request_id));
                 }
+                WebSocketMessage::Audio { headers, data }
             }
             Message::Text(ref text) => {
-                let err_construct = || ParseError::new_bare(text.to_string());
-                let (header_text, body) = text.split_once("\r\n\r\n").ok_or_else(err_construct)?;
-                let mut result = None;
-                for header in header_text.split("\r\n") {
-                    trace!("Found header {header}");
-                    let (k, v) = header.split_once(':').ok_or_else(err_construct)?;
-                    if k == "Path" {
-                        match v.trim() {
-                            "turn.end" => result = Some(WebSocketMessage::TurnEnd),
-                            "turn.start" => result = Some(WebSocketMessage::TurnStart),
-                            "response" => result = Some(WebSocketMessage::Response { body }),
-                            _ => break,
-                        }
+                let (headers, body) =
+                    split_text_frame(text).map_err(|kind| ParseError::new(kind, text))?;
+                match headers.path {
+                    "turn.end" => WebSocketMessage::TurnEnd { headers },
+                    "turn.start" => WebSocketMessage::TurnStart { headers },
+                    "response" => WebSocketMessage::Response { headers, body },
+                    "audio.metadata" => WebSocketMessage::AudioMetadata { headers, body },
+                    path => {
+                        return Err(ParseError::new(
+                            ParseErrorKind::UnexpectedPath(path.to_string()),
+                            text,
+                        )
+                        .with_request_id(headers.request_id))
                     }
                 }
-                result.ok_or_else(err_construct)?
             }
             Message::Close(ref frame) => WebSocketMessage::Close(frame.as_ref()),
             Message::Ping(_) => WebSocketMessage::Ping,
             Message::Pong(_) => WebSocketMessage::Pong,
             ref msg => {
-                return Err(ParseError {
-                    reason: "Neither binary nor text",
-                    msg: format!("{:?}", msg),
-                    source: None,
-                })
+                return Err(ParseError::new(
+                    ParseErrorKind::UnsupportedFrame,
+                    &format!("{:?}", msg),
+                ))
             }
         })
     }
@@ -88,28 +183,51 @@ impl<'a> TryFrom<&'a Message> for WebSocketMessage<'a> {
 #[derive(Debug)]
 #[non_exhaustive]
 pub struct ParseError {
-    pub reason: &'static str,
+    pub kind: ParseErrorKind,
+    /// The beginning of the message that could not be parsed
     pub msg: String,
+    /// The `X-RequestId` of a message whose headers could be parsed
+    pub(crate) request_id: Option<String>,
     pub(crate) source: Option<anyhow::Error>,
 }
 
 impl ParseError {
-    pub(crate) fn new_bare(msg: String) -> Self {
+    pub(crate) fn new(kind: ParseErrorKind, msg: &str) -> Self {
+        let mut end = msg.len().min(MAX_PREVIEW_LEN);
+        while !msg.is_char_boundary(end) {
+            end -= 1;
+        }
         Self {
-            reason: "unknown",
-            msg,
+            kind,
+            msg: msg[..end].to_string(),
+            request_id: None,
             source: None,
         }
     }
+
+    fn with_request_id(mut self, request_id: Option<&str>) -> Self {
+        self.request_id = request_id.map(ToString::to_string);
+        self
+    }
 }
 
 impl Display for ParseError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "msg parse error: reason {}, msg is {}",
-            self.reason, self.msg
-        )
+        use ParseErrorKind::*;
+        write!(f, "msg parse error: ")?;
+        match &self.kind {
+            HeaderOutOfBounds {
+                declared,
+                available,
+            } => write!(
+                f,
+                "the headers are {declared} bytes long but only {available} bytes follow"
+            )?,
+            DuplicateHeader(name) => write!(f, "duplicate header {name}")?,
+            UnexpectedPath(path) => write!(f, "unexpected path {path}")?,
+            kind => write!(f, "{}", kind.as_ref())?,
+        }
+        write!(f, ", msg is {:?}", self.msg)
     }
 }
 
@@ -118,3 +236,311 @@ impl Error for ParseError {
         self.source.as_ref().map(|e| e.as_ref() as _)
     }
 }
+
+#[derive(Debug, PartialEq, Clone, AsRefStr)]
+#[non_exhaustive]
+pub enum ParseErrorKind {
+    /// The binary frame is too short to contain the length of the headers.
+    #[strum(serialize = "the binary frame is too short to contain the length of the headers")]
+    MissingHeaderLength,
+    /// The length of the headers exceeds the size of the binary frame.
+    HeaderOutOfBounds { declared: usize, available: usize },
+    /// The headers are not valid UTF-8.
+    #[strum(serialize = "the headers are not valid UTF-8")]
+    InvalidUtf8,
+    /// The headers of the text frame are not followed by an empty line.
+    #[strum(serialize = "the headers are not followed by an empty line")]
+    MissingHeaderTerminator,
+    /// A header is not of the form `Name:Value`.
+    #[strum(serialize = "a header is not of the form Name:Value")]
+    MalformedHeader,
+    /// A header that may only appear once appears multiple times.
+    DuplicateHeader(String),
+    /// The message has no `Path` header.
+    #[strum(serialize = "the message has no Path header")]
+    MissingPath,
+    /// The `Path` header has a value that aspeak does not expect.
+    UnexpectedPath(String),
+    /// The frame is neither a binary, text, close, ping nor pong frame.
+    #[strum(serialize = "the frame is neither binary nor text")]
+    UnsupportedFrame,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary(header: &str, payload: &[u8]) -> Message {
+        let mut frame = (header.len() as u16).to_be_bytes().to_vec();
+        frame.extend_from_slice(header.as_bytes());
+        frame.extend_from_slice(payload);
+        Message::Binary(frame)
+    }
+
+    fn text(text: &str) -> Message {
+        Message::Text(text.to_string())
+    }
+
+    fn parse_error(msg: &Message) -> ParseErrorKind {
+        WebSocketMessage::try_from(msg).unwrap_err().kind
+    }
+
+    #[test]
+    fn audio_frame() {
+        let msg = binary(
+            "X-RequestId:abc\r\nContent-Type:audio/mpeg\r\nPath:audio\r\n",
+            &[1, 2, 3],
+        );
+        let WebSocketMessage::Audio { headers, data } = WebSocketMessage::try_from(&msg).unwrap()
+        else {
+            panic!("not an audio message");
+        };
+        assert_eq!(headers.path, "audio");
+        assert_eq!(headers.request_id, Some("abc"));
+        assert_eq!(headers.content_type, Some("audio/mpeg"));
+        assert_eq!(data, [1, 2, 3]);
+    }
+
+    #[test]
+    fn text_frames() {
+        let msg = text("path: turn.start\r\nx-requestid: abc\r\nX-Custom: a:b\r\n\r\n{}");
+        let WebSocketMessage::TurnStart { headers } = WebSocketMessage::try_from(&msg).unwrap()
+        else {
+            panic!("not a turn.start message");
+        };
+        assert_eq!(headers.request_id, Some("abc"));
+        assert_eq!(headers.other, [("X-Custom", "a:b")]);
+        let msg = text("Path:audio.metadata\r\n\r\n{\"Metadata\":[]}");
+        assert!(matches!(
+            WebSocketMessage::try_from(&msg).unwrap(),
+            WebSocketMessage::AudioMetadata {
+                body: "{\"Metadata\":[]}",
+                ..
+            }
+        ));
+        let msg = text("Path:turn.end\r\n\r\n");
+        assert!(matches!(
+            WebSocketMessage::try_from(&msg).unwrap(),
+            WebSocketMessage::TurnEnd { .. }
+        ));
+    }
+
+    #[test]
+    fn binary_frame_shorter_than_header_length() {
+        for frame in [vec![], vec![0]] {
+            assert_eq!(
+                parse_error(&Message::Binary(frame)),
+                ParseErrorKind::MissingHeaderLength
+            );
+        }
+        // An empty header is not an error of the frame, but it has no path
+        assert_eq!(
+            parse_error(&Message::Binary(vec![0, 0])),
+            ParseErrorKind::MissingPath
+        );
+    }
+
+    #[test]
+    fn header_length_larger_than_frame() {
+        let mut frame = vec![0x01, 0x00];
+        frame.extend_from_slice(b"Path:audio\r\n");
+        assert_eq!(
+            parse_error(&Message::Binary(frame)),
+            ParseErrorKind::HeaderOutOfBounds {
+                declared: 256,
+                available: 12
+            }
+        );
+        assert_eq!(
+            parse_error(&Message::Binary(vec![0xff, 0xff])),
+            ParseErrorKind::HeaderOutOfBounds {
+                declared: 65535,
+                available: 0
+            }
+        );
+    }
+
+    #[test]
+    fn non_utf8_header() {
+        let mut frame = vec![0x00, 0x0c];
+        frame.extend_from_slice(b"Path:\xffaudio\r\n");
+        assert_eq!(
+            parse_error(&Message::Binary(frame)),
+            ParseErrorKind::InvalidUtf8
+        );
+        // Splitting a multi-byte character between the header and the payload
+        let mut frame = vec![0x00, 0x0b];
+        frame.extend_from_slice("Path:audio\u{e9}".as_bytes());
+        assert_eq!(
+            parse_error(&Message::Binary(frame)),
+            ParseErrorKind::InvalidUtf8
+        );
+    }
+
+    #[test]
+    fn missing_header_terminator() {
+        for msg in [
+            "",
+            "Path:turn.end",
+            "Path:turn.end\r\n",
+            "Path:response\n\n{}",
+        ] {
+            assert_eq!(
+                parse_error(&text(msg)),
+                ParseErrorKind::MissingHeaderTerminator
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_and_missing_headers() {
+        assert_eq!(
+            parse_error(&text("Path turn.end\r\n\r\n")),
+            ParseErrorKind::MalformedHeader
+        );
+        assert_eq!(
+            parse_error(&text("X-RequestId:abc\r\n\r\n")),
+            ParseErrorKind::MissingPath
+        );
+        assert_eq!(
+            parse_error(&binary("X-RequestId:abc\r\n", &[])),
+            ParseErrorKind::MissingPath
+        );
+    }
+
+    #[test]
+    fn duplicate_headers() {
+        assert_eq!(
+            parse_error(&text("Path:turn.end\r\npath:turn.start\r\n\r\n")),
+            ParseErrorKind::DuplicateHeader("path".to_string())
+        );
+        assert_eq!(
+            parse_error(&binary(
+                "X-RequestId:a\r\nPath:audio\r\nX-REQUESTID:b\r\n",
+                &[]
+            )),
+            ParseErrorKind::DuplicateHeader("X-REQUESTID".to_string())
+        );
+        // Other headers may be repeated
+        let msg = text("Path:turn.end\r\nX-Custom:a\r\nX-Custom:b\r\n\r\n");
+        let headers = WebSocketMessage::try_from(&msg).unwrap().headers().cloned();
+        assert_eq!(
+            headers.unwrap().other,
+            [("X-Custom", "a"), ("X-Custom", "b")]
+        );
+    }
+
+    #[test]
+    fn unknown_path() {
+        assert_eq!(
+            parse_error(&text("Path:speech.phrase\r\n\r\n")),
+            ParseErrorKind::UnexpectedPath("speech.phrase".to_string())
+        );
+        // Text messages are not accepted on binary frames and vice versa
+        assert_eq!(
+            parse_error(&binary("Path:turn.end\r\n", &[])),
+            ParseErrorKind::UnexpectedPath("turn.end".to_string())
+        );
+        assert_eq!(
+            parse_error(&text("Path:audio\r\n\r\n")),
+            ParseErrorKind::UnexpectedPath("audio".to_string())
+        );
+    }
+
+    #[test]
+    fn unknown_path_keeps_request_id() {
+        let err =
+            WebSocketMessage::try_from(&text("Path:speech.phrase\r\nX-RequestId:abc\r\n\r\n"))
+                .unwrap_err();
+        assert_eq!(err.request_id.as_deref(), Some("abc"));
+        let err = WebSocketMessage::try_from(&binary("Path:turn.end\r\nX-RequestId:abc\r\n", &[]))
+            .unwrap_err();
+        assert_eq!(err.request_id.as_deref(), Some("abc"));
+        // The request is unknown if the headers cannot be parsed
+        let err = WebSocketMessage::try_from(&text("X-RequestId:abc\r\n\r\n")).unwrap_err();
+        assert_eq!(err.request_id, None);
+    }
+
+    #[test]
+    fn error_preview_is_truncated_at_char_boundary() {
+        let msg = "\u{e9}".repeat(MAX_PREVIEW_LEN);
+        let err = WebSocketMessage::try_from(&text(&msg)).unwrap_err();
+        assert_eq!(err.msg.len(), MAX_PREVIEW_LEN);
+        let err = WebSocketMessage::try_from(&Message::Binary(msg.into_bytes())).unwrap_err();
+        assert!(err.msg.len() <= MAX_PREVIEW_LEN);
+    }
+
+    /// A xorshift generator, so that the random frames are the same in every run
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    /// Build a frame from pieces of valid messages and random bytes
+    fn random_frame(rng: &mut XorShift) -> Vec<u8> {
+        const PIECES: &[&[u8]] = &[
+            b"Path",
+            b"X-RequestId",
+            b"Content-Type",
+            b":",
+            b"\r\n",
+            b"\r\n\r\n",
+            b"audio",
+            b"turn.end",
+            b"response",
+            b" ",
+            "\u{e9}".as_bytes(),
+            b"\xff",
+        ];
+        let mut frame = Vec::new();
+        if rng.below(2) == 0 {
+            // A header length that is often close to the length of the frame
+            frame.extend_from_slice(&(rng.below(64) as u16).to_be_bytes());
+        }
+        for _ in 0..rng.below(32) {
+            if rng.below(3) == 0 {
+                frame.push(rng.next() as u8);
+            } else {
+                frame.extend_from_slice(PIECES[rng.below(PIECES.len())]);
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn arbitrary_frames_do_not_panic() {
+        let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+        for _ in 0..100_000 {
+            let frame = random_frame(&mut rng);
+            let text_frame = String::from_utf8_lossy(&frame).into_owned();
+            for msg in [Message::Binary(frame), Message::Text(text_frame)] {
+                if let Err(e) = WebSocketMessage::try_from(&msg) {
+                    assert!(e.msg.len() <= MAX_PREVIEW_LEN);
+                    let _ = e.to_string();
+                }
+            }
+        }
+        // Frames longer than the preview
+        for _ in 0..1_000 {
+            let frame: Vec<u8> = (0..rng.below(4 * MAX_PREVIEW_LEN))
+                .map(|_| rng.next() as u8)
+                .collect();
+            let text_frame = String::from_utf8_lossy(&frame).into_owned();
+            for msg in [Message::Binary(frame), Message::Text(text_frame)] {
+                if let Err(e) = WebSocketMessage::try_from(&msg) {
+                    assert!(e.msg.len() <= MAX_PREVIEW_LEN);
+                }
+            }
+        }
+    }
+}
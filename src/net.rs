@@ -1,22 +1,85 @@
 use std::{
+    borrow::Cow,
     pin::Pin,
     task::{Context, Poll},
 };
 
-use hyper::Uri;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hyper::{header::PROXY_AUTHORIZATION, StatusCode, Uri};
 use log::debug;
+use percent_encoding::percent_decode_str;
 use reqwest::Url;
 use tokio::{
     io::{AsyncRead, AsyncWrite, ReadBuf},
-    net::TcpStream,
+    net::{lookup_host, TcpStream, ToSocketAddrs},
 };
 
-use tokio_socks::tcp::Socks5Stream;
+use tokio_socks::{tcp::Socks5Stream, TargetAddr};
 use tokio_tungstenite::{tungstenite::client::IntoClientRequest, MaybeTlsStream, WebSocketStream};
 
 use crate::errors::{ConnectError, ConnectErrorKind};
+use crate::timeout::timeout;
+#[cfg(any(feature = "native-tls", feature = "__rustls"))]
+use crate::tls::TlsConnector;
+use crate::{TimeoutPhase, Timeouts, TlsOptions};
 
-pub(crate) type WsStream = WebSocketStream<MaybeTlsStream<MaybeSocks5Stream<TcpStream>>>;
+pub(crate) type WsStream = WebSocketStream<MaybeTlsStream<MaybeSocks5Stream<ProxyStream>>>;
+
+/// A connection to a proxy server or the service, optionally secured by TLS
+#[derive(Debug)]
+pub(crate) enum ProxyStream {
+    Plain(TcpStream),
+    #[cfg(feature = "native-tls")]
+    NativeTls(tokio_native_tls::TlsStream<TcpStream>),
+    #[cfg(feature = "__rustls")]
+    Rustls(Box<tokio_rustls::client::TlsStream<TcpStream>>),
+}
+
+macro_rules! dispatch_proxy_stream {
+    ($self:ident, $s:ident => $body:expr) => {
+        match $self.get_mut() {
+            ProxyStream::Plain($s) => $body,
+            #[cfg(feature = "native-tls")]
+            ProxyStream::NativeTls($s) => $body,
+            #[cfg(feature = "__rustls")]
+            ProxyStream::Rustls($s) => $body,
+        }
+    };
+}
+
+impl AsyncRead for ProxyStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        dispatch_proxy_stream!(self, s => Pin::new(s).poll_read(cx, buf))
+    }
+}
+
+impl AsyncWrite for ProxyStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::result::Result<usize, std::io::Error>> {
+        dispatch_proxy_stream!(self, s => Pin::new(s).poll_write(cx, buf))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::result::Result<(), std::io::Error>> {
+        dispatch_proxy_stream!(self, s => Pin::new(s).poll_flush(cx))
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::result::Result<(), std::io::Error>> {
+        dispatch_proxy_stream!(self, s => Pin::new(s).poll_shutdown(cx))
+    }
+}
 
 #[derive(Debug)]
 pub(crate) enum MaybeSocks5Stream<S: AsyncRead + AsyncWrite + Unpin> {
@@ -98,6 +161,7 @@ macro_rules! impl_uri_ext {
                     (Some(port), _) => port,
                     (None, Some("wss") | Some("https")) => 443,
                     (None, Some("ws") | Some("http")) => 80,
+                    (None, Some("socks5") | Some("socks5h")) => 1080,
                     x => {
                         return Err(ConnectError {
                             kind: ConnectErrorKind::UnsupportedScheme(
@@ -120,7 +184,49 @@ macro_rules! impl_uri_ext {
 impl_uri_ext!(Uri, port_u16, scheme_str);
 impl_uri_ext!(Url, port, scheme);
 
-pub(crate) async fn connect_directly<R>(request: R) -> Result<WsStream, ConnectError>
+/// Open a TCP connection to `addr`, giving up after the connect timeout
+async fn connect_tcp(
+    addr: impl ToSocketAddrs,
+    timeouts: &Timeouts,
+) -> Result<TcpStream, ConnectError> {
+    Ok(timeout(timeouts, TimeoutPhase::Connect, TcpStream::connect(addr)).await??)
+}
+
+/// Perform the TLS handshake with the service and upgrade the connection to a websocket
+async fn websocket_handshake(
+    request: tokio_tungstenite::tungstenite::handshake::client::Request,
+    stream: MaybeSocks5Stream<ProxyStream>,
+    timeouts: &Timeouts,
+    tls: &TlsOptions,
+) -> Result<WsStream, ConnectError> {
+    #[cfg(any(feature = "native-tls", feature = "__rustls"))]
+    let handshake = {
+        // Let tokio-tungstenite use its default TLS connector unless it is customized
+        let connector = if tls.is_empty() {
+            None
+        } else {
+            Some(tls.connector()?.into_tungstenite())
+        };
+        tokio_tungstenite::client_async_tls_with_config(request, stream, None, connector)
+    };
+    #[cfg(not(any(feature = "native-tls", feature = "__rustls")))]
+    let handshake = {
+        // Without a TLS implementation, only ws:// endpoints can be connected to
+        if request.uri().scheme_str() == Some("wss") {
+            tls.connector().map(|connector| match connector {})?;
+        }
+        tokio_tungstenite::client_async_with_config(request, MaybeTlsStream::Plain(stream), None)
+    };
+    Ok(timeout(timeouts, TimeoutPhase::TlsHandshake, handshake)
+        .await??
+        .0)
+}
+
+pub(crate) async fn connect_directly<R>(
+    request: R,
+    timeouts: &Timeouts,
+    tls: &TlsOptions,
+) -> Result<WsStream, ConnectError>
 where
     R: IntoClientRequest + Unpin,
 {
@@ -129,59 +235,183 @@ where
         source: Some(e.into()),
     })?;
     let addr = request.uri().host_colon_port()?;
-    let try_socket = TcpStream::connect(addr).await?;
-    let socket = MaybeSocks5Stream::Plain(try_socket);
-    Ok(tokio_tungstenite::client_async_tls(request, socket)
-        .await?
-        .0)
+    let try_socket = connect_tcp(addr, timeouts).await?;
+    let socket = MaybeSocks5Stream::Plain(ProxyStream::Plain(try_socket));
+    websocket_handshake(request, socket, timeouts, tls).await
+}
+
+/// The percent-decoded username and password in the userinfo of `proxy_addr`
+fn proxy_credentials(proxy_addr: &Url) -> Option<(String, String)> {
+    if proxy_addr.username().is_empty() && proxy_addr.password().is_none() {
+        return None;
+    }
+    let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+    Some((
+        decode(proxy_addr.username()),
+        decode(proxy_addr.password().unwrap_or_default()),
+    ))
 }
 
+/// `proxy_addr` without the password, for logging
+fn redacted(proxy_addr: &Url) -> Cow<'_, Url> {
+    if proxy_addr.password().is_none() {
+        return Cow::Borrowed(proxy_addr);
+    }
+    let mut redacted = proxy_addr.clone();
+    let _ = redacted.set_password(Some("***"));
+    Cow::Owned(redacted)
+}
+
+#[cfg(any(feature = "native-tls", feature = "__rustls"))]
+fn tls_error(e: impl Into<anyhow::Error>) -> ConnectError {
+    ConnectError {
+        kind: ConnectErrorKind::Tls,
+        source: Some(e.into()),
+    }
+}
+
+/// Secure the connection to a proxy server with TLS
+async fn connect_tls(
+    stream: TcpStream,
+    domain: &str,
+    tls: &TlsOptions,
+) -> Result<ProxyStream, ConnectError> {
+    #[cfg(not(any(feature = "native-tls", feature = "__rustls")))]
+    let _ = (stream, domain);
+    match tls.for_proxy().connector()? {
+        #[cfg(feature = "native-tls")]
+        TlsConnector::NativeTls(connector) => {
+            let stream = tokio_native_tls::TlsConnector::from(connector)
+                .connect(domain, stream)
+                .await
+                .map_err(tls_error)?;
+            Ok(ProxyStream::NativeTls(stream))
+        }
+        #[cfg(feature = "__rustls")]
+        TlsConnector::Rustls(config) => {
+            let domain = rustls::ServerName::try_from(domain).map_err(tls_error)?;
+            let stream = tokio_rustls::TlsConnector::from(std::sync::Arc::new(config))
+                .connect(domain, stream)
+                .await
+                .map_err(tls_error)?;
+            Ok(ProxyStream::Rustls(Box::new(stream)))
+        }
+    }
+}
+
+/// Connect to the service through a SOCKS5 proxy.
+///
+/// The `socks5h` scheme lets the proxy resolve the host name of the service,
+/// while it is resolved locally for the `socks5` scheme.
+/// Credentials in the proxy url are used for username/password authentication.
 pub(crate) async fn connect_via_socks5_proxy(
     ws_req: tokio_tungstenite::tungstenite::handshake::client::Request,
     proxy_addr: &Url,
+    timeouts: &Timeouts,
+    tls: &TlsOptions,
 ) -> Result<WsStream, ConnectError> {
-    debug!("Using socks5 proxy: {proxy_addr}");
-    let proxy_stream = MaybeSocks5Stream::Socks5Stream(
-        Socks5Stream::connect(
-            proxy_addr.host_and_port()?,
-            (
-                ws_req
-                    .uri()
-                    .host()
-                    .expect("expected to have uri host")
-                    .to_string(),
-                ws_req.uri().port_u16().unwrap_or(443),
-            ),
-        )
-        .await?,
-    );
+    debug!("Using socks5 proxy: {}", redacted(proxy_addr));
+    let (host, port) = ws_req.uri().host_and_port()?;
+    let target = if proxy_addr.scheme() == "socks5h" {
+        TargetAddr::Domain(Cow::Owned(host.to_string()), port)
+    } else {
+        let addr = timeout(timeouts, TimeoutPhase::Connect, lookup_host((host, port)))
+            .await??
+            .next()
+            .ok_or_else(|| ConnectError {
+                kind: ConnectErrorKind::BadUrl(ws_req.uri().to_string()),
+                source: Some(anyhow::anyhow!("failed to resolve {host}")),
+            })?;
+        TargetAddr::Ip(addr)
+    };
+    let socket = ProxyStream::Plain(connect_tcp(proxy_addr.host_colon_port()?, timeouts).await?);
+    let proxy_stream = timeout(timeouts, TimeoutPhase::Connect, async {
+        match proxy_credentials(proxy_addr) {
+            Some((username, password)) => {
+                Socks5Stream::connect_with_password_and_socket(socket, target, &username, &password)
+                    .await
+            }
+            None => Socks5Stream::connect_with_socket(socket, target).await,
+        }
+    })
+    .await?
+    .map_err(|e| match e {
+        tokio_socks::Error::PasswordAuthFailure(_)
+        | tokio_socks::Error::NoAcceptableAuthMethods => ConnectError {
+            kind: ConnectErrorKind::ProxyAuthentication,
+            source: Some(e.into()),
+        },
+        e => e.into(),
+    })?;
     debug!("Connected to socks5 proxy!");
-    Ok(tokio_tungstenite::client_async_tls(ws_req, proxy_stream)
-        .await?
-        .0)
+    websocket_handshake(
+        ws_req,
+        MaybeSocks5Stream::Socks5Stream(proxy_stream),
+        timeouts,
+        tls,
+    )
+    .await
 }
 
+/// Connect to the service through an HTTP proxy with the CONNECT method.
+///
+/// The connection to the proxy is secured by TLS for the `https` scheme,
+/// and credentials in the proxy url are sent with Basic authentication.
 pub(crate) async fn connect_via_http_proxy(
     ws_req: tokio_tungstenite::tungstenite::handshake::client::Request,
     proxy_addr: &Url,
+    timeouts: &Timeouts,
+    tls: &TlsOptions,
 ) -> Result<WsStream, ConnectError> {
-    debug!("Using http proxy: {proxy_addr}");
+    debug!("Using http proxy: {}", redacted(proxy_addr));
     let authority = ws_req.uri().host_colon_port()?;
     let proxy_server = proxy_addr.host_colon_port()?;
-    let stream = TcpStream::connect(proxy_server).await?;
+    let stream = connect_tcp(proxy_server, timeouts).await?;
+    let stream = if proxy_addr.scheme() == "https" {
+        let (proxy_host, _) = proxy_addr.host_and_port()?;
+        timeout(
+            timeouts,
+            TimeoutPhase::TlsHandshake,
+            connect_tls(stream, proxy_host, tls),
+        )
+        .await??
+    } else {
+        ProxyStream::Plain(stream)
+    };
 
     let (mut request_sender, conn) = hyper::client::conn::handshake(stream).await?;
 
     let conn = tokio::spawn(conn.without_shutdown());
-    let connect_req = hyper::Request::connect(&authority)
+    let mut connect_req = hyper::Request::connect(&authority);
+    if let Some((username, password)) = proxy_credentials(proxy_addr) {
+        connect_req = connect_req.header(
+            PROXY_AUTHORIZATION,
+            format!("Basic {}", BASE64.encode(format!("{username}:{password}"))),
+        );
+    }
+    let connect_req = connect_req
         .body(hyper::Body::empty())
         .map_err(|e| ConnectError {
             kind: ConnectErrorKind::RequestConstruction,
             source: Some(e.into()),
         })?;
 
-    let res = request_sender.send_request(connect_req).await?;
+    let res = timeout(
+        timeouts,
+        TimeoutPhase::Connect,
+        request_sender.send_request(connect_req),
+    )
+    .await??;
 
+    if res.status() == StatusCode::PROXY_AUTHENTICATION_REQUIRED {
+        return Err(ConnectError {
+            source: Some(anyhow::anyhow!(
+                "The proxy server requires authentication: {:?}",
+                res.headers().get(hyper::header::PROXY_AUTHENTICATE)
+            )),
+            kind: ConnectErrorKind::ProxyAuthentication,
+        });
+    }
     if !res.status().is_success() {
         return Err(ConnectError {
             source: Some(anyhow::anyhow!(
@@ -201,6 +431,37 @@ pub(crate) async fn connect_via_http_proxy(
             })??
             .io,
     );
-    let (ws_stream, _) = tokio_tungstenite::client_async_tls(ws_req, tcp).await?;
-    Ok(ws_stream)
+    websocket_handshake(ws_req, tcp, timeouts, tls).await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn connect_to_plain_websocket() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio_tungstenite::accept_async(stream).await.unwrap();
+        });
+        connect_directly(url.as_str(), &Timeouts::default(), &TlsOptions::default())
+            .await
+            .unwrap();
+        server.await.unwrap();
+    }
+
+    #[cfg(not(any(feature = "native-tls", feature = "__rustls")))]
+    #[tokio::test]
+    async fn secure_websocket_requires_tls() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("wss://{}/", listener.local_addr().unwrap());
+        let err = connect_directly(url.as_str(), &Timeouts::default(), &TlsOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ConnectErrorKind::Tls);
+    }
 }
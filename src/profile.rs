@@ -0,0 +1,652 @@
+//! Profiles are TOML files with the default options of the aspeak CLI, `~/.aspeak.toml` by default.
+//!
+//! The same profile can be used by other applications.
+//! A [`Profile`] resolves into the [`AuthOptions`], [`TextOptions`] and [`AudioFormat`] of a synthesizer:
+//!
+//! ```no_run
+//! use aspeak::profile::Profile;
+//!
+//! let profile = Profile::load_default()?.unwrap_or_default();
+//! let mode = profile.mode();
+//! let config = profile.synthesizer_config(mode)?;
+//! let options = profile.text_options()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Run `aspeak config init` to create a profile with all the available keys.
+
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use reqwest::header::{HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use strum::{AsRefStr, Display as StrumDisplay};
+
+use crate::{
+    get_default_voice_by_locale, get_rest_endpoint_by_region, get_websocket_endpoint_by_region,
+    parse::{parse_pitch, parse_rate, validate_style_degree},
+    parse_pinned_public_key,
+    synthesizer::SynthesizerConfig,
+    AudioCodec, AudioContainer, AudioFormat, AuthOptions, RichSsmlOptions, Role, TextOptions,
+    Timeouts, TlsOptions,
+};
+
+/// The file name of the default profile in the home directory
+const DEFAULT_PROFILE_NAME: &str = ".aspeak.toml";
+
+/// A profile, see the [module level documentation](self).
+#[derive(Debug, Clone, Default, Deserialize)]
+#[non_exhaustive]
+pub struct Profile {
+    pub auth: Option<AuthConfig>,
+    pub text: Option<TextConfig>,
+    pub output: Option<OutputConfig>,
+    /// The log verbosity of the CLI
+    pub verbosity: Option<u8>,
+}
+
+impl Profile {
+    /// The location of the default profile, `~/.aspeak.toml`.
+    pub fn default_location() -> Result<PathBuf, ProfileError> {
+        Ok(dirs::home_dir()
+            .ok_or(ProfileError {
+                kind: ProfileErrorKind::NoHomeDirectory,
+                source: None,
+            })?
+            .join(DEFAULT_PROFILE_NAME))
+    }
+
+    /// Load the profile at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ProfileError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Load the default profile, returning `None` if it does not exist.
+    pub fn load_default() -> Result<Option<Self>, ProfileError> {
+        let path = Self::default_location()?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        Self::load(path).map(Some)
+    }
+
+    /// The synthesizer mode in the `auth` section, defaults to [`SynthesizerMode::Rest`].
+    pub fn mode(&self) -> SynthesizerMode {
+        self.auth.as_ref().and_then(|a| a.mode).unwrap_or_default()
+    }
+
+    /// The [`AuthOptions`] for a synthesizer of the given mode.
+    ///
+    /// Fails if the profile specifies neither an endpoint nor a region.
+    pub fn auth_options(&self, mode: SynthesizerMode) -> Result<AuthOptions<'_>, ProfileError> {
+        self.auth
+            .as_ref()
+            .ok_or(ProfileError {
+                kind: ProfileErrorKind::MissingEndpoint,
+                source: None,
+            })?
+            .to_auth_options(mode)
+    }
+
+    /// The [`TextOptions`] in the `text` section, or the default ones.
+    pub fn text_options(&self) -> Result<TextOptions<'_>, ProfileError> {
+        self.text
+            .as_ref()
+            .map(TextConfig::to_text_options)
+            .transpose()
+            .map(Option::unwrap_or_default)
+    }
+
+    /// The [`AudioFormat`] in the `output` section, or the default one.
+    pub fn audio_format(&self) -> Result<AudioFormat, ProfileError> {
+        self.output
+            .as_ref()
+            .map_or_else(OutputConfig::default_audio_format, |o| o.audio_format())
+    }
+
+    /// The [`SynthesizerConfig`] for a synthesizer of the given mode,
+    /// including the audio format, timeouts and TLS options of this profile.
+    pub fn synthesizer_config(
+        &self,
+        mode: SynthesizerMode,
+    ) -> Result<SynthesizerConfig<'_>, ProfileError> {
+        let auth = self.auth_options(mode)?;
+        // `auth_options` has checked that the auth section exists
+        let auth_config = self.auth.as_ref().unwrap();
+        Ok(SynthesizerConfig::new(auth, self.audio_format()?)
+            .with_timeouts(auth_config.timeouts)
+            .with_tls(auth_config.tls_options()?))
+    }
+}
+
+impl FromStr for Profile {
+    type Err = ProfileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let profile: Self = toml::from_str(s)?;
+        if let Some(output) = &profile.output {
+            output.validate()?;
+        }
+        Ok(profile)
+    }
+}
+
+/// The `auth` section of a profile
+#[derive(Debug, Clone, Default, Deserialize)]
+#[non_exhaustive]
+pub struct AuthConfig {
+    #[serde(flatten)]
+    pub endpoint_config: Option<EndpointConfig>,
+    pub key: Option<String>,
+    pub token: Option<String>,
+    pub headers: Option<Vec<(String, String)>>,
+    pub proxy: Option<String>,
+    pub voice_list_api: Option<String>,
+    pub mode: Option<SynthesizerMode>,
+    /// The `connect_timeout`, `tls_timeout`, `first_byte_timeout` and `idle_timeout` in seconds,
+    /// 0 disables a timeout and a missing one takes its default
+    #[serde(flatten)]
+    pub timeouts: Timeouts,
+    /// PEM files with additional root certificates
+    pub ca_certs: Option<Vec<PathBuf>>,
+    /// PEM files with the client certificate chain and its PKCS#8 private key
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    /// SHA-256 hashes of public keys in the format of `sha256//BASE64`
+    pub pinned_public_keys: Option<Vec<String>>,
+}
+
+impl AuthConfig {
+    /// The endpoint for a synthesizer of the given mode, if any.
+    pub fn endpoint(&self, mode: SynthesizerMode) -> Option<Cow<'_, str>> {
+        self.endpoint_config.as_ref().map(|e| e.endpoint(mode))
+    }
+
+    /// The additional request headers.
+    pub fn headers(&self) -> Result<Vec<(HeaderName, HeaderValue)>, ProfileError> {
+        self.headers
+            .iter()
+            .flatten()
+            .map(|(k, v)| {
+                Ok((
+                    HeaderName::from_bytes(k.as_bytes())
+                        .map_err(|e| ProfileError::invalid_value("auth.headers", e))?,
+                    HeaderValue::from_bytes(v.as_bytes())
+                        .map_err(|e| ProfileError::invalid_value("auth.headers", e))?,
+                ))
+            })
+            .collect()
+    }
+
+    /// The [`AuthOptions`] for a synthesizer of the given mode.
+    pub fn to_auth_options(&self, mode: SynthesizerMode) -> Result<AuthOptions<'_>, ProfileError> {
+        let endpoint = self.endpoint(mode).ok_or(ProfileError {
+            kind: ProfileErrorKind::MissingEndpoint,
+            source: None,
+        })?;
+        Ok(AuthOptions::builder(endpoint)
+            .headers(self.headers()?)
+            .optional_key(self.key.as_deref())
+            .optional_token(self.token.as_deref())
+            .optional_proxy(self.proxy.as_deref())
+            .build())
+    }
+
+    /// The [`TlsOptions`] of this profile. The certificate files are read by this method.
+    pub fn tls_options(&self) -> Result<TlsOptions, ProfileError> {
+        let mut builder = TlsOptions::builder();
+        for path in self.ca_certs.iter().flatten() {
+            builder = builder.root_certificates(fs::read(path)?);
+        }
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => {
+                builder = builder.client_identity(fs::read(cert)?, fs::read(key)?);
+            }
+            (None, None) => {}
+            _ => {
+                return Err(ProfileError::invalid_value(
+                    "auth.client_cert",
+                    "both client_cert and client_key must be specified",
+                ))
+            }
+        }
+        for pin in self.pinned_public_keys.iter().flatten() {
+            builder = builder.pinned_public_key(
+                parse_pinned_public_key(pin)
+                    .map_err(|e| ProfileError::invalid_value("auth.pinned_public_keys", e))?,
+            );
+        }
+        Ok(builder.build())
+    }
+}
+
+/// The endpoint of the `auth` section, either a full URL or an official region.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum EndpointConfig {
+    Endpoint { endpoint: String },
+    Region { region: String },
+}
+
+impl EndpointConfig {
+    /// The endpoint for a synthesizer of the given mode.
+    pub fn endpoint(&self, mode: SynthesizerMode) -> Cow<'_, str> {
+        match self {
+            EndpointConfig::Endpoint { endpoint } => Cow::Borrowed(endpoint),
+            EndpointConfig::Region { region } => Cow::Owned(match mode {
+                SynthesizerMode::Websocket => get_websocket_endpoint_by_region(region.as_str()),
+                SynthesizerMode::Rest => get_rest_endpoint_by_region(region.as_str()),
+            }),
+        }
+    }
+}
+
+/// The `text` section of a profile
+#[derive(Debug, Clone, Default, Deserialize)]
+#[non_exhaustive]
+pub struct TextConfig {
+    #[serde(flatten)]
+    pub voice: Option<VoiceConfig>,
+    pub rate: Option<ProsodyValue>,
+    pub pitch: Option<ProsodyValue>,
+    pub style_degree: Option<f32>,
+    pub role: Option<Role>,
+    pub style: Option<String>,
+}
+
+impl TextConfig {
+    /// The voice, or the default voice of the locale.
+    pub fn voice(&self) -> Result<Option<&str>, ProfileError> {
+        self.voice.as_ref().map(VoiceConfig::try_as_str).transpose()
+    }
+
+    /// The speech rate in a format accepted by [`TextOptions`].
+    pub fn rate(&self) -> Result<Option<Cow<'_, str>>, ProfileError> {
+        self.rate
+            .as_ref()
+            .map(|rate| match rate {
+                ProsodyValue::Number(n) => Ok(Cow::Owned(format!("{:.2}%", n * 100f32))),
+                ProsodyValue::Text(s) => {
+                    parse_rate(s).map_err(|e| ProfileError::invalid_value("text.rate", e))
+                }
+            })
+            .transpose()
+    }
+
+    /// The pitch in a format accepted by [`TextOptions`].
+    pub fn pitch(&self) -> Result<Option<Cow<'_, str>>, ProfileError> {
+        self.pitch
+            .as_ref()
+            .map(|pitch| match pitch {
+                ProsodyValue::Number(n) => Ok(Cow::Owned(format!("{:.2}%", n * 100f32))),
+                ProsodyValue::Text(s) => {
+                    parse_pitch(s).map_err(|e| ProfileError::invalid_value("text.pitch", e))
+                }
+            })
+            .transpose()
+    }
+
+    /// The [`TextOptions`] of this section. The voice defaults to the default voice of `en-US`.
+    pub fn to_text_options(&self) -> Result<TextOptions<'_>, ProfileError> {
+        if let Some(degree) = self.style_degree {
+            if !validate_style_degree(degree) {
+                return Err(ProfileError::invalid_value(
+                    "text.style_degree",
+                    format!("{degree} is out of range [0.01, 2]"),
+                ));
+            }
+        }
+        let rich_ssml_options = (self.style.is_some()
+            || self.role.is_some()
+            || self.style_degree.is_some())
+        .then(|| {
+            RichSsmlOptions::builder()
+                .optional_style(self.style.as_deref())
+                .optional_role(self.role)
+                .optional_style_degree(self.style_degree)
+                .build()
+        });
+        Ok(TextOptions::builder()
+            .voice(
+                self.voice()?
+                    .unwrap_or_else(|| get_default_voice_by_locale("en-US").unwrap()),
+            )
+            .optional_rate(self.rate()?)
+            .optional_pitch(self.pitch()?)
+            .optional_rich_ssml_options(rich_ssml_options)
+            .build())
+    }
+}
+
+/// The voice of the `text` section, either a voice name or a locale whose default voice is used.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged, rename_all = "kebab-case")]
+pub enum VoiceConfig {
+    Voice { voice: String },
+    Locale { locale: String },
+}
+
+impl VoiceConfig {
+    /// The voice name, looking up the default voice of the locale if necessary.
+    pub fn try_as_str(&self) -> Result<&str, ProfileError> {
+        Ok(match self {
+            VoiceConfig::Voice { voice } => voice.as_str(),
+            VoiceConfig::Locale { locale } => {
+                get_default_voice_by_locale(locale).ok_or_else(|| {
+                    ProfileError::invalid_value(
+                        "text.locale",
+                        format!("could not find default voice for locale: {locale}"),
+                    )
+                })?
+            }
+        })
+    }
+}
+
+/// The rate or pitch of the `text` section.
+///
+/// Numbers are converted to percentages, e.g. `0.5` is `50.00%`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ProsodyValue {
+    Number(f32),
+    Text(String),
+}
+
+/// The `output` section of a profile
+#[derive(Debug, Clone, Default, Deserialize)]
+#[non_exhaustive]
+pub struct OutputConfig {
+    pub format: Option<AudioFormat>,
+    pub container: Option<ContainerFormat>,
+    pub quality: Option<i32>,
+    /// Options of the audio playback of the CLI
+    pub device: Option<String>,
+    pub volume: Option<f32>,
+    pub speed: Option<f32>,
+    /// Whether the CLI embeds tags into the output files
+    pub tag: Option<bool>,
+}
+
+impl OutputConfig {
+    /// The audio format of this section.
+    ///
+    /// A format takes precedence over the container and quality.
+    /// Out of range qualities are clamped to the closest available one.
+    pub fn audio_format(&self) -> Result<AudioFormat, ProfileError> {
+        if let Some(format) = self.format {
+            return Ok(format);
+        }
+        AudioFormat::from_container_and_quality(
+            self.container.unwrap_or_default().as_ref(),
+            self.quality.unwrap_or_default() as i8,
+            true,
+        )
+        .map_err(|e| ProfileError::invalid_value("output.quality", e))
+    }
+
+    /// Check that the playback volume is not negative and the speed is positive.
+    fn validate(&self) -> Result<(), ProfileError> {
+        if let Some(volume) = self.volume.filter(|v| !(v.is_finite() && *v >= 0.0)) {
+            return Err(ProfileError::invalid_value(
+                "output.volume",
+                format!("the volume must be a non-negative number, got {volume}"),
+            ));
+        }
+        if let Some(speed) = self.speed.filter(|s| !(s.is_finite() && *s > 0.0)) {
+            return Err(ProfileError::invalid_value(
+                "output.speed",
+                format!("the speed must be a positive number, got {speed}"),
+            ));
+        }
+        Ok(())
+    }
+
+    fn default_audio_format() -> Result<AudioFormat, ProfileError> {
+        Self::default().audio_format()
+    }
+}
+
+/// The mode of a synthesizer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, StrumDisplay)]
+#[cfg_attr(feature = "binary", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum SynthesizerMode {
+    /// [`RestSynthesizer`](crate::synthesizer::RestSynthesizer)
+    #[default]
+    Rest,
+    /// [`WebsocketSynthesizer`](crate::synthesizer::WebsocketSynthesizer)
+    Websocket,
+}
+
+/// Container formats of the audio formats, used with a quality to choose an [`AudioFormat`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, AsRefStr, Deserialize)]
+#[cfg_attr(feature = "binary", derive(clap::ValueEnum))]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum ContainerFormat {
+    Mp3,
+    Ogg,
+    Webm,
+    #[default]
+    Wav,
+    Pcm,
+    Alaw,
+    Ulaw,
+    Amr,
+}
+
+impl ContainerFormat {
+    /// Infer the container format from the extension of a file.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        Some(match extension.as_str() {
+            "mp3" => Self::Mp3,
+            "ogg" | "opus" => Self::Ogg,
+            "webm" => Self::Webm,
+            "wav" => Self::Wav,
+            "pcm" => Self::Pcm,
+            "alaw" => Self::Alaw,
+            "ulaw" => Self::Ulaw,
+            "amr" => Self::Amr,
+            _ => return None,
+        })
+    }
+
+    /// Whether audio in `format` is stored in this container format.
+    pub fn contains(self, format: AudioFormat) -> bool {
+        match self {
+            Self::Mp3 => format.container() == AudioContainer::Mp3,
+            Self::Ogg => format.container() == AudioContainer::Ogg,
+            Self::Webm => format.container() == AudioContainer::Webm,
+            Self::Wav => format.container() == AudioContainer::Wav,
+            Self::Amr => format.container() == AudioContainer::Amr,
+            Self::Pcm => {
+                format.container() == AudioContainer::Raw && format.codec() == AudioCodec::Pcm
+            }
+            Self::Alaw => {
+                format.container() == AudioContainer::Raw && format.codec() == AudioCodec::ALaw
+            }
+            Self::Ulaw => {
+                format.container() == AudioContainer::Raw && format.codec() == AudioCodec::MuLaw
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+/// An error that can occur while loading or resolving a [`Profile`].
+pub struct ProfileError {
+    pub kind: ProfileErrorKind,
+    pub(crate) source: Option<anyhow::Error>,
+}
+
+impl ProfileError {
+    fn invalid_value(key: &'static str, reason: impl Display) -> Self {
+        Self {
+            kind: ProfileErrorKind::InvalidValue(key),
+            source: Some(anyhow::anyhow!("{reason}")),
+        }
+    }
+}
+
+impl Display for ProfileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "profile error: ")?;
+        match self.kind {
+            ProfileErrorKind::Io => write!(f, "failed to read a file"),
+            ProfileErrorKind::Syntax => write!(f, "the profile is not valid"),
+            ProfileErrorKind::NoHomeDirectory => write!(f, "could not find the home directory"),
+            ProfileErrorKind::MissingEndpoint => {
+                write!(f, "neither endpoint nor region is specified")
+            }
+            ProfileErrorKind::InvalidValue(key) => write!(f, "invalid value of {key}"),
+        }
+    }
+}
+
+impl Error for ProfileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|e| e.as_ref() as _)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, AsRefStr)]
+#[non_exhaustive]
+#[strum(serialize_all = "title_case")]
+pub enum ProfileErrorKind {
+    /// The profile or a file referenced by it cannot be read
+    Io,
+    /// The profile is not valid TOML or does not match the format of profiles
+    Syntax,
+    /// The home directory, where the default profile is, cannot be found
+    NoHomeDirectory,
+    /// The profile specifies neither an endpoint nor a region
+    MissingEndpoint,
+    /// The value of the given key is invalid
+    InvalidValue(&'static str),
+}
+
+macro_rules! impl_from_for_profile_error {
+    ($error_type:ty, $error_kind:ident) => {
+        impl From<$error_type> for ProfileError {
+            fn from(e: $error_type) -> Self {
+                Self {
+                    kind: ProfileErrorKind::$error_kind,
+                    source: Some(e.into()),
+                }
+            }
+        }
+    };
+}
+
+impl_from_for_profile_error!(std::io::Error, Io);
+impl_from_for_profile_error!(toml::de::Error, Syntax);
+
+#[cfg(feature = "python")]
+impl From<ProfileError> for pyo3::PyErr {
+    fn from(value: ProfileError) -> Self {
+        let os_error = matches!(
+            value.kind,
+            ProfileErrorKind::Io | ProfileErrorKind::NoHomeDirectory
+        );
+        let message = format!("{:?}", color_eyre::Report::from(value));
+        if os_error {
+            pyo3::exceptions::PyOSError::new_err(message)
+        } else {
+            pyo3::exceptions::PyValueError::new_err(message)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn invalid_key(profile: &str) -> Option<&'static str> {
+        match profile.parse::<Profile>() {
+            Err(ProfileError {
+                kind: ProfileErrorKind::InvalidValue(key),
+                ..
+            }) => Some(key),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn playback_options() {
+        let profile: Profile = "[output]\nvolume = 0.0\nspeed = 1.5".parse().unwrap();
+        let output = profile.output.unwrap();
+        assert_eq!((output.volume, output.speed), (Some(0.0), Some(1.5)));
+        for (profile, key) in [
+            ("[output]\nvolume = -0.5", "output.volume"),
+            ("[output]\nvolume = nan", "output.volume"),
+            ("[output]\nspeed = 0", "output.speed"),
+            ("[output]\nspeed = inf", "output.speed"),
+        ] {
+            assert_eq!(invalid_key(profile), Some(key), "{profile}");
+        }
+    }
+
+    #[test]
+    fn timeouts() {
+        let profile: Profile = r#"
+            [auth]
+            region = "eastus"
+            connect_timeout = 5
+            tls_timeout = 0
+            first_byte_timeout = 2.5
+        "#
+        .parse()
+        .unwrap();
+        let timeouts = profile.auth.unwrap().timeouts;
+        assert_eq!(timeouts.connect(), Some(Duration::from_secs(5)));
+        assert_eq!(timeouts.tls_handshake(), None);
+        assert_eq!(timeouts.first_byte(), Some(Duration::from_millis(2500)));
+        assert_eq!(timeouts.idle(), Timeouts::default().idle());
+        assert_eq!(
+            Profile::default().auth.unwrap_or_default().timeouts,
+            Timeouts::default()
+        );
+        let err = "[auth]\nidle_timeout = -1".parse::<Profile>().unwrap_err();
+        assert_eq!(err.kind, ProfileErrorKind::Syntax);
+    }
+
+    #[test]
+    #[cfg(feature = "__rustls")]
+    fn pinned_public_keys() {
+        let profile: Profile = r#"
+            [auth]
+            region = "eastus"
+            pinned_public_keys = ["sha256//yhWirapqYrQk5zIhNogKg4qo+c/TMxiSS/Dc9JeD/Qk="]
+        "#
+        .parse()
+        .unwrap();
+        let tls = profile.auth.unwrap().tls_options().unwrap();
+        assert_eq!(tls.pinned_public_keys()[0][..4], [0xca, 0x15, 0xa2, 0xad]);
+    }
+
+    #[test]
+    fn invalid_pinned_public_keys() {
+        let profile: Profile = "[auth]\npinned_public_keys = [\"sha256//AAAA\"]"
+            .parse()
+            .unwrap();
+        assert!(matches!(
+            profile.auth.unwrap().tls_options(),
+            Err(ProfileError {
+                kind: ProfileErrorKind::InvalidValue("auth.pinned_public_keys"),
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,439 @@
+use std::{
+    env::{self, VarError},
+    error::Error,
+    fmt::{self, Display, Formatter},
+    net::IpAddr,
+};
+
+use log::debug;
+use url::{Host, Url};
+
+/// Resolves the proxy server used for a request, following the conventions of curl.
+///
+/// An explicit proxy takes precedence over the environment. Otherwise, the proxy is taken from
+/// `http_proxy`/`HTTP_PROXY` for `http` and `ws` urls, from `https_proxy`/`HTTPS_PROXY` for `https` and `wss` urls,
+/// and from `all_proxy`/`ALL_PROXY` as a fallback. The lowercase variables take precedence.
+///
+/// Hosts listed in `no_proxy`/`NO_PROXY` are connected to directly, even with an explicit proxy.
+/// An explicit proxy that is an empty string disables proxies altogether.
+#[derive(Debug, Clone, Default)]
+pub struct ProxyResolver {
+    explicit: Option<Url>,
+    http: Option<Url>,
+    https: Option<Url>,
+    all: Option<Url>,
+    no_proxy: NoProxy,
+    disabled: bool,
+}
+
+impl ProxyResolver {
+    /// Create a resolver from an explicit proxy and the environment.
+    ///
+    /// Returns an error if a proxy url is invalid or an environment variable is not valid unicode.
+    pub fn new(explicit: Option<&str>) -> Result<Self, ProxyError> {
+        Self::with_env(explicit, |name| env::var(name))
+    }
+
+    /// Create a resolver from an explicit proxy and the environment variables returned by `var`
+    fn with_env(
+        explicit: Option<&str>,
+        var: impl Fn(&str) -> Result<String, VarError>,
+    ) -> Result<Self, ProxyError> {
+        if explicit == Some("") {
+            return Ok(Self {
+                disabled: true,
+                ..Default::default()
+            });
+        }
+        let resolver = Self {
+            explicit: explicit.map(parse_proxy_url).transpose()?,
+            http: proxy_from_env(&var, &["http_proxy", "HTTP_PROXY"])?,
+            https: proxy_from_env(&var, &["https_proxy", "HTTPS_PROXY"])?,
+            all: proxy_from_env(&var, &["all_proxy", "ALL_PROXY"])?,
+            no_proxy: var_from_env(&var, &["no_proxy", "NO_PROXY"])?
+                .map(|v| NoProxy::parse(&v))
+                .unwrap_or_default(),
+            disabled: false,
+        };
+        Ok(resolver)
+    }
+
+    /// Whether any proxy is configured in the environment
+    pub fn has_env_proxy(&self) -> bool {
+        self.http.is_some() || self.https.is_some() || self.all.is_some()
+    }
+
+    /// The proxy to use for a request to `target`, or `None` to connect directly
+    pub fn resolve(&self, target: &Url) -> Option<&Url> {
+        if self.disabled {
+            return None;
+        }
+        if let Some(host) = target.host() {
+            if self.no_proxy.matches(&host) {
+                debug!("Not using a proxy for {host} because of NO_PROXY");
+                return None;
+            }
+        }
+        if self.explicit.is_some() {
+            return self.explicit.as_ref();
+        }
+        let by_scheme = match target.scheme() {
+            "https" | "wss" => self.https.as_ref(),
+            "http" | "ws" => self.http.as_ref(),
+            _ => None,
+        };
+        by_scheme.or(self.all.as_ref())
+    }
+}
+
+/// Parse a proxy url, assuming `http://` if it has no scheme like curl does
+fn parse_proxy_url(proxy: &str) -> Result<Url, ProxyError> {
+    let url = if proxy.contains("://") {
+        Url::parse(proxy)
+    } else {
+        Url::parse(&format!("http://{proxy}"))
+    };
+    url.map_err(|e| ProxyError {
+        kind: ProxyErrorKind::BadUrl(proxy.to_string()),
+        source: Some(e.into()),
+    })
+}
+
+/// The value of the first environment variable in `names` that is set and not empty
+fn var_from_env(
+    var: impl Fn(&str) -> Result<String, VarError>,
+    names: &[&str],
+) -> Result<Option<String>, ProxyError> {
+    for name in names {
+        match var(name) {
+            Ok(value) if !value.trim().is_empty() => return Ok(Some(value)),
+            Ok(_) | Err(VarError::NotPresent) => continue,
+            Err(e @ VarError::NotUnicode(_)) => {
+                return Err(ProxyError {
+                    kind: ProxyErrorKind::InvalidEnvironment(name.to_string()),
+                    source: Some(e.into()),
+                })
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn proxy_from_env(
+    var: impl Fn(&str) -> Result<String, VarError>,
+    names: &[&str],
+) -> Result<Option<Url>, ProxyError> {
+    var_from_env(var, names)?
+        .as_deref()
+        .map(str::trim)
+        .map(parse_proxy_url)
+        .transpose()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum NoProxyEntry {
+    /// `*` matches every host
+    Wildcard,
+    /// A domain name matches itself and its subdomains
+    Domain(String),
+    /// An IP address or a network in CIDR notation
+    Network(IpAddr, u8),
+}
+
+/// A list of hosts that are connected to directly, in the format of the `NO_PROXY` environment variable.
+///
+/// Entries are separated by commas or whitespace. An entry is `*`, a domain name that also matches
+/// its subdomains (a leading `.` or `*.` is ignored), an IP address or a network in CIDR notation
+/// like `10.0.0.0/8`. Ports are ignored. Host names are not resolved to match IP entries.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NoProxy {
+    entries: Vec<NoProxyEntry>,
+}
+
+impl NoProxy {
+    /// Parse a list of hosts in the format of the `NO_PROXY` environment variable
+    pub fn parse(list: &str) -> Self {
+        let entries = list
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| {
+                if entry == "*" {
+                    return Some(NoProxyEntry::Wildcard);
+                }
+                if let Some((addr, prefix)) = entry.split_once('/') {
+                    let addr: IpAddr = addr.trim_matches(|c| c == '[' || c == ']').parse().ok()?;
+                    let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+                    let prefix: u8 = prefix.parse().ok().filter(|&p| p <= max_prefix)?;
+                    return Some(NoProxyEntry::Network(addr, prefix));
+                }
+                let host = match entry.strip_prefix('[') {
+                    // [IPv6] with an optional port
+                    Some(rest) => rest.split(']').next().unwrap_or_default(),
+                    // IPv6 addresses contain several colons, anything else may end with a port
+                    None if entry.matches(':').count() > 1 => entry,
+                    None => entry.split(':').next().unwrap_or_default(),
+                };
+                if let Ok(addr) = host.parse::<IpAddr>() {
+                    let prefix = if addr.is_ipv4() { 32 } else { 128 };
+                    return Some(NoProxyEntry::Network(addr, prefix));
+                }
+                let domain = host.trim_start_matches('*').trim_start_matches('.');
+                (!domain.is_empty()).then(|| NoProxyEntry::Domain(domain.to_ascii_lowercase()))
+            })
+            .collect();
+        Self { entries }
+    }
+
+    /// Whether requests to `host` should not use a proxy
+    pub fn matches(&self, host: &Host<&str>) -> bool {
+        let addr = match host {
+            Host::Domain(domain) => domain.parse::<IpAddr>().ok(),
+            Host::Ipv4(addr) => Some(IpAddr::V4(*addr)),
+            Host::Ipv6(addr) => Some(IpAddr::V6(*addr)),
+        };
+        self.entries.iter().any(|entry| match (entry, host, addr) {
+            (NoProxyEntry::Wildcard, _, _) => true,
+            (NoProxyEntry::Network(network, prefix), _, Some(addr)) => {
+                in_network(addr, *network, *prefix)
+            }
+            (NoProxyEntry::Domain(domain), Host::Domain(host), None) => {
+                let host = host.trim_end_matches('.').to_ascii_lowercase();
+                host == *domain
+                    || host
+                        .strip_suffix(domain.as_str())
+                        .is_some_and(|sub| sub.ends_with('.'))
+            }
+            _ => false,
+        })
+    }
+}
+
+fn in_network(addr: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    fn masked(bits: u128, width: u8, prefix: u8) -> u128 {
+        match prefix {
+            0 => 0,
+            _ => bits >> (width - prefix),
+        }
+    }
+    match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            masked(u32::from(addr) as u128, 32, prefix)
+                == masked(u32::from(network) as u128, 32, prefix)
+        }
+        (IpAddr::V6(addr), IpAddr::V6(network)) => {
+            masked(u128::from(addr), 128, prefix) == masked(u128::from(network), 128, prefix)
+        }
+        _ => false,
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+/// An error that can occur when resolving the proxy server
+pub struct ProxyError {
+    pub kind: ProxyErrorKind,
+    pub(crate) source: Option<anyhow::Error>,
+}
+
+impl Display for ProxyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "proxy error: ")?;
+        match &self.kind {
+            ProxyErrorKind::BadUrl(url) => write!(f, "bad proxy url: {url}"),
+            ProxyErrorKind::InvalidEnvironment(name) => {
+                write!(f, "environment variable {name} is not valid unicode")
+            }
+        }
+    }
+}
+
+impl Error for ProxyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|e| e.as_ref() as _)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
+pub enum ProxyErrorKind {
+    /// The proxy url could not be parsed
+    BadUrl(String),
+    /// A proxy environment variable is not valid unicode
+    InvalidEnvironment(String),
+}
+
+#[cfg(feature = "python")]
+impl From<ProxyError> for pyo3::PyErr {
+    fn from(value: ProxyError) -> Self {
+        pyo3::exceptions::PyOSError::new_err(format!("{:?}", color_eyre::Report::from(value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn matches(no_proxy: &str, url: &str) -> bool {
+        let url = Url::parse(url).unwrap();
+        NoProxy::parse(no_proxy).matches(&url.host().unwrap())
+    }
+
+    fn env_resolver(explicit: Option<&str>, vars: &[(&str, &str)]) -> ProxyResolver {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        ProxyResolver::with_env(explicit, |name| {
+            vars.get(name)
+                .map(ToString::to_string)
+                .ok_or(VarError::NotPresent)
+        })
+        .unwrap()
+    }
+
+    fn resolve(resolver: &ProxyResolver, url: &str) -> Option<String> {
+        resolver
+            .resolve(&Url::parse(url).unwrap())
+            .map(ToString::to_string)
+    }
+
+    #[test]
+    fn wildcard() {
+        assert!(matches("*", "wss://example.com/"));
+        assert!(matches("*", "wss://10.0.0.1/"));
+        assert!(matches("*", "wss://[::1]/"));
+        assert!(matches("localhost, *", "wss://example.com/"));
+    }
+
+    #[test]
+    fn domain_suffixes() {
+        for no_proxy in [
+            "example.com",
+            ".example.com",
+            "*.example.com",
+            "EXAMPLE.COM",
+        ] {
+            assert!(matches(no_proxy, "wss://example.com/"), "{no_proxy}");
+            assert!(matches(no_proxy, "wss://tts.Example.com./"), "{no_proxy}");
+            assert!(!matches(no_proxy, "wss://badexample.com/"), "{no_proxy}");
+            assert!(!matches(no_proxy, "wss://example.com.evil/"), "{no_proxy}");
+        }
+        assert!(matches("localhost example.com", "wss://example.com/"));
+        assert!(!matches("", "wss://example.com/"));
+    }
+
+    #[test]
+    fn ipv4_networks() {
+        assert!(matches("10.0.0.0/8", "wss://10.1.2.3/"));
+        assert!(!matches("10.0.0.0/8", "wss://11.0.0.1/"));
+        assert!(matches("10.1.2.3", "wss://10.1.2.3/"));
+        assert!(!matches("10.1.2.3", "wss://10.1.2.4/"));
+        assert!(matches("0.0.0.0/0", "wss://192.168.1.1/"));
+        assert!(!matches("0.0.0.0/0", "wss://[::1]/"));
+        assert!(!matches("0.0.0.0/0", "wss://example.com/"));
+        // An out of range prefix ignores the entry
+        assert_eq!(NoProxy::parse("10.0.0.0/33"), NoProxy::default());
+        assert!(!matches("10.0.0.0/33", "wss://10.0.0.1/"));
+    }
+
+    #[test]
+    fn ipv6_networks() {
+        assert!(matches("fd00::/8", "wss://[fd12::1]/"));
+        assert!(!matches("fd00::/8", "wss://[fe80::1]/"));
+        assert!(matches("::1", "wss://[::1]/"));
+        assert!(matches("[::1]/128", "wss://[::1]/"));
+        assert!(matches("::/0", "wss://[2001:db8::1]/"));
+        assert!(!matches("::/0", "wss://10.0.0.1/"));
+        assert_eq!(NoProxy::parse("::/129"), NoProxy::default());
+    }
+
+    #[test]
+    fn ports_are_ignored() {
+        assert!(matches("example.com:8080", "wss://example.com/"));
+        assert!(matches("10.0.0.1:8080", "wss://10.0.0.1:443/"));
+        assert!(matches("[::1]:8080", "wss://[::1]/"));
+        assert!(!matches("example.com:8080", "wss://other.com/"));
+    }
+
+    #[test]
+    fn proxies_by_scheme() {
+        let vars = [
+            ("https_proxy", "http://https.proxy:1"),
+            ("all_proxy", "socks5://all.proxy:2"),
+        ];
+        let resolver = env_resolver(None, &vars);
+        assert!(resolver.has_env_proxy());
+        assert_eq!(
+            resolve(&resolver, "wss://example.com/").as_deref(),
+            Some("http://https.proxy:1/")
+        );
+        assert_eq!(
+            resolve(&resolver, "https://example.com/").as_deref(),
+            Some("http://https.proxy:1/")
+        );
+        // all_proxy is the fallback for the schemes without a proxy
+        assert_eq!(
+            resolve(&resolver, "ws://example.com/").as_deref(),
+            Some("socks5://all.proxy:2")
+        );
+    }
+
+    #[test]
+    fn lowercase_variables_take_precedence() {
+        let resolver = env_resolver(
+            None,
+            &[
+                ("HTTPS_PROXY", "upper.proxy:1"),
+                ("https_proxy", "lower.proxy:1"),
+                ("NO_PROXY", "upper.example.com"),
+                ("no_proxy", "lower.example.com"),
+            ],
+        );
+        assert_eq!(
+            resolve(&resolver, "wss://example.com/").as_deref(),
+            Some("http://lower.proxy:1/")
+        );
+        assert_eq!(resolve(&resolver, "wss://lower.example.com/"), None);
+        assert!(resolve(&resolver, "wss://upper.example.com/").is_some());
+        // Empty variables are skipped
+        let resolver = env_resolver(
+            None,
+            &[("HTTPS_PROXY", "upper.proxy:1"), ("https_proxy", " ")],
+        );
+        assert_eq!(
+            resolve(&resolver, "wss://example.com/").as_deref(),
+            Some("http://upper.proxy:1/")
+        );
+    }
+
+    #[test]
+    fn explicit_proxy() {
+        let vars = [
+            ("https_proxy", "env.proxy:1"),
+            ("no_proxy", "internal.example.com"),
+        ];
+        let resolver = env_resolver(Some("socks5h://explicit.proxy:1"), &vars);
+        assert_eq!(
+            resolve(&resolver, "wss://example.com/").as_deref(),
+            Some("socks5h://explicit.proxy:1")
+        );
+        // NO_PROXY applies to the explicit proxy too
+        assert_eq!(resolve(&resolver, "wss://internal.example.com/"), None);
+        // An empty proxy disables proxies
+        let resolver = env_resolver(Some(""), &vars);
+        assert_eq!(resolve(&resolver, "wss://example.com/"), None);
+        assert!(!resolver.has_env_proxy());
+    }
+
+    #[test]
+    fn invalid_environment() {
+        let err = ProxyResolver::with_env(None, |name| match name {
+            "http_proxy" => Err(VarError::NotUnicode("\u{fffd}".into())),
+            _ => Err(VarError::NotPresent),
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.kind,
+            ProxyErrorKind::InvalidEnvironment("http_proxy".to_string())
+        );
+    }
+}
@@ -2,20 +2,23 @@ use std::borrow::Cow;
 use std::cell::RefCell;
 use std::fs::File;
 use std::io::Write;
+use std::time::Duration;
 
+use futures_util::StreamExt;
 use pyo3::exceptions::PyValueError;
 use pyo3::types::{PyBytes, PySequence};
 use pyo3::{prelude::*, types::PyDict};
 use reqwest::header::{HeaderName, HeaderValue};
 use tokio::runtime::Runtime;
 
-use crate::audio::play_owned_audio_blocking;
+use crate::audio::{pcm, PlaybackOptions, Player};
 use crate::get_rest_endpoint_by_region;
 use crate::parse::{parse_pitch, parse_rate, parse_style_degree};
-use crate::synthesizer::UnifiedSynthesizer;
+use crate::profile::{Profile, SynthesizerMode};
+use crate::synthesizer::{EventStream, UnifiedSynthesizer};
 use crate::{
     get_default_voice_by_locale, get_websocket_endpoint_by_region, synthesizer::SynthesizerConfig,
-    AudioFormat, AuthOptions, TextOptions,
+    AudioFormat, AuthOptions, SynthesisEvent, TextOptions,
 };
 
 #[pymodule]
@@ -34,70 +37,180 @@ fn aspeak(py: Python, m: &PyModule) -> PyResult<()> {
 struct SpeechService {
     synthesizer: RefCell<Box<dyn UnifiedSynthesizer>>,
     runtime: Runtime,
+    audio_format: AudioFormat,
+    /// The default text options, which are taken from the profile if the service is created from one
+    text_options: TextOptions<'static>,
+    /// The player is reused across calls as long as the output device stays the same.
+    player: RefCell<Option<(Option<String>, Player)>>,
 }
 
 impl SpeechService {
-    fn parse_text_options(options: Option<&PyDict>) -> PyResult<Option<TextOptions>> {
-        options
-            .map(|opts| {
-                Ok::<TextOptions, PyErr>(TextOptions {
-                    pitch: opts
-                        .get_item("pitch")
-                        .map(|p| p.extract())
-                        .transpose()?
-                        .map(parse_pitch)
-                        .transpose()?,
-                    rate: opts
-                        .get_item("rate")
-                        .map(|r| r.extract())
-                        .transpose()?
-                        .map(parse_rate)
-                        .transpose()?,
-                    voice: {
-                        if let Some(voice) =
-                            opts.get_item("voice").map(|p| p.extract()).transpose()?
-                        {
-                            Cow::Borrowed(voice)
-                        } else {
-                            let locale = opts
-                                .get_item("locale")
-                                .map(|l| l.extract())
-                                .transpose()?
-                                .unwrap_or("en-US");
-                            Cow::Borrowed(get_default_voice_by_locale(locale).ok_or_else(|| {
-                                PyValueError::new_err(format!(
-                                    "No default voice for locale: {}",
-                                    locale
-                                ))
-                            })?)
-                        }
-                    },
-                    rich_ssml_options: {
-                        let style = opts
-                            .get_item("style")
-                            .map(|s| s.extract())
-                            .transpose()?
-                            .map(Cow::Borrowed);
-                        let style_degree = opts
-                            .get_item("style_degree")
-                            .map(|l| l.extract())
-                            .transpose()?
-                            .map(parse_style_degree)
-                            .transpose()?;
-                        let role = opts.get_item("role").map(|r| r.extract()).transpose()?;
-                        if style.is_some() || style_degree.is_some() || role.is_some() {
-                            Some(crate::types::RichSsmlOptions {
-                                style,
-                                style_degree,
-                                role,
-                            })
-                        } else {
-                            None
-                        }
-                    },
-                })
-            })
-            .transpose()
+    fn parse_mode(mode: &str) -> PyResult<SynthesizerMode> {
+        match mode {
+            "rest" => Ok(SynthesizerMode::Rest),
+            "websocket" => Ok(SynthesizerMode::Websocket),
+            _ => Err(PyValueError::new_err(format!(
+                "Invalid synthesizer mode: {}",
+                mode
+            ))),
+        }
+    }
+
+    fn create(
+        runtime: Runtime,
+        conf: SynthesizerConfig<'_>,
+        mode: SynthesizerMode,
+        text_options: TextOptions<'static>,
+    ) -> PyResult<Self> {
+        let audio_format = conf.audio_format();
+        Ok(Self {
+            synthesizer: RefCell::new(runtime.block_on(async {
+                let boxed: Box<dyn UnifiedSynthesizer> = match mode {
+                    SynthesizerMode::Rest => Box::new(conf.rest_synthesizer()?),
+                    SynthesizerMode::Websocket => Box::new(conf.connect_websocket().await?),
+                };
+                Ok::<Box<dyn UnifiedSynthesizer>, PyErr>(boxed)
+            })?),
+            runtime,
+            audio_format,
+            text_options,
+            player: RefCell::new(None),
+        })
+    }
+
+    /// Parse the text options, falling back to the default text options of this service
+    fn parse_text_options<'a>(&'a self, options: Option<&'a PyDict>) -> PyResult<TextOptions<'a>> {
+        let defaults = &self.text_options;
+        let Some(opts) = options else {
+            return Ok(defaults.clone());
+        };
+        Ok(TextOptions {
+            pitch: opts
+                .get_item("pitch")
+                .map(|p| p.extract())
+                .transpose()?
+                .map(parse_pitch)
+                .transpose()?
+                .or_else(|| defaults.pitch.clone()),
+            rate: opts
+                .get_item("rate")
+                .map(|r| r.extract())
+                .transpose()?
+                .map(parse_rate)
+                .transpose()?
+                .or_else(|| defaults.rate.clone()),
+            voice: {
+                if let Some(voice) = opts.get_item("voice").map(|p| p.extract()).transpose()? {
+                    Cow::Borrowed(voice)
+                } else if let Some(locale) =
+                    opts.get_item("locale").map(|l| l.extract()).transpose()?
+                {
+                    Cow::Borrowed(get_default_voice_by_locale(locale).ok_or_else(|| {
+                        PyValueError::new_err(format!("No default voice for locale: {}", locale))
+                    })?)
+                } else {
+                    defaults.voice.clone()
+                }
+            },
+            rich_ssml_options: {
+                let style = opts
+                    .get_item("style")
+                    .map(|s| s.extract())
+                    .transpose()?
+                    .map(Cow::Borrowed);
+                let style_degree = opts
+                    .get_item("style_degree")
+                    .map(|l| l.extract())
+                    .transpose()?
+                    .map(parse_style_degree)
+                    .transpose()?;
+                let role = opts.get_item("role").map(|r| r.extract()).transpose()?;
+                if style.is_some() || style_degree.is_some() || role.is_some() {
+                    let defaults = defaults.rich_ssml_options.as_ref();
+                    Some(crate::types::RichSsmlOptions {
+                        style: style.or_else(|| defaults.and_then(|d| d.style.clone())),
+                        style_degree: style_degree
+                            .or_else(|| defaults.and_then(|d| d.style_degree)),
+                        role: role.or_else(|| defaults.and_then(|d| d.role)),
+                    })
+                } else {
+                    defaults.rich_ssml_options.clone()
+                }
+            },
+        })
+    }
+
+    fn parse_playback_options(options: Option<&PyDict>) -> PyResult<PlaybackOptions<'_>> {
+        let Some(opts) = options else {
+            return Ok(PlaybackOptions::default());
+        };
+        let mut builder = PlaybackOptions::builder().optional_device(
+            opts.get_item("device")
+                .map(|d| d.extract::<&str>())
+                .transpose()?,
+        );
+        if let Some(volume) = opts.get_item("volume").map(|v| v.extract()).transpose()? {
+            builder = builder.volume(volume);
+        }
+        if let Some(speed) = opts.get_item("speed").map(|s| s.extract()).transpose()? {
+            builder = builder.speed(speed);
+        }
+        if let Some(start_offset) = opts
+            .get_item("start_offset")
+            .map(|o| o.extract::<f64>())
+            .transpose()?
+        {
+            builder =
+                builder
+                    .start_offset(Duration::try_from_secs_f64(start_offset).map_err(|e| {
+                        PyValueError::new_err(format!("Invalid start offset: {e}"))
+                    })?);
+        }
+        Ok(builder.build()?)
+    }
+
+    fn play(&self, buffer: Vec<u8>, options: &PlaybackOptions<'_>) -> PyResult<()> {
+        let mut player = self.player.borrow_mut();
+        let device = options.device().map(String::from);
+        if !matches!(player.as_ref(), Some((d, _)) if *d == device) {
+            *player = Some((device, Player::new(options)?));
+        }
+        let (_, player) = player.as_ref().unwrap();
+        player.set_volume(options.volume());
+        player.set_speed(options.speed());
+        player.enqueue_from(
+            pcm::into_playable(self.audio_format, buffer),
+            options.start_offset(),
+        )?;
+        player.wait_until_finished()?;
+        Ok(())
+    }
+
+    /// Pass the audio chunks and the boundaries to the callbacks as soon as they arrive.
+    async fn dispatch_events(
+        py: Python<'_>,
+        mut events: EventStream<'_>,
+        on_audio: &PyAny,
+        on_boundary: Option<&PyAny>,
+    ) -> PyResult<()> {
+        while let Some(event) = events.next().await {
+            match event? {
+                SynthesisEvent::Audio(chunk) => {
+                    on_audio.call1((PyBytes::new(py, &chunk),))?;
+                }
+                SynthesisEvent::Boundary(boundary) => {
+                    if let Some(on_boundary) = on_boundary {
+                        on_boundary.call1((
+                            boundary.kind.as_ref(),
+                            boundary.offset.as_secs_f64(),
+                            boundary.duration.as_secs_f64(),
+                            boundary.text,
+                        ))?;
+                    }
+                }
+            }
+        }
+        Ok(())
     }
 }
 
@@ -116,13 +229,7 @@ impl SpeechService {
             .map(|e| e.extract::<&str>())
             .transpose()?
             .unwrap_or("rest");
-
-        if mode != "rest" && mode != "websocket" {
-            return Err(PyValueError::new_err(format!(
-                "Invalid synthesizer mode: {}",
-                mode
-            )));
-        }
+        let mode = Self::parse_mode(mode)?;
 
         let endpoint = if let Some(endpoint) = options
             .and_then(|dict| dict.get_item("endpoint"))
@@ -136,12 +243,13 @@ impl SpeechService {
                 .map(|e| e.extract::<&str>())
                 .transpose()?
                 .map(|r| match mode {
-                    "rest" => get_rest_endpoint_by_region(r),
-                    "websocket" => get_websocket_endpoint_by_region(r),
-                    _ => unreachable!(),
+                    SynthesizerMode::Rest => get_rest_endpoint_by_region(r),
+                    SynthesizerMode::Websocket => get_websocket_endpoint_by_region(r),
                 })
                 .map(Cow::Owned)
-                .ok_or_else(|| PyValueError::new_err("No endpoint or region is specified!".to_string()))?
+                .ok_or_else(|| {
+                    PyValueError::new_err("No endpoint or region is specified!".to_string())
+                })?
         };
         let key: Option<String> = options
             .and_then(|dict| dict.get_item("key"))
@@ -181,34 +289,52 @@ impl SpeechService {
         } else {
             Vec::new()
         };
-        Ok(Self {
-            synthesizer: RefCell::new(runtime.block_on(async {
-                let conf = SynthesizerConfig::new(
-                    AuthOptions {
-                        endpoint: Cow::Borrowed(&endpoint),
-                        key: key.as_deref().map(Cow::Borrowed),
-                        headers: Cow::Borrowed(headers.as_slice()),
-                        token: token.as_deref().map(Cow::Borrowed),
-                        proxy: proxy.as_deref().map(Cow::Borrowed),
-                    },
-                    audio_format,
-                );
-                let boxed: Box<dyn UnifiedSynthesizer> = match mode {
-                    "rest" => Box::new(conf.rest_synthesizer()?),
-                    "websocket" => Box::new(conf.connect_websocket().await?),
-                    _ => unreachable!(),
-                };
-                Ok::<Box<dyn UnifiedSynthesizer>, PyErr>(boxed)
-            })?),
+        let conf = SynthesizerConfig::new(
+            AuthOptions {
+                endpoint: Cow::Borrowed(&endpoint),
+                key: key.as_deref().map(Cow::Borrowed),
+                headers: Cow::Borrowed(headers.as_slice()),
+                token: token.as_deref().map(Cow::Borrowed),
+                proxy: proxy.as_deref().map(Cow::Borrowed),
+            },
+            audio_format,
+        );
+        Self::create(runtime, conf, mode, TextOptions::default())
+    }
+
+    /// Create a speech service from a profile of the aspeak CLI, `~/.aspeak.toml` by default.
+    ///
+    /// The text options in the profile are used unless they are overridden in the calls.
+    #[staticmethod]
+    #[pyo3(signature = (path = None, mode = None))]
+    fn from_profile(path: Option<&str>, mode: Option<&str>) -> PyResult<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .enable_time()
+            .build()?;
+        let profile = match path {
+            Some(path) => Profile::load(path)?,
+            None => Profile::load_default()?.unwrap_or_default(),
+        };
+        let mode = mode
+            .map(Self::parse_mode)
+            .transpose()?
+            .unwrap_or_else(|| profile.mode());
+        let text_options = profile.text_options()?.into_owned();
+        Self::create(
             runtime,
-        })
+            profile.synthesizer_config(mode)?,
+            mode,
+            text_options,
+        )
     }
 
-    fn speak_ssml(&self, ssml: &str) -> PyResult<()> {
+    #[pyo3(signature = (ssml, **options))]
+    fn speak_ssml(&self, ssml: &str, options: Option<&PyDict>) -> PyResult<()> {
         let buffer = self
             .runtime
             .block_on(self.synthesizer.borrow_mut().as_mut().process_ssml(ssml))?;
-        play_owned_audio_blocking(buffer)?;
+        self.play(buffer, &Self::parse_playback_options(options)?)?;
         Ok(())
     }
 
@@ -236,13 +362,13 @@ impl SpeechService {
 
     #[pyo3(signature = (text, **options))]
     fn speak_text(&self, text: &str, options: Option<&PyDict>) -> PyResult<()> {
-        let buffer = self
-            .runtime
-            .block_on(self.synthesizer.borrow_mut().as_mut().process_text(
-                text,
-                &Self::parse_text_options(options)?.unwrap_or_default(),
-            ))?;
-        play_owned_audio_blocking(buffer)?;
+        let buffer = self.runtime.block_on(
+            self.synthesizer
+                .borrow_mut()
+                .as_mut()
+                .process_text(text, &self.parse_text_options(options)?),
+        )?;
+        self.play(buffer, &Self::parse_playback_options(options)?)?;
         Ok(())
     }
 
@@ -253,12 +379,12 @@ impl SpeechService {
         options: Option<&PyDict>,
         py: Python<'a>,
     ) -> PyResult<Option<&'a PyBytes>> {
-        let data = self
-            .runtime
-            .block_on(self.synthesizer.borrow_mut().as_mut().process_text(
-                text,
-                &Self::parse_text_options(options)?.unwrap_or_default(),
-            ))?;
+        let data = self.runtime.block_on(
+            self.synthesizer
+                .borrow_mut()
+                .as_mut()
+                .process_text(text, &self.parse_text_options(options)?),
+        )?;
         if let Some(output) = options
             .and_then(|d| d.get_item("output").map(|f| f.extract::<&str>()))
             .transpose()?
@@ -270,4 +396,46 @@ impl SpeechService {
             Ok(Some(PyBytes::new(py, &data)))
         }
     }
+
+    /// Synthesize SSML and pass the audio to `on_audio` in chunks as soon as they arrive.
+    ///
+    /// `on_boundary` receives the kind, the offset and duration in seconds and the text
+    /// of the word, punctuation and sentence boundaries. Only the websocket mode reports them.
+    #[pyo3(signature = (ssml, on_audio, on_boundary = None))]
+    fn stream_ssml(
+        &self,
+        ssml: &str,
+        on_audio: &PyAny,
+        on_boundary: Option<&PyAny>,
+        py: Python<'_>,
+    ) -> PyResult<()> {
+        let mut synthesizer = self.synthesizer.borrow_mut();
+        self.runtime.block_on(async {
+            let events = synthesizer.as_mut().process_ssml_events(ssml).await?;
+            Self::dispatch_events(py, events, on_audio, on_boundary).await
+        })
+    }
+
+    /// Synthesize text and pass the audio to `on_audio` in chunks as soon as they arrive.
+    ///
+    /// See `stream_ssml()` for the boundaries passed to `on_boundary`.
+    #[pyo3(signature = (text, on_audio, on_boundary = None, **options))]
+    fn stream_text(
+        &self,
+        text: &str,
+        on_audio: &PyAny,
+        on_boundary: Option<&PyAny>,
+        options: Option<&PyDict>,
+        py: Python<'_>,
+    ) -> PyResult<()> {
+        let text_options = self.parse_text_options(options)?;
+        let mut synthesizer = self.synthesizer.borrow_mut();
+        self.runtime.block_on(async {
+            let events = synthesizer
+                .as_mut()
+                .process_text_events(text, &text_options)
+                .await?;
+            Self::dispatch_events(py, events, on_audio, on_boundary).await
+        })
+    }
 }
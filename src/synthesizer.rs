@@ -1,14 +1,27 @@
+use std::time::Duration;
+
 use log::info;
+use serde::{Deserialize, Serialize};
 
-use crate::{AudioFormat, AuthOptions};
+use crate::{AudioFormat, AuthOptions, Timeouts, TlsOptions};
 
+#[cfg(any(feature = "unified-synthesizer", feature = "websocket-synthesizer"))]
+mod event;
+#[cfg(feature = "websocket-synthesizer")]
+mod pool;
 #[cfg(feature = "rest-synthesizer")]
 mod rest;
+#[cfg(all(test, feature = "websocket-synthesizer"))]
+mod test_server;
 #[cfg(feature = "unified-synthesizer")]
 mod unified;
 #[cfg(feature = "websocket-synthesizer")]
 mod websocket;
 
+#[cfg(any(feature = "unified-synthesizer", feature = "websocket-synthesizer"))]
+pub use event::*;
+#[cfg(feature = "websocket-synthesizer")]
+pub use pool::*;
 #[cfg(feature = "rest-synthesizer")]
 pub use rest::*;
 #[cfg(feature = "unified-synthesizer")]
@@ -16,13 +29,29 @@ pub use unified::*;
 #[cfg(feature = "websocket-synthesizer")]
 pub use websocket::*;
 /// Synthesizer configuration that can be used to create a [`RestSynthesizer`] or [`WebsocketSynthesizer`] .
-#[derive(Debug, Clone)]
+///
+/// With serde, it can be loaded from the configuration file of an application.
+/// The keepalive interval is given in seconds, like the [`Timeouts`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(unused)]
 pub struct SynthesizerConfig<'a> {
     /// The authentication options.
     pub(crate) auth: AuthOptions<'a>,
     /// The audio format of the output audio.
     pub(crate) audio_format: AudioFormat,
+    /// The timeouts for connecting and receiving the audio.
+    #[serde(default)]
+    pub(crate) timeouts: Timeouts,
+    /// The TLS options for the connections to the service.
+    #[serde(default, skip_serializing_if = "TlsOptions::is_empty")]
+    pub(crate) tls: TlsOptions,
+    /// The interval of keepalive pings on websocket connections.
+    #[serde(
+        default,
+        with = "crate::timeout::optional_secs",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) keepalive: Option<Duration>,
 }
 
 #[cfg(feature = "websocket-synthesizer")]
@@ -32,25 +61,87 @@ impl<'a> SynthesizerConfig<'a> {
     /// Create a new [`SynthesizerConfig`] with the given [`AuthOptions`] and [`AudioFormat`].
     pub fn new(auth: AuthOptions<'a>, audio_format: AudioFormat) -> Self {
         info!("Successfully created SynthesizerConfig");
-        Self { auth, audio_format }
+        Self {
+            auth,
+            audio_format,
+            timeouts: Timeouts::default(),
+            tls: TlsOptions::default(),
+            keepalive: None,
+        }
+    }
+
+    /// Convert into a [`SynthesizerConfig`] that owns its data,
+    /// e.g. to keep it in the state of a service or to move it into spawned tasks.
+    pub fn into_owned(self) -> SynthesizerConfig<'static> {
+        SynthesizerConfig {
+            auth: self.auth.into_owned(),
+            audio_format: self.audio_format,
+            timeouts: self.timeouts,
+            tls: self.tls,
+            keepalive: self.keepalive,
+        }
+    }
+
+    /// The authentication options.
+    pub fn auth(&self) -> &AuthOptions<'a> {
+        &self.auth
+    }
+
+    /// The audio format of the output audio.
+    pub fn audio_format(&self) -> AudioFormat {
+        self.audio_format
+    }
+
+    /// Use `timeouts` instead of the default [`Timeouts`].
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// The timeouts for connecting and receiving the audio.
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.timeouts
+    }
+
+    /// Use `tls` for the connections to the service instead of the default [`TlsOptions`].
+    pub fn with_tls(mut self, tls: TlsOptions) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// The TLS options for the connections to the service.
+    pub fn tls(&self) -> &TlsOptions {
+        &self.tls
+    }
+
+    /// Ping the server every `interval` while a [`WebsocketSynthesizer`] is idle.
+    ///
+    /// A failed ping marks the connection as unhealthy, see [`WebsocketSynthesizer::is_healthy`].
+    /// Keepalive pings are disabled by default.
+    pub fn with_keepalive(mut self, interval: Duration) -> Self {
+        self.keepalive = Some(interval);
+        self
+    }
+
+    /// The interval of keepalive pings on websocket connections.
+    pub fn keepalive(&self) -> Option<Duration> {
+        self.keepalive
     }
 
     #[cfg(feature = "websocket-synthesizer")]
     fn generate_client_request(
         &self,
+        connection_id: &str,
     ) -> Result<tokio_tungstenite::tungstenite::handshake::client::Request, WebsocketSynthesizerError>
     {
         use hyper::http::HeaderValue;
         use log::debug;
         use tokio_tungstenite::tungstenite::client::IntoClientRequest;
-        use uuid::Uuid;
 
-        let uuid = Uuid::new_v4();
-        let request_id = uuid.as_simple().to_string();
         let uri = {
             let mut url = url::Url::parse(&self.auth.endpoint)?;
             url.query_pairs_mut()
-                .append_pair("X-ConnectionId", &request_id);
+                .append_pair("X-ConnectionId", connection_id);
             if let Some(auth_token) = &self.auth.token {
                 url.query_pairs_mut()
                     .append_pair("Authorization", auth_token);
@@ -79,33 +170,71 @@ impl<'a> SynthesizerConfig<'a> {
     #[cfg(feature = "websocket-synthesizer")]
     pub async fn connect_websocket(
         self,
+    ) -> Result<WebsocketSynthesizer, WebsocketSynthesizerError> {
+        use crate::RequestDetails;
+        use uuid::Uuid;
+
+        let connection_id = Uuid::new_v4().as_simple().to_string();
+        self.open_websocket(&connection_id).await.map_err(|e| {
+            let mut details = RequestDetails::new(None, Some(connection_id.clone()));
+            // The service rejected the websocket upgrade
+            if let Some(tokio_tungstenite::tungstenite::Error::Http(response)) = e
+                .source
+                .as_ref()
+                .and_then(|source| source.chain().find_map(|e| e.downcast_ref()))
+            {
+                details.status = Some(response.status().as_u16());
+                details.message = response
+                    .body()
+                    .as_deref()
+                    .map(|body| String::from_utf8_lossy(body).trim().to_string())
+                    .filter(|body| !body.is_empty());
+            }
+            e.with_details(details)
+        })
+    }
+
+    /// Open `size` connections to the Azure Speech Service and return a [`WebsocketPool`] of them on success.
+    #[cfg(feature = "websocket-synthesizer")]
+    pub async fn connect_websocket_pool(
+        self,
+        size: usize,
+    ) -> Result<WebsocketPool<'a>, WebsocketSynthesizerError> {
+        WebsocketPool::connect(self, size).await
+    }
+
+    #[cfg(feature = "websocket-synthesizer")]
+    async fn open_websocket(
+        self,
+        connection_id: &str,
     ) -> Result<WebsocketSynthesizer, WebsocketSynthesizerError> {
         use crate::errors::{ConnectError, ConnectErrorKind};
         use crate::net::{self, connect_directly};
+        use crate::ProxyResolver;
         use chrono::Utc;
         use futures_util::SinkExt;
         use tokio_tungstenite::tungstenite::Message;
         use uuid::Uuid;
 
-        let request = self.generate_client_request()?;
-        let proxy_url = self
-            .auth
-            .proxy
-            .as_deref()
-            .map(reqwest::Url::parse)
-            .transpose()
-            .map_err(|e| ConnectError {
-                kind: ConnectErrorKind::BadUrl(self.auth.proxy.unwrap().to_string()),
-                source: Some(e.into()),
-            })?;
-        let mut wss = match proxy_url.as_ref().map(|x| x.scheme()) {
-            Some("socks5") => {
-                net::connect_via_socks5_proxy(request, proxy_url.as_ref().unwrap()).await?
+        let request = self.generate_client_request(connection_id)?;
+        let resolver =
+            ProxyResolver::new(self.auth.proxy.as_deref()).map_err(ConnectError::from)?;
+        let proxy_url = resolver.resolve(&url::Url::parse(&self.auth.endpoint)?);
+        let mut wss = match proxy_url.map(|x| x.scheme()) {
+            Some("socks5") | Some("socks5h") => {
+                net::connect_via_socks5_proxy(
+                    request,
+                    proxy_url.unwrap(),
+                    &self.timeouts,
+                    &self.tls,
+                )
+                .await?
             }
             Some("http") | Some("https") => {
-                net::connect_via_http_proxy(request, proxy_url.as_ref().unwrap()).await?
+                net::connect_via_http_proxy(request, proxy_url.unwrap(), &self.timeouts, &self.tls)
+                    .await?
             }
-            None => connect_directly(request).await?,
+            None => connect_directly(request, &self.timeouts, &self.tls).await?,
             Some(other_scheme) => {
                 return Err(ConnectError {
                     kind: ConnectErrorKind::UnsupportedScheme(Some(other_scheme.to_string())),
@@ -121,18 +250,21 @@ impl<'a> SynthesizerConfig<'a> {
             "Path: speech.config\r\nX-RequestId: {request_id}\r\nX-Timestamp: {now:?}Content-Type: application/json\r\n\r\n{CLIENT_INFO_PAYLOAD}"
         ))).await?;
         info!("Successfully created Synthesizer");
-        Ok(WebsocketSynthesizer {
-            audio_format: self.audio_format,
-            stream: wss,
-        })
+        Ok(WebsocketSynthesizer::new(
+            self.audio_format,
+            wss,
+            connection_id.to_string(),
+            self.timeouts,
+            self.keepalive,
+        ))
     }
 
     #[cfg(feature = "rest-synthesizer")]
     /// Construct a [`RestSynthesizer`] from this [`SynthesizerConfig`].
     pub fn rest_synthesizer(&self) -> Result<RestSynthesizer, RestSynthesizerError> {
         use crate::utils::{transpose_tuple_option_result, ClientBuilderExt};
+        use crate::ProxyResolver;
         use hyper::{header, http::HeaderValue};
-        use reqwest::Proxy;
 
         Ok(RestSynthesizer {
             client: reqwest::Client::builder()
@@ -165,23 +297,25 @@ impl<'a> SynthesizerConfig<'a> {
                     .flatten()
                     .chain(self.auth.headers.iter().map(Clone::clone)),
                 ))
-                .optional_proxy(
-                    self.auth
-                        .proxy
-                        .as_deref()
-                        .map(Proxy::all)
-                        .transpose()
-                        .map_err(|e| RestSynthesizerError {
-                            kind: RestSynthesizerErrorKind::Connect,
-                            source: Some(e.into()),
-                        })?,
-                )
+                .proxy_resolver(ProxyResolver::new(self.auth.proxy.as_deref()).map_err(|e| {
+                    RestSynthesizerError {
+                        kind: RestSynthesizerErrorKind::Connect,
+                        source: Some(e.into()),
+                    }
+                })?)
+                .connect_timeouts(&self.timeouts)
+                .tls_options(&self.tls)
+                .map_err(|e| RestSynthesizerError {
+                    kind: RestSynthesizerErrorKind::Connect,
+                    source: Some(e.into()),
+                })?
                 .build()
                 .map_err(|e| RestSynthesizerError {
                     kind: RestSynthesizerErrorKind::Connect,
                     source: Some(e.into()),
                 })?,
             endpoint: self.auth.endpoint.to_string(),
+            timeouts: self.timeouts,
         })
     }
 }
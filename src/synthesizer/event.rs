@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+use strum::AsRefStr;
+
+/// An event of a synthesis: a chunk of audio or a boundary in the synthesized text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SynthesisEvent {
+    /// A chunk of the synthesized audio.
+    Audio(Vec<u8>),
+    /// A word, punctuation or sentence is spoken.
+    Boundary(Boundary),
+}
+
+/// A word, punctuation or sentence boundary in the synthesized audio.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Boundary {
+    pub kind: BoundaryKind,
+    /// The offset of the audio of the text from the beginning of the audio.
+    pub offset: Duration,
+    /// The duration of the audio of the text.
+    pub duration: Duration,
+    /// The text that is spoken.
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsRefStr)]
+#[non_exhaustive]
+pub enum BoundaryKind {
+    /// The beginning of a word.
+    Word,
+    /// The beginning of a punctuation mark.
+    Punctuation,
+    /// The beginning of a sentence.
+    Sentence,
+}
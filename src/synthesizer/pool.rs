@@ -0,0 +1,445 @@
+use std::sync::Mutex;
+
+use futures_util::future::try_join_all;
+use futures_util::stream::{self, BoxStream, Stream, StreamExt, TryStreamExt};
+use log::{debug, info};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use super::{
+    CancellationToken, SynthesisEvent, SynthesizerConfig, WebsocketSynthesizer,
+    WebsocketSynthesizerError, WebsocketSynthesizerErrorKind,
+};
+use crate::{interpolate_ssml, TextOptions};
+
+/// A pool of websocket connections that serves concurrent requests.
+///
+/// Every request takes an idle connection from the pool, or opens a new one if there is none,
+/// and returns it to the pool afterwards. Broken connections are dropped and replaced on demand.
+/// At most `size` requests are in flight at the same time, the others wait for a connection.
+///
+/// The pool can be shared between tasks, e.g. in an [`Arc`](std::sync::Arc).
+pub struct WebsocketPool<'a> {
+    config: SynthesizerConfig<'a>,
+    size: usize,
+    idle: Mutex<Vec<WebsocketSynthesizer>>,
+    permits: Semaphore,
+}
+
+impl<'a> WebsocketPool<'a> {
+    pub(super) async fn connect(
+        config: SynthesizerConfig<'a>,
+        size: usize,
+    ) -> Result<WebsocketPool<'a>, WebsocketSynthesizerError> {
+        let size = size.max(1);
+        let connections =
+            try_join_all((0..size).map(|_| config.clone().connect_websocket())).await?;
+        info!("Successfully created a pool of {size} websocket connections");
+        Ok(Self {
+            config,
+            size,
+            idle: Mutex::new(connections),
+            permits: Semaphore::new(size),
+        })
+    }
+
+    /// The maximum number of connections, which is also the maximum number of concurrent requests.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The number of connections that are open and not serving a request.
+    pub fn idle_connections(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    /// Synthesize the given SSML into audio([`Vec<u8>`]).
+    ///
+    /// If a connection from the pool turns out to be broken, the request is retried once on a new connection.
+    pub async fn synthesize_ssml(&self, ssml: &str) -> Result<Vec<u8>, WebsocketSynthesizerError> {
+        self.synthesize_ssml_with_cancellation(ssml, &CancellationToken::new())
+            .await
+    }
+
+    /// Synthesize the given SSML into audio([`Vec<u8>`]), unless `cancellation` is cancelled first.
+    ///
+    /// Unlike dropping the future of [`WebsocketPool::synthesize_ssml`],
+    /// this returns the connection to the pool after cancellation.
+    pub async fn synthesize_ssml_with_cancellation(
+        &self,
+        ssml: &str,
+        cancellation: &CancellationToken,
+    ) -> Result<Vec<u8>, WebsocketSynthesizerError> {
+        let mut lease = self.lease(cancellation).await?;
+        let mut result = lease
+            .synthesizer()
+            .synthesize_ssml_with_cancellation(ssml, cancellation)
+            .await;
+        if lease.reused && is_broken(&result) {
+            debug!("The connection from the pool is broken, retrying on a new connection");
+            lease.replace(self.open_connection(cancellation).await?);
+            result = lease
+                .synthesizer()
+                .synthesize_ssml_with_cancellation(ssml, cancellation)
+                .await;
+        }
+        result
+    }
+
+    /// Synthesize the given SSML and receive the audio in chunks as soon as they arrive.
+    ///
+    /// The connection serves no other request of the pool until the stream is finished or dropped.
+    pub async fn synthesize_ssml_stream(
+        &self,
+        ssml: &str,
+    ) -> Result<
+        impl Stream<Item = Result<Vec<u8>, WebsocketSynthesizerError>> + Send + '_,
+        WebsocketSynthesizerError,
+    > {
+        Ok(self
+            .stream_events(ssml, false)
+            .await?
+            .try_filter_map(|event| async move {
+                Ok(match event {
+                    SynthesisEvent::Audio(data) => Some(data),
+                    _ => None,
+                })
+            }))
+    }
+
+    /// Synthesize the given SSML and receive the audio in chunks
+    /// along with the word, punctuation and sentence boundaries as soon as they arrive.
+    ///
+    /// The connection serves no other request of the pool until the stream is finished or dropped.
+    pub async fn synthesize_ssml_events(
+        &self,
+        ssml: &str,
+    ) -> Result<
+        impl Stream<Item = Result<SynthesisEvent, WebsocketSynthesizerError>> + Send + '_,
+        WebsocketSynthesizerError,
+    > {
+        self.stream_events(ssml, true).await
+    }
+
+    /// Synthesize the given text into audio([`Vec<u8>`]).
+    /// This is a convenience method that interpolates the SSML for you.
+    pub async fn synthesize_text(
+        &self,
+        text: impl AsRef<str>,
+        options: &TextOptions<'_>,
+    ) -> Result<Vec<u8>, WebsocketSynthesizerError> {
+        debug!("Synthesizing text: {}", text.as_ref());
+        let ssml = interpolate_ssml(text, options)?;
+        self.synthesize_ssml(&ssml).await
+    }
+
+    /// Synthesize the given text into audio([`Vec<u8>`]), unless `cancellation` is cancelled first.
+    /// This is a convenience method that interpolates the SSML for you.
+    pub async fn synthesize_text_with_cancellation(
+        &self,
+        text: impl AsRef<str>,
+        options: &TextOptions<'_>,
+        cancellation: &CancellationToken,
+    ) -> Result<Vec<u8>, WebsocketSynthesizerError> {
+        debug!("Synthesizing text: {}", text.as_ref());
+        let ssml = interpolate_ssml(text, options)?;
+        self.synthesize_ssml_with_cancellation(&ssml, cancellation)
+            .await
+    }
+
+    /// Stream the events of a synthesis on a connection of the pool, which is returned when the stream is dropped.
+    ///
+    /// The request is retried on a new connection if a connection from the pool fails before the first event.
+    async fn stream_events(
+        &self,
+        ssml: &str,
+        metadata: bool,
+    ) -> Result<
+        impl Stream<Item = Result<SynthesisEvent, WebsocketSynthesizerError>> + Send + '_,
+        WebsocketSynthesizerError,
+    > {
+        let cancellation = CancellationToken::new();
+        let mut lease = self.lease(&cancellation).await?;
+        let mut started = start_events(lease.synthesizer(), ssml, metadata).await;
+        if lease.reused && is_broken(&started) {
+            debug!("The connection from the pool is broken, retrying on a new connection");
+            lease.replace(self.open_connection(&cancellation).await?);
+            started = start_events(lease.synthesizer(), ssml, metadata).await;
+        }
+        let (first, events) = started?;
+        Ok(stream::iter(first.map(Ok)).chain(events).map(move |event| {
+            // The connection returns to the pool when the stream is dropped
+            let _lease = &lease;
+            event
+        }))
+    }
+
+    /// Wait for a permit and take a connection from the pool, unless `cancellation` is cancelled first.
+    async fn lease(
+        &self,
+        cancellation: &CancellationToken,
+    ) -> Result<Lease<'_, 'a>, WebsocketSynthesizerError> {
+        let permit = tokio::select! {
+            permit = self.permits.acquire() => permit.expect("the semaphore is never closed"),
+            _ = cancellation.cancelled() => return Err(WebsocketSynthesizerError::cancelled()),
+        };
+        let (synthesizer, reused) = match self.take_idle() {
+            Some(synthesizer) => (synthesizer, true),
+            None => (self.open_connection(cancellation).await?, false),
+        };
+        Ok(Lease {
+            pool: self,
+            synthesizer: Some(synthesizer),
+            reused,
+            _permit: permit,
+        })
+    }
+
+    /// Open a new connection, unless `cancellation` is cancelled first.
+    async fn open_connection(
+        &self,
+        cancellation: &CancellationToken,
+    ) -> Result<WebsocketSynthesizer, WebsocketSynthesizerError> {
+        tokio::select! {
+            synthesizer = self.config.clone().connect_websocket() => synthesizer,
+            _ = cancellation.cancelled() => Err(WebsocketSynthesizerError::cancelled()),
+        }
+    }
+
+    /// Take a healthy idle connection, dropping the unhealthy ones.
+    fn take_idle(&self) -> Option<WebsocketSynthesizer> {
+        let mut idle = self.idle.lock().unwrap();
+        while let Some(synthesizer) = idle.pop() {
+            if synthesizer.is_healthy() {
+                return Some(synthesizer);
+            }
+            debug!(
+                "Dropping unhealthy connection {}",
+                synthesizer.connection_id()
+            );
+        }
+        None
+    }
+}
+
+/// A connection taken from a [`WebsocketPool`], which is returned to the pool on drop if it is still healthy.
+struct Lease<'p, 'a> {
+    pool: &'p WebsocketPool<'a>,
+    synthesizer: Option<WebsocketSynthesizer>,
+    /// Whether the connection was idle in the pool, so it may have broken in the meantime
+    reused: bool,
+    _permit: SemaphorePermit<'p>,
+}
+
+impl Lease<'_, '_> {
+    fn synthesizer(&self) -> &WebsocketSynthesizer {
+        self.synthesizer
+            .as_ref()
+            .expect("the synthesizer is only taken on drop")
+    }
+
+    /// Replace the connection with a new one.
+    fn replace(&mut self, synthesizer: WebsocketSynthesizer) {
+        self.synthesizer = Some(synthesizer);
+        self.reused = false;
+    }
+}
+
+impl Drop for Lease<'_, '_> {
+    fn drop(&mut self) {
+        if let Some(synthesizer) = self.synthesizer.take() {
+            if synthesizer.is_healthy() {
+                self.pool.idle.lock().unwrap().push(synthesizer);
+            }
+        }
+    }
+}
+
+/// Send the SSML and wait for the first event, so that a broken connection is detected before streaming.
+async fn start_events(
+    synthesizer: &WebsocketSynthesizer,
+    ssml: &str,
+    metadata: bool,
+) -> Result<
+    (
+        Option<SynthesisEvent>,
+        BoxStream<'static, Result<SynthesisEvent, WebsocketSynthesizerError>>,
+    ),
+    WebsocketSynthesizerError,
+> {
+    let mut events = synthesizer
+        .synthesize_ssml_with_metadata(ssml, metadata)
+        .await?
+        .boxed();
+    let first = events.next().await.transpose()?;
+    Ok((first, events))
+}
+
+/// Whether a request failed because the connection is broken,
+/// e.g. the service closed it while it was idle in the pool.
+fn is_broken<T>(result: &Result<T, WebsocketSynthesizerError>) -> bool {
+    matches!(
+        result,
+        Err(WebsocketSynthesizerError {
+            kind: WebsocketSynthesizerErrorKind::Websocket
+                | WebsocketSynthesizerErrorKind::WebsocketConnectionClosed { .. }
+                | WebsocketSynthesizerErrorKind::Unhealthy,
+            ..
+        })
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures_util::future::join_all;
+
+    use super::super::test_server::{config, echo, next_ssml, respond, serve, ServerStream};
+    use super::*;
+    use crate::TimeoutPhase;
+
+    fn failed(kind: WebsocketSynthesizerErrorKind) -> Result<(), WebsocketSynthesizerError> {
+        Err(WebsocketSynthesizerError { kind, source: None })
+    }
+
+    #[test]
+    fn broken_connections() {
+        assert!(is_broken(&failed(WebsocketSynthesizerErrorKind::Websocket)));
+        assert!(is_broken(&failed(
+            WebsocketSynthesizerErrorKind::WebsocketConnectionClosed {
+                code: "1000".to_string(),
+                reason: "Idle timeout".to_string(),
+            }
+        )));
+        assert!(is_broken(&failed(WebsocketSynthesizerErrorKind::Unhealthy)));
+    }
+
+    #[test]
+    fn failed_requests_on_healthy_connections() {
+        assert!(!is_broken(&Ok(())));
+        for kind in [
+            WebsocketSynthesizerErrorKind::InvalidRequest,
+            WebsocketSynthesizerErrorKind::InvalidMessage,
+            WebsocketSynthesizerErrorKind::Timeout(TimeoutPhase::FirstByte),
+            WebsocketSynthesizerErrorKind::Cancelled,
+        ] {
+            assert!(!is_broken(&failed(kind)));
+        }
+    }
+
+    /// Serve the connections with `handler`, which gets the number of the connection
+    async fn serve_numbered<F, Fut>(handler: F) -> (String, Arc<AtomicUsize>)
+    where
+        F: Fn(ServerStream, usize) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&connections);
+        let url = serve(move |ws| handler(ws, counter.fetch_add(1, Ordering::SeqCst))).await;
+        (url, connections)
+    }
+
+    /// Close a connection once its first SSML arrives
+    async fn close_on_first_request(mut ws: ServerStream) {
+        next_ssml(&mut ws).await;
+        ws.close(None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_are_limited_by_size() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+        let (url, connections) = serve_numbered({
+            let (active, max_active) = (Arc::clone(&active), Arc::clone(&max_active));
+            move |mut ws, _| {
+                let (active, max_active) = (Arc::clone(&active), Arc::clone(&max_active));
+                async move {
+                    while let Some((request_id, ssml)) = next_ssml(&mut ws).await {
+                        let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_active.fetch_max(now, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        active.fetch_sub(1, Ordering::SeqCst);
+                        respond(&mut ws, &request_id, ssml.as_bytes()).await;
+                    }
+                }
+            }
+        })
+        .await;
+        let pool = config(&url).connect_websocket_pool(2).await.unwrap();
+        let ssmls: Vec<_> = (0..6).map(|i| format!("request {i}")).collect();
+        let results = join_all(ssmls.iter().map(|ssml| pool.synthesize_ssml(ssml))).await;
+        for (result, ssml) in results.into_iter().zip(&ssmls) {
+            assert_eq!(result.unwrap(), ssml.as_bytes());
+        }
+        assert!(max_active.load(Ordering::SeqCst) <= 2);
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.idle_connections(), 2);
+    }
+
+    #[tokio::test]
+    async fn lease_returns_to_pool_on_drop() {
+        let url = serve(echo).await;
+        let pool = config(&url).connect_websocket_pool(1).await.unwrap();
+        assert_eq!(pool.idle_connections(), 1);
+        let mut stream = pool.synthesize_ssml_stream("ssml").await.unwrap().boxed();
+        assert_eq!(pool.idle_connections(), 0);
+        assert_eq!(stream.next().await.unwrap().unwrap(), b"ssml");
+        drop(stream);
+        assert_eq!(pool.idle_connections(), 1);
+    }
+
+    #[tokio::test]
+    async fn unhealthy_connections_are_discarded() {
+        let (url, connections) = serve_numbered(|mut ws, connection| async move {
+            if connection == 0 {
+                // Close the first connection after the client sent speech.config
+                ws.next().await;
+                ws.close(None).await.unwrap();
+            } else {
+                echo(ws).await;
+            }
+        })
+        .await;
+        let pool = config(&url).connect_websocket_pool(1).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while pool.idle.lock().unwrap()[0].is_healthy() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(pool.take_idle().is_none());
+        assert_eq!(pool.idle_connections(), 0);
+        assert_eq!(pool.synthesize_ssml("ssml").await.unwrap(), b"ssml");
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.idle_connections(), 1);
+    }
+
+    #[tokio::test]
+    async fn broken_connection_is_retried_once() {
+        let (url, connections) = serve_numbered(|ws, connection| async move {
+            if connection == 0 {
+                close_on_first_request(ws).await;
+            } else {
+                echo(ws).await;
+            }
+        })
+        .await;
+        let pool = config(&url).connect_websocket_pool(1).await.unwrap();
+        assert_eq!(pool.synthesize_ssml("ssml").await.unwrap(), b"ssml");
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+
+        // The retry on the new connection fails as well
+        let (url, connections) =
+            serve_numbered(|ws, _| async move { close_on_first_request(ws).await }).await;
+        let pool = config(&url).connect_websocket_pool(1).await.unwrap();
+        let err = pool.synthesize_ssml("ssml").await.unwrap_err();
+        assert!(matches!(
+            err.kind,
+            WebsocketSynthesizerErrorKind::WebsocketConnectionClosed { .. }
+        ));
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+}
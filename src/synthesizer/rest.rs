@@ -3,18 +3,25 @@ use std::{
     fmt::{self, Display, Formatter},
 };
 
-use bytes::Bytes;
+use std::pin::pin;
+
+use bytes::{Bytes, BytesMut};
+use futures_util::stream::{try_unfold, Stream, StreamExt};
 use hyper::header::{InvalidHeaderName, InvalidHeaderValue};
 use log::debug;
 use reqwest::{Client, StatusCode};
 use strum::AsRefStr;
+use tokio::io::AsyncRead;
+use tokio_util::io::StreamReader;
 
-use crate::{interpolate_ssml, SsmlError, TextOptions};
+use crate::timeout::{timeout, TimeoutError};
+use crate::{interpolate_ssml, RequestDetails, SsmlError, TextOptions, TimeoutPhase, Timeouts};
 
 /// The synthesizer that uses the RESTful API.
 pub struct RestSynthesizer {
     pub(super) client: Client,
     pub(super) endpoint: String,
+    pub(super) timeouts: Timeouts,
 }
 
 impl RestSynthesizer {
@@ -28,39 +35,108 @@ impl RestSynthesizer {
         &self,
         ssml: &str,
     ) -> Result<Bytes, RestSynthesizerError> {
-        let res = self
-            .client
-            .post(&self.endpoint)
-            .body(ssml.to_string())
-            .send()
-            .await
+        let mut stream = pin!(self.synthesize_ssml_stream(ssml).await?);
+        let mut bytes = BytesMut::new();
+        while let Some(chunk) = stream.next().await {
+            bytes.extend_from_slice(&chunk?);
+        }
+        Ok(bytes.freeze())
+    }
+
+    /// Synthesize the given SSML into a stream of audio chunks as the service sends them.
+    ///
+    /// Errors of the request are returned right away, errors while receiving the audio end the stream.
+    pub async fn synthesize_ssml_stream(
+        &self,
+        ssml: &str,
+    ) -> Result<
+        impl Stream<Item = Result<Bytes, RestSynthesizerError>> + Send + 'static,
+        RestSynthesizerError,
+    > {
+        let (response, request_id) = self.send(ssml).await?;
+        let timeouts = self.timeouts;
+        Ok(try_unfold(response, move |mut response| {
+            let request_id = request_id.clone();
+            async move {
+                let chunk = timeout(&timeouts, TimeoutPhase::Idle, response.chunk())
+                    .await
+                    .map_err(RestSynthesizerError::from)
+                    .and_then(|chunk| {
+                        chunk.map_err(|e| RestSynthesizerError {
+                            kind: RestSynthesizerErrorKind::Connection,
+                            source: Some(e.into()),
+                        })
+                    })
+                    .map_err(|e| e.with_details(RequestDetails::new(request_id, None)))?;
+                Ok(chunk.map(|chunk| (chunk, response)))
+            }
+        }))
+    }
+
+    /// Synthesize the given SSML into an [`AsyncRead`] of the audio as the service sends it.
+    ///
+    /// Errors while receiving the audio are returned as [`std::io::Error`]s
+    /// that wrap a [`RestSynthesizerError`].
+    pub async fn synthesize_ssml_reader(
+        &self,
+        ssml: &str,
+    ) -> Result<impl AsyncRead + Send + Unpin + 'static, RestSynthesizerError> {
+        let stream = self.synthesize_ssml_stream(ssml).await?;
+        Ok(StreamReader::new(Box::pin(
+            stream.map(|chunk| chunk.map_err(std::io::Error::other)),
+        )))
+    }
+
+    /// Send the request and check the status of the response,
+    /// returning the response and the ID the service assigned to the request.
+    async fn send(
+        &self,
+        ssml: &str,
+    ) -> Result<(reqwest::Response, Option<String>), RestSynthesizerError> {
+        // reqwest does not tell us when the connection is established,
+        // so the first byte timeout also includes the time for connecting.
+        let mut send_timeouts = self.timeouts;
+        send_timeouts.first_byte = self.timeouts.first_byte.map(|d| {
+            d + self.timeouts.connect.unwrap_or_default()
+                + self.timeouts.tls_handshake.unwrap_or_default()
+        });
+        let request = self.client.post(&self.endpoint).body(ssml.to_string());
+        let res = timeout(&send_timeouts, TimeoutPhase::FirstByte, request.send())
+            .await?
             .map_err(|e| RestSynthesizerError {
-                kind: RestSynthesizerErrorKind::Connect,
+                kind: if e.is_timeout() {
+                    RestSynthesizerErrorKind::Timeout(TimeoutPhase::Connect)
+                } else {
+                    RestSynthesizerErrorKind::Connect
+                },
                 source: Some(e.into()),
-            })?
-            .error_for_status()
-            .map_err(|e| {
-                use RestSynthesizerErrorKind::*;
-                let kind = match e.status() {
-                    Some(code) => match code {
-                        StatusCode::TOO_MANY_REQUESTS => TooManyRequests,
-                        StatusCode::UNAUTHORIZED => Unauthorized,
-                        StatusCode::BAD_REQUEST => InvalidRequest,
-                        StatusCode::UNSUPPORTED_MEDIA_TYPE => UnsupportedMediaType,
-                        _ => OtherHttp,
-                    },
-                    None => OtherHttp,
-                };
-                RestSynthesizerError {
-                    kind,
-                    source: Some(e.into()),
-                }
             })?;
-        let bytes = res.bytes().await.map_err(|e| RestSynthesizerError {
-            kind: RestSynthesizerErrorKind::Connection,
-            source: Some(e.into()),
-        })?;
-        Ok(bytes)
+        let mut details = RequestDetails::new(request_id(&res), None);
+        if let Err(e) = res.error_for_status_ref() {
+            use RestSynthesizerErrorKind::*;
+            let status = res.status();
+            let kind = match status {
+                StatusCode::TOO_MANY_REQUESTS => TooManyRequests,
+                StatusCode::UNAUTHORIZED => Unauthorized,
+                StatusCode::BAD_REQUEST => InvalidRequest,
+                StatusCode::UNSUPPORTED_MEDIA_TYPE => UnsupportedMediaType,
+                _ => OtherHttp,
+            };
+            details.status = Some(status.as_u16());
+            // The body is only a bonus, the status is the actual error.
+            details.message = timeout(&self.timeouts, TimeoutPhase::Idle, res.text())
+                .await
+                .ok()
+                .and_then(Result::ok)
+                .map(|body| body.trim().to_string())
+                .filter(|body| !body.is_empty());
+            return Err(RestSynthesizerError {
+                kind,
+                source: Some(e.into()),
+            }
+            .with_details(details));
+        }
+        Ok((res, details.request_id))
     }
 
     /// This is a convenience method that interpolates the SSML for you.
@@ -86,6 +162,14 @@ impl RestSynthesizer {
     }
 }
 
+/// The ID the service assigned to the request of `response`.
+fn request_id(response: &reqwest::Response) -> Option<String> {
+    ["X-RequestId", "apim-request-id"]
+        .into_iter()
+        .find_map(|name| response.headers().get(name)?.to_str().ok())
+        .map(ToString::to_string)
+}
+
 /// Errors that can occur while using the RESTful API.
 #[derive(Debug)]
 #[non_exhaustive]
@@ -94,6 +178,18 @@ pub struct RestSynthesizerError {
     pub(crate) source: Option<anyhow::Error>,
 }
 
+impl RestSynthesizerError {
+    /// The HTTP status, the error message and the request ID of the failed request, if the service responded.
+    pub fn details(&self) -> Option<&RequestDetails> {
+        RequestDetails::find(&self.source)
+    }
+
+    pub(crate) fn with_details(mut self, details: RequestDetails) -> Self {
+        details.attach_to(&mut self.source);
+        self
+    }
+}
+
 impl Display for RestSynthesizerError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         use RestSynthesizerErrorKind::*;
@@ -108,6 +204,7 @@ impl Display for RestSynthesizerError {
                 f,
                 "you are unauthorized. Did you set up the auth key/token?"
             ),
+            Timeout(phase) => write!(f, "{phase} timed out"),
             _ => write!(f, "{} error", self.kind.as_ref()),
         }
     }
@@ -146,6 +243,8 @@ pub enum RestSynthesizerErrorKind {
     Connection,
     /// Errors when interpolating SSML.
     Ssml,
+    /// A timeout expired while connecting or waiting for the audio.
+    Timeout(TimeoutPhase),
 }
 
 macro_rules! impl_from_for_rest_synthesizer_error {
@@ -164,3 +263,12 @@ macro_rules! impl_from_for_rest_synthesizer_error {
 impl_from_for_rest_synthesizer_error!(InvalidHeaderValue, InvalidRequest);
 impl_from_for_rest_synthesizer_error!(InvalidHeaderName, InvalidRequest);
 impl_from_for_rest_synthesizer_error!(SsmlError, Ssml);
+
+impl From<TimeoutError> for RestSynthesizerError {
+    fn from(e: TimeoutError) -> Self {
+        Self {
+            kind: RestSynthesizerErrorKind::Timeout(e.phase),
+            source: Some(e.into()),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! A local websocket server that stands in for the speech service in tests.
+
+use std::future::Future;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+
+use crate::{AudioFormat, AuthOptionsBuilder, SynthesizerConfig};
+
+pub(crate) type ServerStream = WebSocketStream<TcpStream>;
+
+/// Serve every connection with `handler` and return the url of the server.
+pub(crate) async fn serve<F, Fut>(handler: F) -> String
+where
+    F: Fn(ServerStream) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            tokio::spawn(handler(ws));
+        }
+    });
+    url
+}
+
+/// A config that connects to the server at `url` without a proxy.
+pub(crate) fn config(url: &str) -> SynthesizerConfig<'static> {
+    let auth = AuthOptionsBuilder::new(url.to_string()).proxy("").build();
+    SynthesizerConfig::new(auth, AudioFormat::Raw16Khz16BitMonoPcm)
+}
+
+/// Receive the next SSML, returning its `X-RequestId` and the SSML, or `None` when the connection is closed.
+pub(crate) async fn next_ssml(ws: &mut ServerStream) -> Option<(String, String)> {
+    while let Some(Ok(message)) = ws.next().await {
+        let Message::Text(text) = message else {
+            continue;
+        };
+        let (headers, ssml) = text.split_once("\r\n\r\n")?;
+        if !headers.starts_with("Path: ssml") {
+            continue;
+        }
+        let request_id = headers
+            .split("\r\n")
+            .find_map(|header| header.strip_prefix("X-RequestId: "))?;
+        return Some((request_id.to_string(), ssml.to_string()));
+    }
+    None
+}
+
+/// A text message of the service with the given `Path`.
+pub(crate) fn text(path: &str, request_id: &str, body: &str) -> Message {
+    Message::Text(format!(
+        "Path:{path}\r\nX-RequestId:{request_id}\r\n\r\n{body}"
+    ))
+}
+
+/// An audio message of the service.
+pub(crate) fn audio(request_id: &str, data: &[u8]) -> Message {
+    let headers = format!("Path:audio\r\nX-RequestId:{request_id}\r\n");
+    let mut frame = (headers.len() as u16).to_be_bytes().to_vec();
+    frame.extend_from_slice(headers.as_bytes());
+    frame.extend_from_slice(data);
+    Message::Binary(frame)
+}
+
+/// Send `data` as the audio of a request, followed by its `turn.end`.
+pub(crate) async fn respond(ws: &mut ServerStream, request_id: &str, data: &[u8]) {
+    ws.send(audio(request_id, data)).await.unwrap();
+    ws.send(text("turn.end", request_id, "")).await.unwrap();
+}
+
+/// Answer every request with its SSML as the audio until the connection is closed.
+pub(crate) async fn echo(mut ws: ServerStream) {
+    while let Some((request_id, ssml)) = next_ssml(&mut ws).await {
+        respond(&mut ws, &request_id, ssml.as_bytes()).await;
+    }
+}
@@ -4,10 +4,17 @@ use std::{
 };
 
 use async_trait::async_trait;
+use futures_util::stream::{self, BoxStream, StreamExt, TryStreamExt};
 use log::debug;
 use strum::AsRefStr;
 
-use crate::{interpolate_ssml, SsmlError, TextOptions};
+use crate::{interpolate_ssml, SsmlError, SynthesisEvent, TextOptions};
+
+/// The audio of a synthesis in chunks, see [`UnifiedSynthesizer::process_ssml_stream`].
+pub type AudioStream<'a> = BoxStream<'a, Result<Vec<u8>, UnifiedSynthesizerError>>;
+
+/// The audio and boundaries of a synthesis, see [`UnifiedSynthesizer::process_ssml_events`].
+pub type EventStream<'a> = BoxStream<'a, Result<SynthesisEvent, UnifiedSynthesizerError>>;
 
 #[async_trait]
 pub trait UnifiedSynthesizer: Send {
@@ -23,6 +30,53 @@ pub trait UnifiedSynthesizer: Send {
         let ssml = interpolate_ssml(text, options)?;
         self.process_ssml(&ssml).await
     }
+    /// Synthesize the given SSML and receive the audio in chunks.
+    ///
+    /// The default implementation returns the audio of [`UnifiedSynthesizer::process_ssml`] as a single chunk,
+    /// the synthesizers of this crate yield the chunks as soon as they arrive.
+    async fn process_ssml_stream<'s>(
+        &'s mut self,
+        ssml: &str,
+    ) -> Result<AudioStream<'s>, UnifiedSynthesizerError> {
+        let audio = self.process_ssml(ssml).await?;
+        Ok(stream::once(async { Ok(audio) }).boxed())
+    }
+    /// This is a convenience method that interpolates the SSML for you.
+    async fn process_text_stream<'s>(
+        &'s mut self,
+        text: &str,
+        options: &TextOptions<'_>,
+    ) -> Result<AudioStream<'s>, UnifiedSynthesizerError> {
+        debug!("Synthesizing text: {}", text);
+        let ssml = interpolate_ssml(text, options)?;
+        self.process_ssml_stream(&ssml).await
+    }
+    /// Synthesize the given SSML and receive the audio in chunks
+    /// along with the word, punctuation and sentence boundaries.
+    ///
+    /// The default implementation only yields the [`SynthesisEvent::Audio`] events of
+    /// [`UnifiedSynthesizer::process_ssml_stream`], which is also what backends without boundary support do,
+    /// e.g. [`RestSynthesizer`](crate::synthesizer::RestSynthesizer).
+    async fn process_ssml_events<'s>(
+        &'s mut self,
+        ssml: &str,
+    ) -> Result<EventStream<'s>, UnifiedSynthesizerError> {
+        Ok(self
+            .process_ssml_stream(ssml)
+            .await?
+            .map_ok(SynthesisEvent::Audio)
+            .boxed())
+    }
+    /// This is a convenience method that interpolates the SSML for you.
+    async fn process_text_events<'s>(
+        &'s mut self,
+        text: &str,
+        options: &TextOptions<'_>,
+    ) -> Result<EventStream<'s>, UnifiedSynthesizerError> {
+        debug!("Synthesizing text: {}", text);
+        let ssml = interpolate_ssml(text, options)?;
+        self.process_ssml_events(&ssml).await
+    }
 }
 
 /// Errors that can occur when creating and using a [`UnifiedSynthesizer`].
@@ -33,6 +87,13 @@ pub struct UnifiedSynthesizerError {
     pub(crate) source: Option<anyhow::Error>,
 }
 
+impl UnifiedSynthesizerError {
+    /// The HTTP status, the error message and the request and connection IDs of the failed request, if any.
+    pub fn details(&self) -> Option<&crate::RequestDetails> {
+        crate::RequestDetails::find(&self.source)
+    }
+}
+
 impl Display for UnifiedSynthesizerError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         use UnifiedSynthesizerErrorKind::*;
@@ -43,6 +104,7 @@ impl Display for UnifiedSynthesizerError {
                 f,
                 "an invalid request is constructed or 400 status reported by the server"
             ),
+            Timeout(phase) => write!(f, "{phase} timed out"),
             _ => write!(f, "{} error", self.kind.as_ref()),
         }
     }
@@ -78,6 +140,10 @@ pub enum UnifiedSynthesizerErrorKind {
     InvalidMessage,
     /// Errors that occur while processing SSML.
     Ssml,
+    /// A timeout expired while connecting or waiting for the audio.
+    Timeout(crate::TimeoutPhase),
+    /// The request was cancelled.
+    Cancelled,
 }
 
 macro_rules! impl_from_for_unified_synthesizer_error {
@@ -124,6 +190,10 @@ impl From<super::RestSynthesizerError> for UnifiedSynthesizerError {
                 kind: Ssml,
                 source: Some(value.into()),
             },
+            RestKind::Timeout(phase) => Self {
+                kind: Timeout(*phase),
+                source: Some(value.into()),
+            },
         }
     }
 }
@@ -142,7 +212,7 @@ impl From<super::WebsocketSynthesizerError> for UnifiedSynthesizerError {
                 kind: Connection,
                 source: Some(value.into()),
             },
-            WsKind::Websocket => Self {
+            WsKind::Websocket | WsKind::Unhealthy => Self {
                 kind: Connection,
                 source: Some(value.into()),
             },
@@ -158,6 +228,14 @@ impl From<super::WebsocketSynthesizerError> for UnifiedSynthesizerError {
                 kind: Ssml,
                 source: Some(value.into()),
             },
+            WsKind::Cancelled => Self {
+                kind: Cancelled,
+                source: Some(value.into()),
+            },
+            WsKind::Timeout(phase) => Self {
+                kind: Timeout(*phase),
+                source: Some(value.into()),
+            },
         }
     }
 }
@@ -168,6 +246,18 @@ impl UnifiedSynthesizer for super::RestSynthesizer {
     async fn process_ssml(&mut self, ssml: &str) -> Result<Vec<u8>, UnifiedSynthesizerError> {
         Ok(self.synthesize_ssml(ssml).await?)
     }
+
+    async fn process_ssml_stream<'s>(
+        &'s mut self,
+        ssml: &str,
+    ) -> Result<AudioStream<'s>, UnifiedSynthesizerError> {
+        Ok(self
+            .synthesize_ssml_stream(ssml)
+            .await?
+            .map_ok(Vec::from)
+            .err_into()
+            .boxed())
+    }
 }
 
 #[cfg(feature = "websocket-synthesizer")]
@@ -176,4 +266,38 @@ impl UnifiedSynthesizer for super::WebsocketSynthesizer {
     async fn process_ssml(&mut self, ssml: &str) -> Result<Vec<u8>, UnifiedSynthesizerError> {
         Ok(self.synthesize_ssml(ssml).await?)
     }
+    async fn process_ssml_stream<'s>(
+        &'s mut self,
+        ssml: &str,
+    ) -> Result<AudioStream<'s>, UnifiedSynthesizerError> {
+        Ok(self.synthesize_ssml_stream(ssml).await?.err_into().boxed())
+    }
+
+    async fn process_ssml_events<'s>(
+        &'s mut self,
+        ssml: &str,
+    ) -> Result<EventStream<'s>, UnifiedSynthesizerError> {
+        Ok(self.synthesize_ssml_events(ssml).await?.err_into().boxed())
+    }
+}
+
+#[cfg(feature = "websocket-synthesizer")]
+#[async_trait]
+impl UnifiedSynthesizer for super::WebsocketPool<'_> {
+    async fn process_ssml(&mut self, ssml: &str) -> Result<Vec<u8>, UnifiedSynthesizerError> {
+        Ok(self.synthesize_ssml(ssml).await?)
+    }
+    async fn process_ssml_stream<'s>(
+        &'s mut self,
+        ssml: &str,
+    ) -> Result<AudioStream<'s>, UnifiedSynthesizerError> {
+        Ok(self.synthesize_ssml_stream(ssml).await?.err_into().boxed())
+    }
+
+    async fn process_ssml_events<'s>(
+        &'s mut self,
+        ssml: &str,
+    ) -> Result<EventStream<'s>, UnifiedSynthesizerError> {
+        Ok(self.synthesize_ssml_events(ssml).await?.err_into().boxed())
+    }
 }
@@ -1,83 +1,469 @@
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::errors::ConnectError;
-use crate::msg;
+use crate::errors::{ConnectError, ConnectErrorKind};
+use crate::msg::{self, ParseErrorKind};
 use crate::net::WsStream;
-use crate::{interpolate_ssml, msg::WebSocketMessage, AudioFormat, TextOptions};
+use crate::timeout::{timeout, TimeoutError};
+use crate::{
+    interpolate_ssml, msg::WebSocketMessage, AudioFormat, Boundary, BoundaryKind, RequestDetails,
+    SynthesisEvent, TextOptions, TimeoutPhase, Timeouts,
+};
 use chrono::Utc;
-use futures_util::{SinkExt, StreamExt};
+use futures_util::stream::{try_unfold, SplitSink, SplitStream, Stream};
+use futures_util::{SinkExt, StreamExt, TryStreamExt};
 use hyper::header::InvalidHeaderValue;
 use log::{debug, info, warn};
+use serde::Deserialize;
 
 use strum::AsRefStr;
-use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::protocol::{frame::CloseFrame, Message};
+pub use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+type WsSink = tokio::sync::Mutex<SplitSink<WsStream, Message>>;
+
 /// The main struct for interacting with the Azure Speech Service.
+///
+/// Several requests can be in flight on the same connection.
+/// The messages from the service are routed to the requests by their `X-RequestId`.
 pub struct WebsocketSynthesizer {
     pub(super) audio_format: AudioFormat,
-    pub(super) stream: WsStream,
+    pub(super) sink: Arc<WsSink>,
+    pub(super) connection_id: String,
+    pub(super) timeouts: Timeouts,
+    pub(super) state: Arc<Mutex<ConnectionState>>,
+    pub(super) reader: JoinHandle<()>,
+    pub(super) keepalive: Option<JoinHandle<()>>,
+}
+
+/// The state of the websocket connection, shared with the reader and keepalive tasks.
+#[derive(Debug)]
+pub(super) struct ConnectionState {
+    healthy: bool,
+    latency: Option<Duration>,
+    /// The requests in flight, by their `X-RequestId`
+    requests: HashMap<String, mpsc::UnboundedSender<Event>>,
+    /// The cancelled requests whose messages are discarded until `turn.end`
+    cancelled: HashSet<String>,
+    /// The pings waiting for a pong, by their payload
+    pings: HashMap<Vec<u8>, oneshot::Sender<()>>,
+}
+
+impl Default for ConnectionState {
+    fn default() -> Self {
+        Self {
+            healthy: true,
+            latency: None,
+            requests: HashMap::new(),
+            cancelled: HashSet::new(),
+            pings: HashMap::new(),
+        }
+    }
+}
+
+/// A message of the service for a request in flight
+#[derive(Debug)]
+pub(super) enum Event {
+    Response(String),
+    Audio(Vec<u8>),
+    Metadata(String),
+    TurnEnd,
+    Failed(WebsocketSynthesizerErrorKind, Option<String>),
+}
+
+/// Removes a request from the [`ConnectionState`] when it finishes or is cancelled.
+///
+/// The messages of a request that is cancelled after the SSML was sent
+/// are discarded until its `turn.end`.
+struct Registration {
+    state: Arc<Mutex<ConnectionState>>,
+    request_id: String,
+    sent: bool,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        // The reader removes the request on turn.end, so it is still there if it was cancelled.
+        if state.requests.remove(&self.request_id).is_some() && self.sent && state.healthy {
+            debug!(
+                "Discarding the remaining messages of request {}",
+                self.request_id
+            );
+            state.cancelled.insert(self.request_id.clone());
+        }
+    }
+}
+
+/// A request in flight, which receives its messages from the reader task.
+///
+/// It does not borrow the [`WebsocketSynthesizer`], so its events can be streamed independently.
+struct Request {
+    registration: Registration,
+    receiver: mpsc::UnboundedReceiver<Event>,
+    connection_id: String,
+    timeouts: Timeouts,
+    phase: TimeoutPhase,
+    /// Whether the service was asked for word and sentence boundaries
+    metadata: bool,
+    /// The body of the last `response` message, which is reported in errors
+    last_response: Option<String>,
+    /// The boundaries of a metadata message that were not returned yet
+    boundaries: VecDeque<Boundary>,
+}
+
+impl Request {
+    /// Receive the next chunk of audio or boundary, or `None` after `turn.end`.
+    async fn next_event(&mut self) -> Result<Option<SynthesisEvent>, WebsocketSynthesizerError> {
+        match self.receive().await {
+            Ok(event) => Ok(event),
+            Err(e) => Err(self.fail(e)),
+        }
+    }
+
+    async fn receive(&mut self) -> Result<Option<SynthesisEvent>, WebsocketSynthesizerError> {
+        loop {
+            if let Some(boundary) = self.boundaries.pop_front() {
+                return Ok(Some(SynthesisEvent::Boundary(boundary)));
+            }
+            let event = timeout(&self.timeouts, self.phase, self.receiver.recv())
+                .await?
+                .unwrap_or_else(|| {
+                    Event::Failed(
+                        WebsocketSynthesizerErrorKind::Websocket,
+                        Some("The connection is closed".to_string()),
+                    )
+                });
+            self.phase = TimeoutPhase::Idle;
+            match event {
+                Event::Response(body) => {
+                    debug!("Received a response: {body}");
+                    self.last_response = Some(body);
+                }
+                Event::Audio(data) => return Ok(Some(SynthesisEvent::Audio(data))),
+                Event::Metadata(body) if self.metadata => {
+                    self.boundaries.extend(parse_boundaries(&body)?);
+                }
+                Event::Metadata(body) => debug!("Ignoring unrequested metadata: {body}"),
+                Event::TurnEnd => return Ok(None),
+                Event::Failed(kind, message) => {
+                    return Err(WebsocketSynthesizerError {
+                        kind,
+                        source: message.map(anyhow::Error::msg),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Attach the details of the request to the error,
+    /// and mark the connection as unhealthy if the error broke it.
+    fn fail(&self, e: WebsocketSynthesizerError) -> WebsocketSynthesizerError {
+        if matches!(
+            e.kind,
+            WebsocketSynthesizerErrorKind::Websocket
+                | WebsocketSynthesizerErrorKind::WebsocketConnectionClosed { .. }
+                | WebsocketSynthesizerErrorKind::Timeout(_)
+        ) {
+            self.registration.state.lock().unwrap().healthy = false;
+        }
+        let mut details = RequestDetails::new(
+            Some(self.registration.request_id.clone()),
+            Some(self.connection_id.clone()),
+        );
+        details.message = match &e.kind {
+            WebsocketSynthesizerErrorKind::WebsocketConnectionClosed { reason, .. } => {
+                Some(reason.clone())
+            }
+            _ => self.last_response.clone(),
+        };
+        e.with_details(details)
+    }
+}
+
+/// The body of an `audio.metadata` message
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AudioMetadata {
+    metadata: Vec<MetadataEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct MetadataEntry {
+    #[serde(rename = "Type")]
+    kind: String,
+    data: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BoundaryData {
+    /// In ticks of 100 nanoseconds
+    offset: u64,
+    /// In ticks of 100 nanoseconds
+    duration: u64,
+    #[serde(rename = "text")]
+    text: BoundaryText,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BoundaryText {
+    text: String,
+    box_type: Option<String>,
+}
+
+/// Parse the word and sentence boundaries of an `audio.metadata` message, ignoring other metadata.
+fn parse_boundaries(body: &str) -> Result<Vec<Boundary>, WebsocketSynthesizerError> {
+    let metadata: AudioMetadata = serde_json::from_str(body)?;
+    let mut boundaries = Vec::new();
+    for entry in metadata.metadata {
+        let default_kind = match entry.kind.as_str() {
+            "WordBoundary" => BoundaryKind::Word,
+            "SentenceBoundary" => BoundaryKind::Sentence,
+            kind => {
+                debug!("Ignoring metadata of type {kind}");
+                continue;
+            }
+        };
+        let data: BoundaryData = serde_json::from_value(entry.data)?;
+        let kind = match data.text.box_type.as_deref() {
+            Some("Word") => BoundaryKind::Word,
+            Some("Punctuation") => BoundaryKind::Punctuation,
+            Some("Sentence") => BoundaryKind::Sentence,
+            _ => default_kind,
+        };
+        boundaries.push(Boundary {
+            kind,
+            offset: Duration::from_nanos(data.offset.saturating_mul(100)),
+            duration: Duration::from_nanos(data.duration.saturating_mul(100)),
+            text: data.text.text,
+        });
+    }
+    Ok(boundaries)
 }
 
 impl WebsocketSynthesizer {
+    pub(super) fn new(
+        audio_format: AudioFormat,
+        stream: WsStream,
+        connection_id: String,
+        timeouts: Timeouts,
+        keepalive: Option<Duration>,
+    ) -> Self {
+        let (sink, stream) = stream.split();
+        let sink = Arc::new(tokio::sync::Mutex::new(sink));
+        let state = Arc::new(Mutex::new(ConnectionState::default()));
+        let reader = tokio::spawn(read_messages(stream, Arc::clone(&state)));
+        let keepalive = keepalive.map(|interval| {
+            tokio::spawn(keepalive_task(
+                interval,
+                Arc::clone(&sink),
+                Arc::clone(&state),
+                timeouts,
+            ))
+        });
+        Self {
+            audio_format,
+            sink,
+            connection_id,
+            timeouts,
+            state,
+            reader,
+            keepalive,
+        }
+    }
+
+    /// The ID of the websocket connection, which Azure support asks for.
+    pub fn connection_id(&self) -> &str {
+        &self.connection_id
+    }
+
+    /// Whether the connection is still usable.
+    ///
+    /// The connection becomes unhealthy when a ping fails or a request breaks the connection.
+    /// Requests on an unhealthy connection fail immediately and a new connection should be established.
+    pub fn is_healthy(&self) -> bool {
+        self.state.lock().unwrap().healthy
+    }
+
+    /// The round-trip time of the last successful ping, if any.
+    pub fn latency(&self) -> Option<Duration> {
+        self.state.lock().unwrap().latency
+    }
+
+    /// Send a ping to the server and wait for the pong, returning the round-trip time.
+    ///
+    /// The pong must arrive before the first byte timeout expires.
+    pub async fn ping(&self) -> Result<Duration, WebsocketSynthesizerError> {
+        self.ensure_healthy()?;
+        ping(&self.sink, &self.state, &self.timeouts).await
+    }
+
     /// Synthesize the given SSML into audio([`Vec<u8>`]).
-    pub async fn synthesize_ssml(
-        &mut self,
+    pub async fn synthesize_ssml(&self, ssml: &str) -> Result<Vec<u8>, WebsocketSynthesizerError> {
+        let mut request = self.send_request(ssml, false).await?;
+        let mut buffer = Vec::new();
+        while let Some(event) = request.next_event().await? {
+            if let SynthesisEvent::Audio(data) = event {
+                buffer.extend_from_slice(&data);
+            }
+        }
+        Ok(buffer)
+    }
+
+    /// Synthesize the given SSML and receive the audio in chunks as soon as they arrive.
+    ///
+    /// Dropping the stream cancels the request like [`WebsocketSynthesizer::synthesize_ssml_with_cancellation`] does.
+    pub async fn synthesize_ssml_stream(
+        &self,
+        ssml: &str,
+    ) -> Result<
+        impl Stream<Item = Result<Vec<u8>, WebsocketSynthesizerError>> + Send + 'static,
+        WebsocketSynthesizerError,
+    > {
+        Ok(self
+            .synthesize_ssml_with_metadata(ssml, false)
+            .await?
+            .try_filter_map(|event| async move {
+                Ok(match event {
+                    SynthesisEvent::Audio(data) => Some(data),
+                    _ => None,
+                })
+            }))
+    }
+
+    /// Synthesize the given SSML and receive the audio in chunks
+    /// along with the word, punctuation and sentence boundaries as soon as they arrive.
+    ///
+    /// Dropping the stream cancels the request like [`WebsocketSynthesizer::synthesize_ssml_with_cancellation`] does.
+    pub async fn synthesize_ssml_events(
+        &self,
         ssml: &str,
+    ) -> Result<
+        impl Stream<Item = Result<SynthesisEvent, WebsocketSynthesizerError>> + Send + 'static,
+        WebsocketSynthesizerError,
+    > {
+        self.synthesize_ssml_with_metadata(ssml, true).await
+    }
+
+    /// Stream the events of a synthesis, with boundaries only if `metadata` is set.
+    pub(super) async fn synthesize_ssml_with_metadata(
+        &self,
+        ssml: &str,
+        metadata: bool,
+    ) -> Result<
+        impl Stream<Item = Result<SynthesisEvent, WebsocketSynthesizerError>> + Send + 'static,
+        WebsocketSynthesizerError,
+    > {
+        let request = self.send_request(ssml, metadata).await?;
+        Ok(try_unfold(request, |mut request| async move {
+            Ok(request.next_event().await?.map(|event| (event, request)))
+        }))
+    }
+
+    /// Synthesize the given SSML into audio([`Vec<u8>`]), unless `cancellation` is cancelled first.
+    ///
+    /// The service has no way to abort a synthesis, so the audio that it still sends for a cancelled request
+    /// is discarded by the synthesizer, which stays usable for other requests.
+    /// Dropping the future of [`WebsocketSynthesizer::synthesize_ssml`] has the same effect.
+    pub async fn synthesize_ssml_with_cancellation(
+        &self,
+        ssml: &str,
+        cancellation: &CancellationToken,
     ) -> Result<Vec<u8>, WebsocketSynthesizerError> {
-        let uuid = Uuid::new_v4();
-        let request_id = uuid.as_simple();
+        tokio::select! {
+            result = self.synthesize_ssml(ssml) => result,
+            _ = cancellation.cancelled() => Err(WebsocketSynthesizerError::cancelled()),
+        }
+    }
+
+    fn ensure_healthy(&self) -> Result<(), WebsocketSynthesizerError> {
+        if self.is_healthy() {
+            Ok(())
+        } else {
+            Err(WebsocketSynthesizerError {
+                kind: WebsocketSynthesizerErrorKind::Unhealthy,
+                source: None,
+            })
+        }
+    }
+
+    /// Register a new request and send the SSML, asking for boundaries if `metadata` is set.
+    async fn send_request(
+        &self,
+        ssml: &str,
+        metadata: bool,
+    ) -> Result<Request, WebsocketSynthesizerError> {
+        self.ensure_healthy()?;
+        let request_id = Uuid::new_v4().as_simple().to_string();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .insert(request_id.clone(), sender);
+        let mut request = Request {
+            registration: Registration {
+                state: Arc::clone(&self.state),
+                request_id,
+                sent: false,
+            },
+            receiver,
+            connection_id: self.connection_id.clone(),
+            timeouts: self.timeouts,
+            phase: TimeoutPhase::FirstByte,
+            metadata,
+            last_response: None,
+            boundaries: VecDeque::new(),
+        };
+        match self
+            .send_ssml(ssml, metadata, &mut request.registration)
+            .await
+        {
+            Ok(()) => Ok(request),
+            Err(e) => Err(request.fail(e)),
+        }
+    }
+
+    async fn send_ssml(
+        &self,
+        ssml: &str,
+        metadata: bool,
+        registration: &mut Registration,
+    ) -> Result<(), WebsocketSynthesizerError> {
+        let request_id = registration.request_id.clone();
         let now = Utc::now();
         let synthesis_context = format!(
-            r#"{{"synthesis":{{"audio":{{"metadataOptions":{{"sentenceBoundaryEnabled":false,"wordBoundaryEnabled":false,"sessionEndEnabled":false}},"outputFormat":"{}"}}}}}}"#,
-            Into::<&str>::into(self.audio_format)
+            r#"{{"synthesis":{{"audio":{{"metadataOptions":{{"sentenceBoundaryEnabled":{metadata},"wordBoundaryEnabled":{metadata},"sessionEndEnabled":false}},"outputFormat":"{}"}}}}}}"#,
+            Into::<&str>::into(self.audio_format),
         );
-        self.stream.send(Message::Text(format!(
-            "Path: synthesis.context\r\nX-RequestId: {request_id}\r\nX-Timestamp: {now:?}Content-Type: application/json\r\n\r\n{synthesis_context}", 
-            request_id = &request_id)),
-        ).await?;
+        let mut sink = self.sink.lock().await;
+        sink.send(Message::Text(format!(
+            "Path: synthesis.context\r\nX-RequestId: {request_id}\r\nX-Timestamp: {now:?}Content-Type: application/json\r\n\r\n{synthesis_context}",
+        )))
+        .await?;
         info!("Before sending the SSML to the server");
-        self.stream.send(Message::Text(format!(
+        sink.send(Message::Text(format!(
             "Path: ssml\r\nX-RequestId: {request_id}\r\nX-Timestamp: {now:?}\r\nContent-Type: application/ssml+xml\r\n\r\n{ssml}"
-        ))).await?;
-        let mut buffer = Vec::new();
-        while let Some(raw_msg) = self.stream.next().await.transpose()? {
-            let msg = WebSocketMessage::try_from(&raw_msg)?;
-            match msg {
-                WebSocketMessage::TurnStart | WebSocketMessage::Response { body: _ } => continue,
-                WebSocketMessage::Audio { data } => {
-                    buffer.extend_from_slice(data);
-                }
-                WebSocketMessage::TurnEnd => {
-                    break;
-                }
-                WebSocketMessage::Close(frame) => {
-                    return Err(frame.map_or_else(
-                        || {
-                            WebsocketSynthesizerError::connection_closed(
-                                "Unknown".to_string(),
-                                "The server closed the connection without a reason".to_string(),
-                            )
-                        },
-                        |fr| {
-                            WebsocketSynthesizerError::connection_closed(
-                                fr.code.to_string(),
-                                fr.reason.to_string(),
-                            )
-                        },
-                    ));
-                }
-                msg => warn!("Received a message that is not handled: {:?}", msg),
-            }
-        }
-        Ok(buffer)
+        )))
+        .await?;
+        // Only a request whose SSML was sent gets a turn.end, which removes it from the cancelled requests
+        registration.sent = true;
+        Ok(())
     }
 
     /// Synthesize the given text into audio([`Vec<u8>`]).
     /// This is a convenience method that interpolates the SSML for you.
     pub async fn synthesize_text(
-        &mut self,
+        &self,
         text: impl AsRef<str>,
         options: &TextOptions<'_>,
     ) -> Result<Vec<u8>, WebsocketSynthesizerError> {
@@ -85,6 +471,189 @@ impl WebsocketSynthesizer {
         let ssml = interpolate_ssml(text, options)?;
         self.synthesize_ssml(&ssml).await
     }
+
+    /// Synthesize the given text into audio([`Vec<u8>`]), unless `cancellation` is cancelled first.
+    /// This is a convenience method that interpolates the SSML for you.
+    pub async fn synthesize_text_with_cancellation(
+        &self,
+        text: impl AsRef<str>,
+        options: &TextOptions<'_>,
+        cancellation: &CancellationToken,
+    ) -> Result<Vec<u8>, WebsocketSynthesizerError> {
+        debug!("Synthesizing text: {}", text.as_ref());
+        let ssml = interpolate_ssml(text, options)?;
+        self.synthesize_ssml_with_cancellation(&ssml, cancellation)
+            .await
+    }
+}
+
+impl Drop for WebsocketSynthesizer {
+    fn drop(&mut self) {
+        self.reader.abort();
+        if let Some(keepalive) = self.keepalive.take() {
+            keepalive.abort();
+        }
+    }
+}
+
+/// Read the messages of the service and route them to the requests in flight by their `X-RequestId`.
+///
+/// Messages of unknown requests, e.g. late messages of a cancelled request, are dropped.
+/// A message with an unexpected `Path` only fails its own request.
+/// When the connection breaks or a message cannot be parsed at all,
+/// all requests in flight fail and the connection becomes unhealthy.
+async fn read_messages(mut stream: SplitStream<WsStream>, state: Arc<Mutex<ConnectionState>>) {
+    let (kind, message) = loop {
+        let raw_msg = match stream.next().await {
+            Some(Ok(raw_msg)) => raw_msg,
+            Some(Err(e)) => {
+                break (
+                    WebsocketSynthesizerErrorKind::Websocket,
+                    Some(e.to_string()),
+                )
+            }
+            None => break (WebsocketSynthesizerError::closed_by_server(None).kind, None),
+        };
+        if let Message::Pong(payload) = &raw_msg {
+            if let Some(pong) = state.lock().unwrap().pings.remove(payload) {
+                let _ = pong.send(());
+            }
+            continue;
+        }
+        let msg = match WebSocketMessage::try_from(&raw_msg) {
+            Ok(msg) => msg,
+            Err(e) if matches!(e.kind, ParseErrorKind::UnexpectedPath(_)) => {
+                // The headers are fine, so only the request of the message fails
+                warn!("Failed to handle a message: {e}");
+                let request = e
+                    .request_id
+                    .as_ref()
+                    .and_then(|request_id| state.lock().unwrap().requests.remove(request_id));
+                if let Some(request) = request {
+                    let _ = request.send(Event::Failed(
+                        WebsocketSynthesizerErrorKind::InvalidMessage,
+                        Some(e.to_string()),
+                    ));
+                }
+                continue;
+            }
+            Err(e) => {
+                // The message cannot be attributed to a request, so the connection is broken
+                warn!("Failed to parse a message: {e}");
+                break (
+                    WebsocketSynthesizerErrorKind::InvalidMessage,
+                    Some(e.to_string()),
+                );
+            }
+        };
+        let event = match msg {
+            WebSocketMessage::Response { body, .. } => Event::Response(body.to_string()),
+            WebSocketMessage::Audio { data, .. } => Event::Audio(data.to_vec()),
+            WebSocketMessage::AudioMetadata { body, .. } => Event::Metadata(body.to_string()),
+            WebSocketMessage::TurnEnd { .. } => Event::TurnEnd,
+            WebSocketMessage::TurnStart { .. }
+            | WebSocketMessage::Ping
+            | WebSocketMessage::Pong => continue,
+            WebSocketMessage::Close(frame) => {
+                break (
+                    WebsocketSynthesizerError::closed_by_server(frame).kind,
+                    None,
+                )
+            }
+        };
+        let Some(request_id) = msg.headers().and_then(|headers| headers.request_id) else {
+            warn!("Dropping a message without X-RequestId: {msg:?}");
+            continue;
+        };
+        let mut state = state.lock().unwrap();
+        if state.cancelled.contains(request_id) {
+            if matches!(event, Event::TurnEnd) {
+                debug!("Cancelled request {request_id} ended");
+                state.cancelled.remove(request_id);
+            }
+            continue;
+        }
+        let request = if matches!(event, Event::TurnEnd) {
+            state.requests.remove(request_id)
+        } else {
+            state.requests.get(request_id).cloned()
+        };
+        match request {
+            Some(request) => {
+                let _ = request.send(event);
+            }
+            None => debug!("Dropping a message of unknown request {request_id}"),
+        }
+    };
+    debug!("The reader of the websocket connection stopped");
+    let mut state = state.lock().unwrap();
+    state.healthy = false;
+    state.pings.clear();
+    state.cancelled.clear();
+    for (_, request) in state.requests.drain() {
+        let _ = request.send(Event::Failed(kind.clone(), message.clone()));
+    }
+}
+
+/// Ping the server every `interval` while the connection is idle, until a ping fails.
+async fn keepalive_task(
+    interval: Duration,
+    sink: Arc<WsSink>,
+    state: Arc<Mutex<ConnectionState>>,
+    timeouts: Timeouts,
+) {
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        {
+            let state = state.lock().unwrap();
+            if !state.healthy {
+                return;
+            }
+            // Requests in flight keep the connection alive by themselves.
+            if !state.requests.is_empty() {
+                continue;
+            }
+        }
+        match ping(&sink, &state, &timeouts).await {
+            Ok(latency) => debug!("Keepalive ping succeeded in {latency:?}"),
+            Err(e) => {
+                warn!("Keepalive ping failed: {e}");
+                return;
+            }
+        }
+    }
+}
+
+/// Send a ping and wait for the pong, recording the outcome in `state`.
+async fn ping(
+    sink: &WsSink,
+    state: &Mutex<ConnectionState>,
+    timeouts: &Timeouts,
+) -> Result<Duration, WebsocketSynthesizerError> {
+    let payload = Uuid::new_v4().as_bytes().to_vec();
+    let (sender, receiver) = oneshot::channel();
+    state.lock().unwrap().pings.insert(payload.clone(), sender);
+    let start = Instant::now();
+    let result = async {
+        sink.lock()
+            .await
+            .send(Message::Ping(payload.clone()))
+            .await?;
+        timeout(timeouts, TimeoutPhase::FirstByte, receiver)
+            .await?
+            .map_err(|_| WebsocketSynthesizerError::closed_by_server(None))?;
+        Ok(start.elapsed())
+    }
+    .await;
+    let mut state = state.lock().unwrap();
+    state.pings.remove(&payload);
+    match result {
+        Ok(latency) => state.latency = Some(latency),
+        Err(_) => state.healthy = false,
+    }
+    result
 }
 
 /// Errors that can occur when creating and using a [`WebsocketSynthesizer`].
@@ -96,12 +665,41 @@ pub struct WebsocketSynthesizerError {
 }
 
 impl WebsocketSynthesizerError {
+    /// The request and connection IDs and the last response of the service for the failed request.
+    pub fn details(&self) -> Option<&RequestDetails> {
+        RequestDetails::find(&self.source)
+    }
+
+    pub(crate) fn with_details(mut self, details: RequestDetails) -> Self {
+        details.attach_to(&mut self.source);
+        self
+    }
+
+    pub(super) fn cancelled() -> Self {
+        Self {
+            kind: WebsocketSynthesizerErrorKind::Cancelled,
+            source: None,
+        }
+    }
+
     fn connection_closed(code: String, reason: String) -> Self {
         Self {
             kind: WebsocketSynthesizerErrorKind::WebsocketConnectionClosed { code, reason },
             source: None,
         }
     }
+
+    fn closed_by_server(frame: Option<&CloseFrame<'_>>) -> Self {
+        frame.map_or_else(
+            || {
+                Self::connection_closed(
+                    "Unknown".to_string(),
+                    "The server closed the connection without a reason".to_string(),
+                )
+            },
+            |fr| Self::connection_closed(fr.code.to_string(), fr.reason.to_string()),
+        )
+    }
 }
 
 impl Display for WebsocketSynthesizerError {
@@ -117,6 +715,12 @@ impl Display for WebsocketSynthesizerError {
                 )
             }
             InvalidMessage => write!(f, "aspeak cannot handle this message. Please report this bug to https://github.com/kxxt/aspeak/issues."),
+            Timeout(phase) => write!(f, "{phase} timed out"),
+            Cancelled => write!(f, "the request was cancelled"),
+            Unhealthy => write!(
+                f,
+                "the connection is unhealthy and should be re-established"
+            ),
             _ => write!(f, "{} error", self.kind.as_ref()),
         }
     }
@@ -151,6 +755,12 @@ pub enum WebsocketSynthesizerErrorKind {
     InvalidMessage,
     /// Errors that occur when interpolating SSML.
     Ssml,
+    /// A timeout expired while connecting or waiting for the audio.
+    Timeout(TimeoutPhase),
+    /// A previous ping or request broke the connection.
+    Unhealthy,
+    /// The request was cancelled.
+    Cancelled,
 }
 
 macro_rules! impl_from_for_ws_synthesizer_error {
@@ -168,9 +778,30 @@ macro_rules! impl_from_for_ws_synthesizer_error {
 
 impl_from_for_ws_synthesizer_error!(InvalidHeaderValue, InvalidRequest);
 impl_from_for_ws_synthesizer_error!(url::ParseError, InvalidRequest);
-impl_from_for_ws_synthesizer_error!(ConnectError, Connect);
 impl_from_for_ws_synthesizer_error!(tokio_tungstenite::tungstenite::Error, Websocket);
 impl_from_for_ws_synthesizer_error!(crate::ssml::SsmlError, Ssml);
+impl_from_for_ws_synthesizer_error!(serde_json::Error, InvalidMessage);
+
+impl From<ConnectError> for WebsocketSynthesizerError {
+    fn from(e: ConnectError) -> Self {
+        Self {
+            kind: match e.kind {
+                ConnectErrorKind::Timeout(phase) => WebsocketSynthesizerErrorKind::Timeout(phase),
+                _ => WebsocketSynthesizerErrorKind::Connect,
+            },
+            source: Some(e.into()),
+        }
+    }
+}
+
+impl From<TimeoutError> for WebsocketSynthesizerError {
+    fn from(e: TimeoutError) -> Self {
+        Self {
+            kind: WebsocketSynthesizerErrorKind::Timeout(e.phase),
+            source: Some(e.into()),
+        }
+    }
+}
 
 impl From<msg::ParseError> for WebsocketSynthesizerError {
     fn from(e: msg::ParseError) -> Self {
@@ -180,3 +811,86 @@ impl From<msg::ParseError> for WebsocketSynthesizerError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_server::{audio, config, next_ssml, respond, serve, text, ServerStream};
+    use super::*;
+
+    #[tokio::test]
+    async fn unexpected_path_fails_only_its_request() {
+        let url = serve(|mut ws: ServerStream| async move {
+            let mut requests = HashMap::new();
+            for _ in 0..2 {
+                let (request_id, ssml) = next_ssml(&mut ws).await.unwrap();
+                requests.insert(ssml, request_id);
+            }
+            let (bad, good) = (&requests["bad"], &requests["good"]);
+            ws.send(text("speech.phrase", bad, "{}")).await.unwrap();
+            respond(&mut ws, good, b"audio").await;
+            ws.send(text("turn.end", bad, "")).await.unwrap();
+            while let Some((request_id, _)) = next_ssml(&mut ws).await {
+                respond(&mut ws, &request_id, b"more audio").await;
+            }
+        })
+        .await;
+        let synthesizer = config(&url).connect_websocket().await.unwrap();
+        let (bad, good) = tokio::join!(
+            synthesizer.synthesize_ssml("bad"),
+            synthesizer.synthesize_ssml("good")
+        );
+        assert_eq!(
+            bad.unwrap_err().kind,
+            WebsocketSynthesizerErrorKind::InvalidMessage
+        );
+        assert_eq!(good.unwrap(), b"audio");
+        assert!(synthesizer.is_healthy());
+        assert_eq!(
+            synthesizer.synthesize_ssml("next").await.unwrap(),
+            b"more audio"
+        );
+    }
+
+    #[tokio::test]
+    async fn late_messages_of_cancelled_request_are_discarded() {
+        let url = serve(|mut ws: ServerStream| async move {
+            let (cancelled, _) = next_ssml(&mut ws).await.unwrap();
+            ws.send(audio(&cancelled, b"audio")).await.unwrap();
+            // The next request is sent after the first one was cancelled
+            let (request_id, _) = next_ssml(&mut ws).await.unwrap();
+            respond(&mut ws, &cancelled, b"late audio").await;
+            respond(&mut ws, &request_id, b"next audio").await;
+            while ws.next().await.is_some() {}
+        })
+        .await;
+        let synthesizer = config(&url).connect_websocket().await.unwrap();
+        let mut stream = synthesizer
+            .synthesize_ssml_stream("cancelled")
+            .await
+            .unwrap()
+            .boxed();
+        assert_eq!(stream.next().await.unwrap().unwrap(), b"audio");
+        drop(stream);
+        assert_eq!(synthesizer.state.lock().unwrap().cancelled.len(), 1);
+        assert_eq!(
+            synthesizer.synthesize_ssml("next").await.unwrap(),
+            b"next audio"
+        );
+        assert!(synthesizer.state.lock().unwrap().cancelled.is_empty());
+        assert!(synthesizer.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn malformed_frame_breaks_connection() {
+        let url = serve(|mut ws: ServerStream| async move {
+            next_ssml(&mut ws).await.unwrap();
+            ws.send(Message::Binary(vec![0])).await.unwrap();
+            while ws.next().await.is_some() {}
+        })
+        .await;
+        let synthesizer = config(&url).connect_websocket().await.unwrap();
+        let err = synthesizer.synthesize_ssml("ssml").await.unwrap_err();
+        assert_eq!(err.kind, WebsocketSynthesizerErrorKind::InvalidMessage);
+        assert!(!synthesizer.is_healthy());
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use strum::AsRefStr;
+
+/// Timeouts for connecting to the service and receiving the synthesized audio.
+///
+/// A timeout that is `None` never expires.
+/// By default, connecting and the TLS handshake time out after 10 seconds,
+/// waiting for the first byte and for the next chunk of audio after 30 seconds.
+///
+/// With serde, the timeouts are given in seconds, where zero disables a timeout
+/// and a missing timeout takes its default.
+/// The keys of the profile, e.g. `connect_timeout`, are accepted as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    /// Timeout for establishing the connection, including the handshake with a proxy server
+    #[serde(with = "optional_secs", alias = "connect_timeout")]
+    pub(crate) connect: Option<Duration>,
+    /// Timeout for the TLS handshake and the websocket upgrade
+    #[serde(with = "optional_secs", alias = "tls_timeout")]
+    pub(crate) tls_handshake: Option<Duration>,
+    /// Timeout for the first byte of the response after the request is sent
+    #[serde(with = "optional_secs", alias = "first_byte_timeout")]
+    pub(crate) first_byte: Option<Duration>,
+    /// Timeout between two consecutive chunks of the response
+    #[serde(with = "optional_secs", alias = "idle_timeout")]
+    pub(crate) idle: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Some(Duration::from_secs(10)),
+            tls_handshake: Some(Duration::from_secs(10)),
+            first_byte: Some(Duration::from_secs(30)),
+            idle: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+impl Timeouts {
+    /// Timeouts that never expire
+    pub fn none() -> Self {
+        Self {
+            connect: None,
+            tls_handshake: None,
+            first_byte: None,
+            idle: None,
+        }
+    }
+
+    /// Create a builder for [`Timeouts`], starting from the defaults
+    pub fn builder() -> TimeoutsBuilder {
+        TimeoutsBuilder::new()
+    }
+
+    /// Timeout for establishing the connection, including the handshake with a proxy server
+    pub fn connect(&self) -> Option<Duration> {
+        self.connect
+    }
+
+    /// Timeout for establishing the connection, including the handshake with a proxy server
+    pub fn connect_mut(&mut self) -> &mut Option<Duration> {
+        &mut self.connect
+    }
+
+    /// Timeout for the TLS handshake and the websocket upgrade
+    pub fn tls_handshake(&self) -> Option<Duration> {
+        self.tls_handshake
+    }
+
+    /// Timeout for the TLS handshake and the websocket upgrade
+    pub fn tls_handshake_mut(&mut self) -> &mut Option<Duration> {
+        &mut self.tls_handshake
+    }
+
+    /// Timeout for the first byte of the response after the request is sent
+    pub fn first_byte(&self) -> Option<Duration> {
+        self.first_byte
+    }
+
+    /// Timeout for the first byte of the response after the request is sent
+    pub fn first_byte_mut(&mut self) -> &mut Option<Duration> {
+        &mut self.first_byte
+    }
+
+    /// Timeout between two consecutive chunks of the response
+    pub fn idle(&self) -> Option<Duration> {
+        self.idle
+    }
+
+    /// Timeout between two consecutive chunks of the response
+    pub fn idle_mut(&mut self) -> &mut Option<Duration> {
+        &mut self.idle
+    }
+
+    /// The timeout of `phase`
+    pub fn get(&self, phase: TimeoutPhase) -> Option<Duration> {
+        match phase {
+            TimeoutPhase::Connect => self.connect,
+            TimeoutPhase::TlsHandshake => self.tls_handshake,
+            TimeoutPhase::FirstByte => self.first_byte,
+            TimeoutPhase::Idle => self.idle,
+        }
+    }
+}
+
+/// (De)serialize an optional [`Duration`] as seconds, where zero means `None`.
+pub(crate) mod optional_secs {
+    use std::time::Duration;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        duration
+            .map_or(0.0, |duration| duration.as_secs_f64())
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        let secs = f64::deserialize(deserializer)?;
+        let duration = Duration::try_from_secs_f64(secs)
+            .map_err(|e| D::Error::custom(format!("invalid duration {secs}: {e}")))?;
+        Ok((!duration.is_zero()).then_some(duration))
+    }
+}
+
+/// Builder for [`Timeouts`]
+#[derive(Debug, Clone)]
+pub struct TimeoutsBuilder {
+    timeouts: Timeouts,
+}
+
+impl TimeoutsBuilder {
+    /// Create a builder starting from the default timeouts
+    pub fn new() -> Self {
+        Self {
+            timeouts: Timeouts::default(),
+        }
+    }
+
+    /// Timeout for establishing the connection, including the handshake with a proxy server
+    pub fn connect(mut self, timeout: Duration) -> Self {
+        self.timeouts.connect = Some(timeout);
+        self
+    }
+
+    /// Timeout for establishing the connection, `None` to disable it
+    pub fn optional_connect(mut self, timeout: Option<Duration>) -> Self {
+        self.timeouts.connect = timeout;
+        self
+    }
+
+    /// Timeout for the TLS handshake and the websocket upgrade
+    pub fn tls_handshake(mut self, timeout: Duration) -> Self {
+        self.timeouts.tls_handshake = Some(timeout);
+        self
+    }
+
+    /// Timeout for the TLS handshake and the websocket upgrade, `None` to disable it
+    pub fn optional_tls_handshake(mut self, timeout: Option<Duration>) -> Self {
+        self.timeouts.tls_handshake = timeout;
+        self
+    }
+
+    /// Timeout for the first byte of the response after the request is sent
+    pub fn first_byte(mut self, timeout: Duration) -> Self {
+        self.timeouts.first_byte = Some(timeout);
+        self
+    }
+
+    /// Timeout for the first byte of the response, `None` to disable it
+    pub fn optional_first_byte(mut self, timeout: Option<Duration>) -> Self {
+        self.timeouts.first_byte = timeout;
+        self
+    }
+
+    /// Timeout between two consecutive chunks of the response
+    pub fn idle(mut self, timeout: Duration) -> Self {
+        self.timeouts.idle = Some(timeout);
+        self
+    }
+
+    /// Timeout between two consecutive chunks of the response, `None` to disable it
+    pub fn optional_idle(mut self, timeout: Option<Duration>) -> Self {
+        self.timeouts.idle = timeout;
+        self
+    }
+
+    /// Build [`Timeouts`]
+    pub fn build(self) -> Timeouts {
+        self.timeouts
+    }
+}
+
+impl Default for TimeoutsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The phase of a request that timed out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsRefStr)]
+#[strum(serialize_all = "lowercase")]
+#[non_exhaustive]
+pub enum TimeoutPhase {
+    /// Establishing the connection
+    Connect,
+    /// The TLS handshake or the websocket upgrade
+    #[strum(serialize = "TLS handshake")]
+    TlsHandshake,
+    /// Waiting for the first byte of the response
+    #[strum(serialize = "first byte")]
+    FirstByte,
+    /// Waiting for the next chunk of the response
+    Idle,
+}
+
+impl Display for TimeoutPhase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_ref())
+    }
+}
+
+/// A timeout of [`Timeouts`] expired
+#[cfg(any(feature = "rest-synthesizer", feature = "websocket-synthesizer"))]
+#[derive(Debug)]
+pub(crate) struct TimeoutError {
+    pub phase: TimeoutPhase,
+    pub duration: Duration,
+}
+
+#[cfg(any(feature = "rest-synthesizer", feature = "websocket-synthesizer"))]
+impl Display for TimeoutError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} timed out after {:?}", self.phase, self.duration)
+    }
+}
+
+#[cfg(any(feature = "rest-synthesizer", feature = "websocket-synthesizer"))]
+impl std::error::Error for TimeoutError {}
+
+/// Await `future`, giving up after the timeout of `phase` in `timeouts`
+#[cfg(any(feature = "rest-synthesizer", feature = "websocket-synthesizer"))]
+pub(crate) async fn timeout<F: std::future::Future>(
+    timeouts: &Timeouts,
+    phase: TimeoutPhase,
+    future: F,
+) -> Result<F::Output, TimeoutError> {
+    match timeouts.get(phase) {
+        Some(duration) => tokio::time::timeout(duration, future)
+            .await
+            .map_err(|_| TimeoutError { phase, duration }),
+        None => Ok(future.await),
+    }
+}
@@ -0,0 +1,836 @@
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+use serde::{Deserialize, Serialize};
+use strum::AsRefStr;
+
+/// TLS options for the connections to the service.
+///
+/// Additional root certificates are trusted besides the system or webpki roots,
+/// e.g. the private CA of an inspecting proxy.
+/// The client certificate is presented to the service for mutual TLS.
+/// If public keys are pinned, the certificate of the service must have one of them.
+/// Pinning requires one of the `rustls-tls-*` features.
+///
+/// With serde, the certificates and the key are PEM strings
+/// and the pinned public keys are of the form `sha256//BASE64`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsOptions {
+    /// Additional PEM encoded root certificates
+    #[serde(with = "serde_tls::pem_list", skip_serializing_if = "Vec::is_empty")]
+    pub(crate) root_certificates: Vec<Vec<u8>>,
+    /// PEM encoded client certificate chain and its PKCS#8 private key
+    #[serde(with = "serde_tls::identity", skip_serializing_if = "Option::is_none")]
+    pub(crate) client_identity: Option<(Vec<u8>, Vec<u8>)>,
+    /// SHA-256 hashes of the pinned DER encoded SubjectPublicKeyInfo
+    #[serde(with = "serde_tls::pins", skip_serializing_if = "Vec::is_empty")]
+    pub(crate) pinned_public_keys: Vec<[u8; 32]>,
+}
+
+impl TlsOptions {
+    /// Create a builder for [`TlsOptions`]
+    pub fn builder() -> TlsOptionsBuilder {
+        TlsOptionsBuilder::new()
+    }
+
+    /// Whether the defaults of the TLS implementation are used
+    pub fn is_empty(&self) -> bool {
+        self.root_certificates.is_empty()
+            && self.client_identity.is_none()
+            && self.pinned_public_keys.is_empty()
+    }
+
+    /// Additional PEM encoded root certificates
+    pub fn root_certificates(&self) -> &[Vec<u8>] {
+        &self.root_certificates
+    }
+
+    /// Additional PEM encoded root certificates
+    pub fn root_certificates_mut(&mut self) -> &mut Vec<Vec<u8>> {
+        &mut self.root_certificates
+    }
+
+    /// PEM encoded client certificate chain and its PKCS#8 private key
+    pub fn client_identity(&self) -> Option<(&[u8], &[u8])> {
+        self.client_identity
+            .as_ref()
+            .map(|(cert, key)| (cert.as_slice(), key.as_slice()))
+    }
+
+    /// PEM encoded client certificate chain and its PKCS#8 private key
+    pub fn client_identity_mut(&mut self) -> &mut Option<(Vec<u8>, Vec<u8>)> {
+        &mut self.client_identity
+    }
+
+    /// SHA-256 hashes of the pinned DER encoded SubjectPublicKeyInfo
+    pub fn pinned_public_keys(&self) -> &[[u8; 32]] {
+        &self.pinned_public_keys
+    }
+
+    /// SHA-256 hashes of the pinned DER encoded SubjectPublicKeyInfo
+    pub fn pinned_public_keys_mut(&mut self) -> &mut Vec<[u8; 32]> {
+        &mut self.pinned_public_keys
+    }
+
+    /// The options for the TLS connection to a proxy server.
+    ///
+    /// Proxy servers are verified with the additional root certificates,
+    /// but neither the client certificate nor the pinned public keys apply to them.
+    #[cfg(feature = "websocket-synthesizer")]
+    pub(crate) fn for_proxy(&self) -> Self {
+        Self {
+            root_certificates: self.root_certificates.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Build a TLS connector with these options.
+    ///
+    /// native-tls is preferred if it is enabled, unless public keys are pinned.
+    pub(crate) fn connector(&self) -> Result<TlsConnector, TlsError> {
+        #[cfg(feature = "native-tls")]
+        if self.pinned_public_keys.is_empty() {
+            return native::connector(self).map(TlsConnector::NativeTls);
+        }
+        #[cfg(feature = "__rustls")]
+        {
+            rustls_tls::config(self).map(TlsConnector::Rustls)
+        }
+        #[cfg(not(feature = "__rustls"))]
+        {
+            Err(TlsError {
+                kind: if self.pinned_public_keys.is_empty() {
+                    TlsErrorKind::Unsupported
+                } else {
+                    TlsErrorKind::PinningUnsupported
+                },
+                source: None,
+            })
+        }
+    }
+}
+
+/// The serde representations of the fields of [`TlsOptions`]
+mod serde_tls {
+    use serde::{de::Error as _, Deserializer, Serializer};
+
+    use super::*;
+
+    fn to_pem<E: serde::ser::Error>(pem: &[u8]) -> Result<&str, E> {
+        std::str::from_utf8(pem).map_err(|_| E::custom("PEM data is not valid UTF-8"))
+    }
+
+    pub mod pem_list {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            pems: &[Vec<u8>],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(
+                pems.iter()
+                    .map(|pem| to_pem(pem))
+                    .collect::<Result<Vec<_>, S::Error>>()?,
+            )
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<Vec<u8>>, D::Error> {
+            Ok(Vec::<String>::deserialize(deserializer)?
+                .into_iter()
+                .map(String::into_bytes)
+                .collect())
+        }
+    }
+
+    /// A PEM encoded certificate chain and its private key
+    type PemIdentity = (Vec<u8>, Vec<u8>);
+
+    #[derive(Serialize, Deserialize)]
+    struct Identity<T> {
+        certificate: T,
+        key: T,
+    }
+
+    pub mod identity {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            identity: &Option<PemIdentity>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            identity
+                .as_ref()
+                .map(|(cert, key)| -> Result<_, S::Error> {
+                    Ok(Identity {
+                        certificate: to_pem(cert)?,
+                        key: to_pem(key)?,
+                    })
+                })
+                .transpose()?
+                .serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<PemIdentity>, D::Error> {
+            Ok(Option::<Identity<String>>::deserialize(deserializer)?
+                .map(|identity| (identity.certificate.into_bytes(), identity.key.into_bytes())))
+        }
+    }
+
+    /// Pins are only supported with rustls, so they fail to serialize and deserialize otherwise
+    pub mod pins {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            pins: &[[u8; 32]],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            #[cfg(feature = "__rustls")]
+            {
+                use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+                serializer.collect_seq(
+                    pins.iter()
+                        .map(|pin| format!("sha256//{}", BASE64.encode(pin))),
+                )
+            }
+            #[cfg(not(feature = "__rustls"))]
+            {
+                let _ = (pins, serializer);
+                Err(<S::Error as serde::ser::Error>::custom(TlsError {
+                    kind: TlsErrorKind::PinningUnsupported,
+                    source: None,
+                }))
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<[u8; 32]>, D::Error> {
+            Vec::<String>::deserialize(deserializer)?
+                .iter()
+                .map(|pin| parse_pinned_public_key(pin).map_err(D::Error::custom))
+                .collect()
+        }
+    }
+}
+
+/// Parse a pinned public key of the form `sha256//BASE64`, like the `--pinnedpubkey` option of curl,
+/// into the SHA-256 hash of the DER encoded SubjectPublicKeyInfo.
+///
+/// Pinning requires one of the `rustls-tls-*` features, without them this always fails.
+pub fn parse_pinned_public_key(pin: &str) -> Result<[u8; 32], TlsError> {
+    #[cfg(feature = "__rustls")]
+    {
+        use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+        let invalid = |reason: String| TlsError {
+            kind: TlsErrorKind::InvalidPinnedPublicKey(reason),
+            source: None,
+        };
+        let hash = pin
+            .strip_prefix("sha256//")
+            .ok_or_else(|| invalid(format!("{pin} does not start with sha256//")))?;
+        BASE64
+            .decode(hash)
+            .map_err(|e| invalid(format!("{hash} is not valid base64: {e}")))?
+            .try_into()
+            .map_err(|_| invalid(format!("{hash} is not a SHA-256 hash")))
+    }
+    #[cfg(not(feature = "__rustls"))]
+    {
+        let _ = pin;
+        Err(TlsError {
+            kind: TlsErrorKind::PinningUnsupported,
+            source: None,
+        })
+    }
+}
+
+/// Builder for [`TlsOptions`]
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptionsBuilder {
+    options: TlsOptions,
+}
+
+impl TlsOptionsBuilder {
+    /// Create a builder without additional root certificates, client certificate or pinned keys
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust the PEM encoded root certificates in `pem` in addition to the default roots
+    pub fn root_certificates(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.options.root_certificates.push(pem.into());
+        self
+    }
+
+    /// Present the PEM encoded certificate chain `cert` with its PKCS#8 private key `key` to the service
+    pub fn client_identity(mut self, cert: impl Into<Vec<u8>>, key: impl Into<Vec<u8>>) -> Self {
+        self.options.client_identity = Some((cert.into(), key.into()));
+        self
+    }
+
+    /// Present the PEM encoded certificate chain with its PKCS#8 private key to the service
+    pub fn optional_client_identity(
+        mut self,
+        identity: Option<(impl Into<Vec<u8>>, impl Into<Vec<u8>>)>,
+    ) -> Self {
+        self.options.client_identity = identity.map(|(cert, key)| (cert.into(), key.into()));
+        self
+    }
+
+    /// Pin the public key with the SHA-256 hash `sha256` of its DER encoded SubjectPublicKeyInfo
+    pub fn pinned_public_key(mut self, sha256: [u8; 32]) -> Self {
+        self.options.pinned_public_keys.push(sha256);
+        self
+    }
+
+    /// Build [`TlsOptions`]
+    pub fn build(self) -> TlsOptions {
+        self.options
+    }
+}
+
+/// A TLS connector built from [`TlsOptions`]
+pub(crate) enum TlsConnector {
+    #[cfg(feature = "native-tls")]
+    NativeTls(native_tls::TlsConnector),
+    #[cfg(feature = "__rustls")]
+    Rustls(rustls::ClientConfig),
+}
+
+impl TlsConnector {
+    #[cfg(all(
+        feature = "websocket-synthesizer",
+        any(feature = "native-tls", feature = "__rustls")
+    ))]
+    pub(crate) fn into_tungstenite(self) -> tokio_tungstenite::Connector {
+        match self {
+            #[cfg(feature = "native-tls")]
+            TlsConnector::NativeTls(connector) => {
+                tokio_tungstenite::Connector::NativeTls(connector)
+            }
+            #[cfg(feature = "__rustls")]
+            TlsConnector::Rustls(config) => {
+                tokio_tungstenite::Connector::Rustls(std::sync::Arc::new(config))
+            }
+        }
+    }
+}
+
+/// Split a PEM file into its blocks with the given `label`, e.g. `CERTIFICATE`
+#[cfg(feature = "native-tls")]
+fn pem_blocks<'a>(pem: &'a [u8], label: &str) -> Vec<&'a [u8]> {
+    let begin = format!("-----BEGIN {label}-----");
+    let end = format!("-----END {label}-----");
+    let mut blocks = Vec::new();
+    let mut rest = pem;
+    while let Some(start) = find(rest, begin.as_bytes()) {
+        let Some(len) = find(&rest[start..], end.as_bytes()) else {
+            break;
+        };
+        let stop = start + len + end.len();
+        blocks.push(&rest[start..stop]);
+        rest = &rest[stop..];
+    }
+    blocks
+}
+
+#[cfg(feature = "native-tls")]
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(feature = "native-tls")]
+mod native {
+    use super::{pem_blocks, TlsError, TlsErrorKind, TlsOptions};
+
+    pub(super) fn connector(options: &TlsOptions) -> Result<native_tls::TlsConnector, TlsError> {
+        let mut builder = native_tls::TlsConnector::builder();
+        for pem in options.root_certificates.iter() {
+            let certs = pem_blocks(pem, "CERTIFICATE");
+            if certs.is_empty() {
+                return Err(TlsError::no_pem_block(
+                    TlsErrorKind::InvalidCertificate,
+                    "CERTIFICATE",
+                ));
+            }
+            for cert in certs {
+                builder.add_root_certificate(native_tls::Certificate::from_pem(cert).map_err(
+                    |e| TlsError {
+                        kind: TlsErrorKind::InvalidCertificate,
+                        source: Some(e.into()),
+                    },
+                )?);
+            }
+        }
+        if let Some((cert, key)) = options.client_identity.as_ref() {
+            builder.identity(native_tls::Identity::from_pkcs8(cert, key).map_err(|e| {
+                TlsError {
+                    kind: TlsErrorKind::InvalidIdentity,
+                    source: Some(e.into()),
+                }
+            })?);
+        }
+        builder.build().map_err(|e| TlsError {
+            kind: TlsErrorKind::Backend,
+            source: Some(e.into()),
+        })
+    }
+}
+
+#[cfg(feature = "__rustls")]
+mod rustls_tls {
+    use std::{sync::Arc, time::SystemTime};
+
+    use rustls::{
+        client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+        Certificate, ClientConfig, PrivateKey, RootCertStore, ServerName,
+    };
+    use rustls_pemfile::Item;
+    use sha2::{Digest, Sha256};
+
+    use super::{TlsError, TlsErrorKind, TlsOptions};
+
+    /// Verifies the certificate with the root certificates, then checks its public key against the pinned keys
+    struct PinningVerifier {
+        inner: WebPkiVerifier,
+        pinned_public_keys: Vec<[u8; 32]>,
+    }
+
+    impl ServerCertVerifier for PinningVerifier {
+        fn verify_server_cert(
+            &self,
+            end_entity: &Certificate,
+            intermediates: &[Certificate],
+            server_name: &ServerName,
+            scts: &mut dyn Iterator<Item = &[u8]>,
+            ocsp_response: &[u8],
+            now: SystemTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            let verified = self.inner.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                scts,
+                ocsp_response,
+                now,
+            )?;
+            let spki = subject_public_key_info(&end_entity.0).ok_or(
+                rustls::Error::InvalidCertificate(rustls::CertificateError::BadEncoding),
+            )?;
+            let hash: [u8; 32] = Sha256::digest(spki).into();
+            if self.pinned_public_keys.contains(&hash) {
+                Ok(verified)
+            } else {
+                Err(rustls::Error::General(
+                    "the public key of the server is not pinned".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Read a DER header at the start of `der`, returning the tag, the contents and the rest
+    fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+        let (&tag, rest) = der.split_first()?;
+        let (&first, rest) = rest.split_first()?;
+        let (len, rest) = if first < 0x80 {
+            (first as usize, rest)
+        } else {
+            let n = (first & 0x7f) as usize;
+            if n == 0 || n > 4 || rest.len() < n {
+                return None;
+            }
+            let len = rest[..n]
+                .iter()
+                .fold(0usize, |acc, &b| (acc << 8) | b as usize);
+            (len, &rest[n..])
+        };
+        (rest.len() >= len).then(|| (tag, &rest[..len], &rest[len..]))
+    }
+
+    /// The DER encoded SubjectPublicKeyInfo of an X.509 certificate
+    fn subject_public_key_info(cert: &[u8]) -> Option<&[u8]> {
+        const SEQUENCE: u8 = 0x30;
+        const VERSION: u8 = 0xa0;
+        let (SEQUENCE, cert, _) = der_element(cert)? else {
+            return None;
+        };
+        let (SEQUENCE, mut tbs, _) = der_element(cert)? else {
+            return None;
+        };
+        if tbs.first() == Some(&VERSION) {
+            tbs = der_element(tbs)?.2;
+        }
+        // serialNumber, signature, issuer, validity and subject precede subjectPublicKeyInfo
+        for _ in 0..5 {
+            tbs = der_element(tbs)?.2;
+        }
+        let (SEQUENCE, contents, rest) = der_element(tbs)? else {
+            return None;
+        };
+        let header_len = tbs.len() - rest.len() - contents.len();
+        Some(&tbs[..header_len + contents.len()])
+    }
+
+    fn root_store(options: &TlsOptions) -> Result<RootCertStore, TlsError> {
+        #[allow(unused_mut)]
+        let mut root_store = RootCertStore::empty();
+        #[cfg(feature = "rustls-tls-native-roots")]
+        {
+            let native_certs = rustls_native_certs::load_native_certs().map_err(|e| TlsError {
+                kind: TlsErrorKind::Backend,
+                source: Some(e.into()),
+            })?;
+            let der_certs: Vec<Vec<u8>> = native_certs.into_iter().map(|cert| cert.0).collect();
+            root_store.add_parsable_certificates(&der_certs);
+        }
+        #[cfg(feature = "rustls-tls-webpki-roots")]
+        root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+            rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+        for pem in options.root_certificates.iter() {
+            let certs = certificates(pem)?;
+            for cert in certs {
+                root_store.add(&cert).map_err(|e| TlsError {
+                    kind: TlsErrorKind::InvalidCertificate,
+                    source: Some(e.into()),
+                })?;
+            }
+        }
+        Ok(root_store)
+    }
+
+    fn certificates(pem: &[u8]) -> Result<Vec<Certificate>, TlsError> {
+        let certs = rustls_pemfile::certs(&mut &*pem).map_err(|e| TlsError {
+            kind: TlsErrorKind::InvalidCertificate,
+            source: Some(e.into()),
+        })?;
+        if certs.is_empty() {
+            return Err(TlsError::no_pem_block(
+                TlsErrorKind::InvalidCertificate,
+                "CERTIFICATE",
+            ));
+        }
+        Ok(certs.into_iter().map(Certificate).collect())
+    }
+
+    fn private_key(pem: &[u8]) -> Result<PrivateKey, TlsError> {
+        let items = rustls_pemfile::read_all(&mut &*pem).map_err(|e| TlsError {
+            kind: TlsErrorKind::InvalidIdentity,
+            source: Some(e.into()),
+        })?;
+        items
+            .into_iter()
+            .find_map(|item| match item {
+                Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+                _ => None,
+            })
+            .ok_or_else(|| TlsError::no_pem_block(TlsErrorKind::InvalidIdentity, "PRIVATE KEY"))
+    }
+
+    pub(super) fn config(options: &TlsOptions) -> Result<ClientConfig, TlsError> {
+        let root_store = root_store(options)?;
+        let builder = ClientConfig::builder().with_safe_defaults();
+        let builder = if options.pinned_public_keys.is_empty() {
+            builder
+                .with_custom_certificate_verifier(Arc::new(WebPkiVerifier::new(root_store, None)))
+        } else {
+            builder.with_custom_certificate_verifier(Arc::new(PinningVerifier {
+                inner: WebPkiVerifier::new(root_store, None),
+                pinned_public_keys: options.pinned_public_keys.clone(),
+            }))
+        };
+        match options.client_identity.as_ref() {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(certificates(cert)?, private_key(key)?)
+                .map_err(|e| TlsError {
+                    kind: TlsErrorKind::InvalidIdentity,
+                    source: Some(e.into()),
+                }),
+            None => Ok(builder.with_no_client_auth()),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        use super::*;
+
+        /// A self-signed P-256 CA, valid until 2126
+        const CA: &str = "-----BEGIN CERTIFICATE-----
+MIIBmjCCAT+gAwIBAgIUQNJgk7N1F2wI/VgOgBbZud0L6CUwCgYIKoZIzj0EAwIw
+GTEXMBUGA1UEAwwOYXNwZWFrIHRlc3QgQ0EwIBcNMjYxMDE5MDI0MzIzWhgPMjEy
+NjA5MjUwMjQzMjNaMBkxFzAVBgNVBAMMDmFzcGVhayB0ZXN0IENBMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAEnfIfF0INfSkykCagYh1yEZA28GGH141dCVfrs24D
+hClklrFa3paEss6zyhZ8tywq8dIGMPwTissWqRGqtDK3b6NjMGEwHQYDVR0OBBYE
+FJrA92umf3jM+Gd6JXJa6rm+irhDMB8GA1UdIwQYMBaAFJrA92umf3jM+Gd6JXJa
+6rm+irhDMA8GA1UdEwEB/wQFMAMBAf8wDgYDVR0PAQH/BAQDAgIEMAoGCCqGSM49
+BAMCA0kAMEYCIQDUQzIX7ph3SlsQ81MPcNgQGcmbgNWjiHXxGRwqLCVP2QIhAPL2
+YlEAndg523rFszZeU0sR7/iu+2k49y9wqLMwFiEh
+-----END CERTIFICATE-----
+";
+
+        /// A P-256 certificate for `localhost` issued by [`CA`], valid until 2126
+        const LEAF: &str = "-----BEGIN CERTIFICATE-----
+MIIBqjCCAU+gAwIBAgIURTE2xXgcoP+/Oddy21DUrwRAvvwwCgYIKoZIzj0EAwIw
+GTEXMBUGA1UEAwwOYXNwZWFrIHRlc3QgQ0EwIBcNMjYxMDE5MDI0MzIzWhgPMjEy
+NjA5MjUwMjQzMjNaMBQxEjAQBgNVBAMMCWxvY2FsaG9zdDBZMBMGByqGSM49AgEG
+CCqGSM49AwEHA0IABHhot5JZsQAMttPus0r3+fh9VGQUp8btyXLge4qe/QcP27NK
+4I2+JhOPVVXBelFF0zEtVnuY2n/I8BkipL3h9LmjeDB2MBQGA1UdEQQNMAuCCWxv
+Y2FsaG9zdDAJBgNVHRMEAjAAMBMGA1UdJQQMMAoGCCsGAQUFBwMBMB0GA1UdDgQW
+BBTDXVThaIQB264oZ6pP8QXWnjRu9TAfBgNVHSMEGDAWgBSawPdrpn94zPhneiVy
+Wuq5voq4QzAKBggqhkjOPQQDAgNJADBGAiEAgMER7fxFKvhcWHq/ztosS28V+PR0
+ZRGyNMf5Ic0J8O0CIQDMXX2zUME6s2LdfnsedGrSO+kZVI1YAoudu02CHCTj/w==
+-----END CERTIFICATE-----
+";
+
+        /// `sha256//yhWirapqYrQk5zIhNogKg4qo+c/TMxiSS/Dc9JeD/Qk=`, the pin of [`LEAF`]
+        const LEAF_PIN: [u8; 32] = [
+            0xca, 0x15, 0xa2, 0xad, 0xaa, 0x6a, 0x62, 0xb4, 0x24, 0xe7, 0x32, 0x21, 0x36, 0x88,
+            0x0a, 0x83, 0x8a, 0xa8, 0xf9, 0xcf, 0xd3, 0x33, 0x18, 0x92, 0x4b, 0xf0, 0xdc, 0xf4,
+            0x97, 0x83, 0xfd, 0x09,
+        ];
+
+        fn leaf() -> Certificate {
+            certificates(LEAF.as_bytes()).unwrap().remove(0)
+        }
+
+        fn verifier(pinned_public_keys: Vec<[u8; 32]>) -> PinningVerifier {
+            let options = TlsOptions::builder().root_certificates(CA).build();
+            PinningVerifier {
+                inner: WebPkiVerifier::new(root_store(&options).unwrap(), None),
+                pinned_public_keys,
+            }
+        }
+
+        fn verify(
+            verifier: &PinningVerifier,
+            server_name: &str,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            verifier.verify_server_cert(
+                &leaf(),
+                &[],
+                &ServerName::try_from(server_name).unwrap(),
+                &mut std::iter::empty(),
+                &[],
+                UNIX_EPOCH + Duration::from_secs(1_900_000_000),
+            )
+        }
+
+        #[test]
+        fn der_element_short_form() {
+            assert_eq!(
+                der_element(&[0x04, 0x02, 1, 2, 3]),
+                Some((0x04, &[1u8, 2][..], &[3u8][..]))
+            );
+            assert_eq!(der_element(&[0x05, 0x00]), Some((0x05, &[][..], &[][..])));
+        }
+
+        #[test]
+        fn der_element_long_form() {
+            let mut der = vec![0x04, 0x82, 0x01, 0x00];
+            der.extend((0..=255).chain([7]));
+            let (tag, contents, rest) = der_element(&der).unwrap();
+            assert_eq!(tag, 0x04);
+            assert_eq!(contents.len(), 256);
+            assert_eq!(contents[255], 255);
+            assert_eq!(rest, [7]);
+            // The long form may also encode short lengths
+            assert_eq!(
+                der_element(&[0x04, 0x81, 0x01, 9]),
+                Some((0x04, &[9u8][..], &[][..]))
+            );
+        }
+
+        #[test]
+        fn der_element_rejects_malformed_headers() {
+            assert_eq!(der_element(&[]), None);
+            assert_eq!(der_element(&[0x30]), None);
+            // Contents shorter than the length
+            assert_eq!(der_element(&[0x04, 0x03, 1, 2]), None);
+            assert_eq!(der_element(&[0x04, 0x82, 0x01, 0x00, 1]), None);
+            // Missing length bytes
+            assert_eq!(der_element(&[0x04, 0x82, 0x01]), None);
+            // Indefinite length and lengths of more than 4 bytes
+            assert_eq!(der_element(&[0x04, 0x80, 0x00, 0x00]), None);
+            assert_eq!(der_element(&[0x04, 0x85, 0, 0, 0, 0, 1, 0]), None);
+        }
+
+        #[test]
+        fn spki_of_certificate() {
+            let leaf = leaf();
+            // The outer SEQUENCE of the certificate has a long form length
+            assert_eq!(leaf.0[1], 0x82);
+            let spki = subject_public_key_info(&leaf.0).unwrap();
+            // SEQUENCE { AlgorithmIdentifier, BIT STRING } of a P-256 key
+            assert_eq!(spki.len(), 91);
+            assert_eq!(spki[..2], [0x30, 0x59]);
+            assert_eq!(<[u8; 32]>::from(Sha256::digest(spki)), LEAF_PIN);
+        }
+
+        #[test]
+        fn spki_of_truncated_certificate() {
+            let leaf = leaf();
+            for len in 0..leaf.0.len() {
+                assert_eq!(subject_public_key_info(&leaf.0[..len]), None, "{len}");
+            }
+            assert_eq!(subject_public_key_info(&[0x04, 0x00]), None);
+        }
+
+        #[test]
+        fn pinned_public_key_is_accepted() {
+            assert!(verify(&verifier(vec![[0; 32], LEAF_PIN]), "localhost").is_ok());
+        }
+
+        #[test]
+        fn other_public_key_is_rejected() {
+            assert!(matches!(
+                verify(&verifier(vec![[0; 32]]), "localhost"),
+                Err(rustls::Error::General(_))
+            ));
+        }
+
+        #[test]
+        fn pinning_does_not_skip_verification() {
+            assert!(matches!(
+                verify(&verifier(vec![LEAF_PIN]), "example.com"),
+                Err(rustls::Error::InvalidCertificate(_))
+            ));
+        }
+    }
+}
+
+/// Errors that can occur when building a TLS connector from [`TlsOptions`]
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct TlsError {
+    pub kind: TlsErrorKind,
+    pub(crate) source: Option<anyhow::Error>,
+}
+
+impl TlsError {
+    #[cfg(any(feature = "native-tls", feature = "__rustls"))]
+    fn no_pem_block(kind: TlsErrorKind, label: &str) -> Self {
+        Self {
+            kind,
+            source: Some(anyhow::anyhow!("no {label} found in the PEM file")),
+        }
+    }
+}
+
+impl Display for TlsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "tls error: ")?;
+        match &self.kind {
+            TlsErrorKind::InvalidCertificate => write!(f, "invalid root certificate"),
+            TlsErrorKind::InvalidIdentity => write!(f, "invalid client certificate or key"),
+            TlsErrorKind::InvalidPinnedPublicKey(reason) => {
+                write!(f, "invalid pinned public key: {reason}")
+            }
+            TlsErrorKind::PinningUnsupported => write!(
+                f,
+                "public key pinning requires aspeak to be built with a rustls-tls-* feature"
+            ),
+            TlsErrorKind::Unsupported => write!(f, "aspeak is built without TLS support"),
+            TlsErrorKind::Backend => write!(f, "failed to build the TLS connector"),
+        }
+    }
+}
+
+impl Error for TlsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|e| e.as_ref() as _)
+    }
+}
+
+#[cfg(feature = "python")]
+impl From<TlsError> for pyo3::PyErr {
+    fn from(value: TlsError) -> Self {
+        pyo3::exceptions::PyOSError::new_err(format!("{:?}", color_eyre::Report::from(value)))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, AsRefStr)]
+#[non_exhaustive]
+#[strum(serialize_all = "title_case")]
+pub enum TlsErrorKind {
+    /// A root certificate could not be parsed
+    InvalidCertificate,
+    /// The client certificate or its private key could not be parsed
+    InvalidIdentity,
+    /// A pinned public key is not of the form `sha256//BASE64`
+    InvalidPinnedPublicKey(String),
+    /// Public keys are pinned, but no rustls TLS implementation is enabled
+    PinningUnsupported,
+    /// No TLS implementation is enabled
+    Unsupported,
+    /// The TLS implementation failed to build a connector
+    Backend,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "__rustls")]
+    fn pinned_public_keys() {
+        let pin = "sha256//yhWirapqYrQk5zIhNogKg4qo+c/TMxiSS/Dc9JeD/Qk=";
+        let hash = parse_pinned_public_key(pin).unwrap();
+        assert_eq!(hash[..4], [0xca, 0x15, 0xa2, 0xad]);
+        let options = TlsOptions::builder().pinned_public_key(hash).build();
+        let json = serde_json::to_string(&options).unwrap();
+        assert_eq!(json, format!(r#"{{"pinned_public_keys":["{pin}"]}}"#));
+        assert_eq!(serde_json::from_str::<TlsOptions>(&json).unwrap(), options);
+    }
+
+    #[test]
+    #[cfg(feature = "__rustls")]
+    fn invalid_pinned_public_keys() {
+        for pin in [
+            "yhWirapqYrQk5zIhNogKg4qo+c/TMxiSS/Dc9JeD/Qk=",
+            "sha256//not base64",
+            // The hash of SHA-1 is too short
+            "sha256//2jmj7l5rSw0yVb/vlWAYkK/YBwk=",
+        ] {
+            assert!(matches!(
+                parse_pinned_public_key(pin),
+                Err(TlsError {
+                    kind: TlsErrorKind::InvalidPinnedPublicKey(_),
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "__rustls"))]
+    fn pinning_unsupported() {
+        let pin = "sha256//yhWirapqYrQk5zIhNogKg4qo+c/TMxiSS/Dc9JeD/Qk=";
+        assert!(matches!(
+            parse_pinned_public_key(pin),
+            Err(TlsError {
+                kind: TlsErrorKind::PinningUnsupported,
+                ..
+            })
+        ));
+        let json = format!(r#"{{"pinned_public_keys":["{pin}"]}}"#);
+        assert!(serde_json::from_str::<TlsOptions>(&json).is_err());
+        let options = TlsOptions::builder().pinned_public_key([0; 32]).build();
+        assert!(serde_json::to_string(&options).is_err());
+        assert_eq!(serde_json::to_string(&TlsOptions::default()).unwrap(), "{}");
+    }
+}
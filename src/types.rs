@@ -1,6 +1,6 @@
 use std::borrow::Cow;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use strum::IntoStaticStr;
 
 use crate::get_default_voice_by_locale;
@@ -13,7 +13,7 @@ use crate::get_default_voice_by_locale;
     derive(clap::ValueEnum),
     clap(rename_all = "verbatim")
 )]
-#[derive(Debug, Clone, Copy, IntoStaticStr, Deserialize)]
+#[derive(Debug, Clone, Copy, IntoStaticStr, Serialize, Deserialize)]
 pub enum Role {
     Girl,
     Boy,
@@ -26,17 +26,29 @@ pub enum Role {
 }
 
 /// Options that are only available if rich ssml is enabled
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RichSsmlOptions<'a> {
     /// Speech style
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) style: Option<Cow<'a, str>>,
     /// Speech role
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) role: Option<Role>,
     /// Speech style degree, which should be in range [0.01, 2]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) style_degree: Option<f32>,
 }
 
 impl<'a> RichSsmlOptions<'a> {
+    /// Convert into [`RichSsmlOptions`] that own their data
+    pub fn into_owned(self) -> RichSsmlOptions<'static> {
+        RichSsmlOptions {
+            style: self.style.map(|style| Cow::Owned(style.into_owned())),
+            role: self.role,
+            style_degree: self.style_degree,
+        }
+    }
     /// Speech style
     pub fn style(&self) -> Option<&str> {
         self.style.as_deref()
@@ -128,15 +140,21 @@ impl<'a> RichSsmlOptionsBuilder<'a> {
 }
 
 /// Options for text-to-speech
-#[derive(Debug, Clone)]
+///
+/// With serde, a missing voice is the default voice of `en-US`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct TextOptions<'a> {
     /// Voice identifier. It should be in the format of `locale-voice_name` like `en-US-JennyNeural`.
     pub(crate) voice: Cow<'a, str>,
     /// Pitch string that will be inserted directly into SSML
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) pitch: Option<Cow<'a, str>>,
     /// Rate string that will be inserted directly into SSML
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) rate: Option<Cow<'a, str>>,
     /// Rich SSML options
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) rich_ssml_options: Option<RichSsmlOptions<'a>>,
 }
 
@@ -152,6 +170,16 @@ impl Default for TextOptions<'_> {
 }
 
 impl<'a> TextOptions<'a> {
+    /// Convert into [`TextOptions`] that own their data, e.g. to store them in long-lived structs
+    pub fn into_owned(self) -> TextOptions<'static> {
+        TextOptions {
+            voice: Cow::Owned(self.voice.into_owned()),
+            pitch: self.pitch.map(|pitch| Cow::Owned(pitch.into_owned())),
+            rate: self.rate.map(|rate| Cow::Owned(rate.into_owned())),
+            rich_ssml_options: self.rich_ssml_options.map(RichSsmlOptions::into_owned),
+        }
+    }
+
     /// Voice identifier. It should be in the format of `locale-voice_name` like `en-US-JennyNeural`.
     pub fn voice(&self) -> &str {
         &self.voice
@@ -183,7 +211,7 @@ impl<'a> TextOptions<'a> {
     }
 
     /// Rich SSML options
-    pub fn rich_ssml_options(&self) -> &Option<RichSsmlOptions> {
+    pub fn rich_ssml_options(&self) -> &Option<RichSsmlOptions<'_>> {
         &self.rich_ssml_options
     }
 
@@ -1,15 +1,50 @@
 use reqwest::{ClientBuilder, Proxy};
 
-pub(crate) trait ClientBuilderExt {
-    fn optional_proxy(self, proxy: Option<Proxy>) -> Self;
+#[cfg(any(feature = "native-tls", feature = "__rustls"))]
+use crate::tls::TlsConnector;
+use crate::{ProxyResolver, Timeouts, TlsError, TlsOptions};
+
+pub(crate) trait ClientBuilderExt: Sized {
+    fn proxy_resolver(self, resolver: ProxyResolver) -> Self;
+    fn connect_timeouts(self, timeouts: &Timeouts) -> Self;
+    fn tls_options(self, tls: &TlsOptions) -> Result<Self, TlsError>;
 }
 
 impl ClientBuilderExt for ClientBuilder {
-    fn optional_proxy(self, proxy: Option<Proxy>) -> Self {
-        if let Some(proxy) = proxy {
-            self.proxy(proxy)
-        } else {
-            self
+    /// Use `resolver` instead of the system proxy detection of reqwest
+    fn proxy_resolver(self, resolver: ProxyResolver) -> Self {
+        self.no_proxy()
+            .proxy(Proxy::custom(move |url| resolver.resolve(url).cloned()))
+    }
+
+    /// reqwest applies its connect timeout to both connecting and the TLS handshake,
+    /// so the TLS handshake timeout is only added to it if the connect timeout is set.
+    fn connect_timeouts(self, timeouts: &Timeouts) -> Self {
+        match (timeouts.connect, timeouts.tls_handshake) {
+            (Some(connect), Some(tls_handshake)) => self.connect_timeout(connect + tls_handshake),
+            (Some(connect), None) => self.connect_timeout(connect),
+            (None, _) => self,
+        }
+    }
+
+    /// Use a TLS connector built from `tls` unless the defaults of reqwest are used
+    fn tls_options(self, tls: &TlsOptions) -> Result<Self, TlsError> {
+        if tls.is_empty() {
+            return Ok(self);
+        }
+        #[cfg(any(feature = "native-tls", feature = "__rustls"))]
+        {
+            Ok(match tls.connector()? {
+                #[cfg(feature = "native-tls")]
+                TlsConnector::NativeTls(connector) => self.use_preconfigured_tls(connector),
+                #[cfg(feature = "__rustls")]
+                TlsConnector::Rustls(config) => self.use_preconfigured_tls(config),
+            })
+        }
+        // Without a TLS implementation, there is no connector and building it always fails
+        #[cfg(not(any(feature = "native-tls", feature = "__rustls")))]
+        {
+            tls.connector().map(|connector| match connector {})
         }
     }
 }
@@ -6,7 +6,9 @@ use std::{
 
 use crate::{
     constants::{ORIGIN, TRIAL_VOICE_LIST_URL},
-    AudioFormat,
+    timeout::TimeoutPhase,
+    utils::ClientBuilderExt,
+    AudioFormat, ProxyResolver, Timeouts, TlsOptions,
 };
 use colored::Colorize;
 use hyper::{header::InvalidHeaderValue, http::HeaderValue};
@@ -76,19 +78,49 @@ impl Voice {
         auth: Option<VoiceListAPIAuth<'_>>,
         proxy: Option<&str>,
         additional_headers: Option<reqwest::header::HeaderMap>,
+    ) -> Result<Vec<Self>, VoiceListAPIError> {
+        Self::request_available_voices_with_options(
+            endpoint,
+            auth,
+            proxy,
+            additional_headers,
+            &Timeouts::default(),
+            &TlsOptions::default(),
+        )
+        .await
+    }
+
+    /// Query available voices from the voice list API with the given [`Timeouts`] and [`TlsOptions`].
+    ///
+    /// Like the REST synthesizer, the first byte timeout also includes the time for connecting.
+    /// The voice list is received as a whole, so the idle timeout applies to receiving the whole list.
+    /// Without the `rest-synthesizer` or `websocket-synthesizer` feature, only the connect timeouts apply.
+    pub async fn request_available_voices_with_options(
+        endpoint: VoiceListAPIEndpoint<'_>,
+        auth: Option<VoiceListAPIAuth<'_>>,
+        proxy: Option<&str>,
+        additional_headers: Option<reqwest::header::HeaderMap>,
+        timeouts: &Timeouts,
+        tls: &TlsOptions,
     ) -> Result<Vec<Self>, VoiceListAPIError> {
         let url = endpoint.get_endpoint_url();
-        let mut client = reqwest::ClientBuilder::new().no_proxy(); // Disable default system proxy detection.
-        if let Some(proxy) = proxy {
-            client = client.proxy(reqwest::Proxy::all(proxy).map_err(|e| VoiceListAPIError {
-                kind: VoiceListAPIErrorKind::Proxy,
-                source: Some(e.into()),
-            })?);
-        }
-        let client = client.build().map_err(|e| VoiceListAPIError {
-            kind: VoiceListAPIErrorKind::Request,
+        let resolver = ProxyResolver::new(proxy).map_err(|e| VoiceListAPIError {
+            kind: VoiceListAPIErrorKind::Proxy,
             source: Some(e.into()),
         })?;
+        let client = reqwest::ClientBuilder::new()
+            .proxy_resolver(resolver)
+            .connect_timeouts(timeouts)
+            .tls_options(tls)
+            .map_err(|e| VoiceListAPIError {
+                kind: VoiceListAPIErrorKind::Request,
+                source: Some(e.into()),
+            })?
+            .build()
+            .map_err(|e| VoiceListAPIError {
+                kind: VoiceListAPIErrorKind::Request,
+                source: Some(e.into()),
+            })?;
         let mut request = client.get(&*url);
         let request_error = |e: InvalidHeaderValue| VoiceListAPIError {
             kind: VoiceListAPIErrorKind::Request,
@@ -109,18 +141,32 @@ impl Voice {
             }
             None => {}
         }
-        if additional_headers.is_some() {
-            request = request.headers(additional_headers.unwrap());
+        if let Some(additional_headers) = additional_headers {
+            request = request.headers(additional_headers);
         } else if Some(url.as_ref()) == TRIAL_VOICE_LIST_URL {
             // Trial endpoint
             request = request.header("Origin", HeaderValue::from_str(ORIGIN).unwrap());
         }
         let request_error = |e: reqwest::Error| VoiceListAPIError {
-            kind: VoiceListAPIErrorKind::Request,
+            kind: if e.is_timeout() {
+                VoiceListAPIErrorKind::Timeout
+            } else {
+                VoiceListAPIErrorKind::Request
+            },
             source: Some(e.into()),
         };
         let request = request.build().map_err(request_error)?;
-        let response = client.execute(request).await.map_err(request_error)?;
+        let mut send_timeouts = *timeouts;
+        send_timeouts.first_byte = timeouts.first_byte.map(|d| {
+            d + timeouts.connect.unwrap_or_default() + timeouts.tls_handshake.unwrap_or_default()
+        });
+        let response = phase_timeout(
+            &send_timeouts,
+            TimeoutPhase::FirstByte,
+            client.execute(request),
+        )
+        .await?
+        .map_err(request_error)?;
         let response = response.error_for_status().map_err(|e| VoiceListAPIError {
             kind: VoiceListAPIErrorKind::Response,
             source: Some(
@@ -131,11 +177,14 @@ impl Voice {
                 .into(),
             ),
         })?;
-        response
-            .json::<Vec<Voice>>()
-            .await
+        phase_timeout(timeouts, TimeoutPhase::Idle, response.json::<Vec<Voice>>())
+            .await?
             .map_err(|e| VoiceListAPIError {
-                kind: VoiceListAPIErrorKind::Parse,
+                kind: if e.is_timeout() {
+                    VoiceListAPIErrorKind::Timeout
+                } else {
+                    VoiceListAPIErrorKind::Parse
+                },
                 source: Some(e.into()),
             })
     }
@@ -212,6 +261,28 @@ impl Voice {
     }
 }
 
+/// Await `future`, giving up after the timeout of `phase` in `timeouts` if tokio is available
+async fn phase_timeout<F: std::future::Future>(
+    timeouts: &Timeouts,
+    phase: TimeoutPhase,
+    future: F,
+) -> Result<F::Output, VoiceListAPIError> {
+    #[cfg(any(feature = "rest-synthesizer", feature = "websocket-synthesizer"))]
+    {
+        crate::timeout::timeout(timeouts, phase, future)
+            .await
+            .map_err(|e| VoiceListAPIError {
+                kind: VoiceListAPIErrorKind::Timeout,
+                source: Some(e.into()),
+            })
+    }
+    #[cfg(not(any(feature = "rest-synthesizer", feature = "websocket-synthesizer")))]
+    {
+        let _ = (timeouts, phase);
+        Ok(future.await)
+    }
+}
+
 impl Display for Voice {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "{}", self.name.bright_green())?;
@@ -311,4 +382,6 @@ pub enum VoiceListAPIErrorKind {
     Parse,
     /// A response was received from the voice list API, but it is not successful
     Response,
+    /// The voice list API did not respond in time
+    Timeout,
 }